@@ -1,13 +1,17 @@
 use crate::model::transfer_log::TransferLog;
 use crate::ui::main::run_ui;
 use crate::ui::ui_command::{UiCommand, UpdateStateDetails};
-use device_controller::controller::command_request::CommandRequest;
 use device_controller::controller::connection_handler::ConnectionHandler;
 use device_controller::controller::connection_mgr::ConnectionManager;
-use device_controller::dev_finder::DeviceFinder;
+use device_controller::controller::handle::{ControllerEvent, ControllerHandle};
+use device_controller::dev_finder::{DeviceFinder, FINDER_USAGE};
+use log::warn;
+use std::process;
 use std::sync::mpsc::{channel as std_channel, Sender};
 use tokio::select;
-use tokio::sync::mpsc::{channel as tokio_channel, Receiver};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::broadcast::error::RecvError;
 
 mod model;
 mod ui;
@@ -20,48 +24,75 @@ fn main() {
     }));
 
     let (ui_cmd_tx, ui_cmd_rx) = std_channel();
-    let (ui_request_tx, ui_request_rx) = tokio_channel(10);
+    let finder = finder_from_args();
+    let stepper = finder.backend.stepper().cloned();
+    let (manager, handle) = ConnectionManager::new(finder, ConnectionHandler::default());
+    let ui_request_tx = handle.commands().clone();
+    let shutdown_handle = handle.clone();
 
-    let _ = std::thread::spawn(move || tokio_thread(ui_cmd_tx, ui_request_rx));
+    let tokio_thread = std::thread::spawn(move || tokio_thread(ui_cmd_tx, manager, handle));
 
     // Run the UI in the main thread.
-    run_ui(ui_cmd_rx, ui_request_tx);
+    run_ui(ui_cmd_rx, ui_request_tx, stepper);
+
+    // Disconnect from the thermometer cleanly before exiting, so that it's free for something else to connect to.
+    shutdown_handle.shutdown();
+    let _ = tokio_thread.join();
+}
+
+/// Choose what to talk to from the command line. By default it's a thermometer over Bluetooth.
+fn finder_from_args() -> DeviceFinder {
+    match DeviceFinder::from_args(std::env::args().skip(1)) {
+        Ok((finder, others)) if others.is_empty() => finder,
+        Ok(_) => usage(),
+        Err(e) => {
+            eprintln!("{}", e);
+            usage()
+        }
+    }
 }
 
-fn tokio_thread(ui_cmd_tx: Sender<UiCommand>, ui_request_rx: Receiver<CommandRequest>) {
+fn usage() -> ! {
+    eprintln!("Usage: cursive-ui {}", FINDER_USAGE);
+    process::exit(1);
+}
+
+fn tokio_thread(
+    ui_cmd_tx: Sender<UiCommand>,
+    manager: ConnectionManager,
+    handle: ControllerHandle,
+) {
     tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .unwrap()
         .block_on(async {
-            tokio_main_loop(ui_cmd_tx, ui_request_rx).await;
+            tokio_main_loop(ui_cmd_tx, manager, handle).await;
         });
 }
 
-async fn tokio_main_loop(ui_cmd_tx: Sender<UiCommand>, ui_request_rx: Receiver<CommandRequest>) {
-    let (state_tx, mut state_rx) = tokio_channel(10);
-    let (transfer_tx, mut transfer_rx) = tokio_channel(10);
-    let finder = DeviceFinder {};
-
-    let task_a = ConnectionManager::run(
-        finder,
-        ConnectionHandler {},
-        state_tx,
-        transfer_tx,
-        ui_request_rx,
-    );
+async fn tokio_main_loop(
+    ui_cmd_tx: Sender<UiCommand>,
+    manager: ConnectionManager,
+    handle: ControllerHandle,
+) {
+    let mut state_rx = handle.subscribe_state();
+    let mut event_rx = handle.subscribe_events();
+
+    let mut task_a = tokio::spawn(manager.run());
 
     let ui_cmd_tx_2 = ui_cmd_tx.clone();
     let ui_cmd_tx_3 = ui_cmd_tx.clone();
 
     let task_b = tokio::spawn(async move {
         loop {
-            let Some(new_state) = state_rx.recv().await else {
+            if state_rx.changed().await.is_err() {
                 return;
-            };
+            }
+            let new_state = state_rx.borrow_and_update().clone();
             if ui_cmd_tx
                 .send(UiCommand::UpdateState(UpdateStateDetails {
-                    device_state: new_state,
+                    device_state: Box::new(new_state),
                 }))
                 .is_err()
             {
@@ -74,8 +105,14 @@ async fn tokio_main_loop(ui_cmd_tx: Sender<UiCommand>, ui_request_rx: Receiver<C
     let task_c = tokio::spawn(async move {
         let transfer_log = TransferLog::new();
         loop {
-            let Some(transfer) = transfer_rx.recv().await else {
-                return;
+            let transfer = match event_rx.recv().await {
+                Ok(ControllerEvent::Transfer(transfer)) => transfer,
+                Ok(ControllerEvent::Device(_) | ControllerEvent::HostAlarm(_)) => continue,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Transfer log missed {} transfers", n);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
             };
             transfer_log.push_transfer(transfer);
             if ui_cmd_tx_2
@@ -88,8 +125,34 @@ async fn tokio_main_loop(ui_cmd_tx: Sender<UiCommand>, ui_request_rx: Receiver<C
         }
     });
 
-    select!(_ = task_a => {}, _ = task_b => {}, _ = task_c => {});
+    // When the UI quits, it shuts the controller down, which ends `task_a`.
+    select!(
+        _ = &mut task_a => {},
+        _ = task_b => {},
+        _ = task_c => {},
+        _ = shutdown_signal() => {},
+    );
+
+    handle.shutdown();
+    if !task_a.is_finished() {
+        let _ = task_a.await;
+    }
 
     // No need to handle errors here, we're about to exit anyway.
     ui_cmd_tx_3.send(UiCommand::Quit).unwrap_or_default();
 }
+
+/// Wait for Ctrl-C, or SIGTERM where there is such a thing. (The UI normally sees Ctrl-C as a key press and quits, but
+/// this covers the times it doesn't.)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+        select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
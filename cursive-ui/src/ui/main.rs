@@ -8,14 +8,14 @@ use cursive::event::{Event, Key};
 use cursive::traits::*;
 use cursive::views::{Dialog, DummyView, LinearLayout, Panel};
 use cursive::Cursive;
-use device_controller::controller::command_request::CommandRequest;
+use device_controller::controller::command_request::CommandSender;
 use device_controller::model::device::TemperatureMode;
 use device_controller::model::probe::Probe;
+use device_controller::peripheral::replay::ReplayStepper;
 use log::LevelFilter::Warn;
 use log::{info, trace};
 use std::sync::Mutex;
 use std::thread;
-use tokio::sync::mpsc::Sender;
 
 fn probe(index: usize) -> Dialog {
     let p = Probe::default();
@@ -38,7 +38,13 @@ pub fn update_probe(c: &mut Cursive, index: usize, probe: &Probe, temp_mode: &Te
     });
 }
 
-pub fn run_ui(ui_command_receiver: CommandReceiver, request_tx: Sender<CommandRequest>) {
+/// Run the UI until it's quit. If a replay is being stepped through, `stepper` is used to take a step whenever 's' is
+/// pressed.
+pub fn run_ui(
+    ui_command_receiver: CommandReceiver,
+    request_tx: CommandSender,
+    stepper: Option<ReplayStepper>,
+) {
     // Without the following line, Cursive spams Debug level logs about its layout calculations,
     // which we don't need to see.
     cursive::logger::set_filter_levels_from_env();
@@ -79,6 +85,9 @@ pub fn run_ui(ui_command_receiver: CommandReceiver, request_tx: Sender<CommandRe
     siv.add_global_callback('q', |s| s.quit());
     siv.add_global_callback(Key::Esc, |s| s.select_menubar());
     siv.add_global_callback('~', Cursive::toggle_debug_console);
+    if let Some(stepper) = stepper {
+        siv.add_global_callback('s', move |_| stepper.step());
+    }
 
     // The cursive documentation isn't particularly clear on this, but `Event::WindowResize` has to be handled as a
     // "pre-event". I think this means it would be swallowed somewhere "in the view", whereas key events are not (and
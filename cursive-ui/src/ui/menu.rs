@@ -1,15 +1,15 @@
 use crate::ui::report_profile_dialog::report_profile_cb;
 use crate::ui::set_profile_dialog::set_profile_cb;
 use cursive::{menu, CursiveRunnable};
-use device_controller::controller::command_request::CommandRequest;
-use tokio::sync::mpsc::Sender;
+use device_controller::controller::command_request::{CommandRequest, CommandSender};
 
-pub fn install_menu(c: &mut CursiveRunnable, request_tx: Sender<CommandRequest>) {
+pub fn install_menu(c: &mut CursiveRunnable, request_tx: CommandSender) {
     let tx_a = request_tx.clone();
     let tx_b = request_tx.clone();
     let tx_c = request_tx.clone();
     let tx_d = request_tx.clone();
     let tx_e = request_tx.clone();
+    let tx_f = request_tx.clone();
 
     c.menubar()
         .add_subtree(
@@ -27,6 +27,10 @@ pub fn install_menu(c: &mut CursiveRunnable, request_tx: Sender<CommandRequest>)
                 .leaf("Set profile", move |c| set_profile_cb(c, &tx_d))
                 .leaf("Acknowledge alarm", move |_| {
                     tx_e.blocking_send(CommandRequest::AckAlarm).unwrap();
+                })
+                .leaf("Report alt temperatures", move |_| {
+                    tx_f.blocking_send(CommandRequest::ReportAltTemperatures)
+                        .unwrap();
                 }),
         )
         .add_delimiter()
@@ -2,10 +2,9 @@ use crate::ui::views::ProbeSelectView;
 use cursive::traits::Nameable;
 use cursive::views::Dialog;
 use cursive::Cursive;
-use device_controller::controller::command_request::CommandRequest;
-use tokio::sync::mpsc::Sender;
+use device_controller::controller::command_request::{CommandRequest, CommandSender};
 
-pub fn report_profile_cb(c: &mut Cursive, tx: &Sender<CommandRequest>) {
+pub fn report_profile_cb(c: &mut Cursive, tx: &CommandSender) {
     let tx_cb = tx.clone();
     c.add_layer(
         Dialog::new()
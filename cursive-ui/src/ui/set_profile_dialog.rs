@@ -2,12 +2,11 @@ use crate::ui::views::ProbeSelectView;
 use cursive::traits::Nameable;
 use cursive::views::{Dialog, EditView, ListView, SelectView};
 use cursive::Cursive;
-use device_controller::controller::command_request::CommandRequest;
+use device_controller::controller::command_request::{CommandRequest, CommandSender};
 use device_controller::model::device_temperature::InRangeDeviceTemperature;
 use device_controller::model::probe::{AlarmThreshold, RangeLimitThreshold, UpperLimitThreshold};
 use std::num::ParseFloatError;
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc::Sender;
 
 const TS_NO_THRESHOLD: &str = "No thresholds";
 const TS_UPPER_ONLY: &str = "Upper only";
@@ -25,7 +24,7 @@ enum TypeMenuIndex {
     Range,
 }
 
-pub fn set_profile_cb(c: &mut Cursive, tx: &Sender<CommandRequest>) {
+pub fn set_profile_cb(c: &mut Cursive, tx: &CommandSender) {
     let tx_cb = tx.clone();
 
     let type_state = Arc::new(Mutex::new(TsStore {
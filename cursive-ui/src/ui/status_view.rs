@@ -7,7 +7,7 @@ use device_controller::model::device::{TP25State, TemperatureMode};
 
 const STATUS_TEXT_NAME: &str = "status_text";
 pub fn make_status_view() -> Panel<NamedView<TextView>> {
-    Panel::new(TextView::new(status_view_message(false, None)).with_name(STATUS_TEXT_NAME))
+    Panel::new(TextView::new(status_view_message(false, None, false)).with_name(STATUS_TEXT_NAME))
         .title("Device status")
         .title_position(HAlign::Left)
 }
@@ -17,11 +17,16 @@ pub fn update_status_view(c: &mut Cursive, device_state: &TP25State) {
         v.set_content(status_view_message(
             device_state.connected,
             device_state.temperature_mode,
+            device_state.device_info.sync_complete,
         ))
     });
 }
 
-fn status_view_message(connected: bool, temperature_mode: Option<TemperatureMode>) -> StyledString {
+fn status_view_message(
+    connected: bool,
+    temperature_mode: Option<TemperatureMode>,
+    sync_complete: bool,
+) -> StyledString {
     let mut connection_text = StyledString::plain(if connected {
         "Device: Connected"
     } else {
@@ -37,5 +42,14 @@ fn status_view_message(connected: bool, temperature_mode: Option<TemperatureMode
     connection_text.append("\n");
     connection_text.append(format!("Temperature unit: {}", temp_mode_text));
 
+    if connected {
+        connection_text.append("\n");
+        connection_text.append(if sync_complete {
+            "Initial sync: Complete"
+        } else {
+            "Initial sync: Incomplete"
+        });
+    }
+
     connection_text
 }
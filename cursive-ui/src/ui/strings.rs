@@ -8,13 +8,18 @@ pub trait GetName {
 impl GetName for Notification {
     fn get_name(&self) -> &'static str {
         match self.decoded {
-            NtfyDecoded::Unknown => "Unknown",
-            NtfyDecoded::Startup => "Startup",
-            NtfyDecoded::SetTempMode => "Set Temp Mode",
-            NtfyDecoded::ReportProbeProfile(_) => "Report Probe Profile",
-            NtfyDecoded::Temperatures(_) => "Temperatures",
-            NtfyDecoded::SetProbeProfile => "Set Probe Profile",
-            NtfyDecoded::Error => "Error",
+            Err(_) => "Unknown",
+            Ok(NtfyDecoded::Startup) => "Startup",
+            Ok(NtfyDecoded::SetTempMode) => "Set Temp Mode",
+            Ok(NtfyDecoded::ReportProbeProfile(_)) => "Report Probe Profile",
+            Ok(NtfyDecoded::ReportExtraProbeProfile(_)) => "Report Extra Probe Profile",
+            Ok(NtfyDecoded::AltTemperatures(_)) => "Alt Temperatures",
+            Ok(NtfyDecoded::Unknown26(_)) => "Unknown 0x26",
+            Ok(NtfyDecoded::Unknown41(_)) => "Unknown 0x41",
+            Ok(NtfyDecoded::Temperatures(_)) => "Temperatures",
+            Ok(NtfyDecoded::SetProbeProfile(_)) => "Set Probe Profile",
+            Ok(NtfyDecoded::AlarmAck) => "Alarm Ack",
+            Ok(NtfyDecoded::Error) => "Error",
         }
     }
 }
@@ -26,7 +31,12 @@ impl GetName for Command {
             CmdDecoded::Startup => "Startup",
             CmdDecoded::SetTempMode(_) => "Set Temp Mode",
             CmdDecoded::ReportProfile(_) => "Report Probe Profile",
+            CmdDecoded::ReportExtraProfile(_) => "Report Extra Probe Profile",
             CmdDecoded::SetProbeProfile(_, _) => "Set Probe Profile",
+            CmdDecoded::TempReport => "Temp Report",
+            CmdDecoded::AltTempReport => "Alt Temp Report",
+            CmdDecoded::Unknown26 => "Unknown 0x26",
+            CmdDecoded::Unknown41 => "Unknown 0x41",
             CmdDecoded::Custom(_) => "Custom command",
         }
     }
@@ -2,7 +2,8 @@ use crate::model::transfer_log::TransferLog;
 use device_controller::model::device::TP25State;
 
 pub struct UpdateStateDetails {
-    pub device_state: TP25State,
+    // Boxed, as the state is much larger than the other commands.
+    pub device_state: Box<TP25State>,
 }
 
 pub enum UiCommand {
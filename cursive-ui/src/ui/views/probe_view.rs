@@ -4,7 +4,8 @@ use cursive::view::ViewWrapper;
 use cursive::views::TextView;
 use device_controller::model::device::TemperatureMode;
 use device_controller::model::device_temperature::DeviceTemperature::{InRange, OutOfRange};
-use device_controller::model::probe::{AlarmState, AlarmThreshold, Probe};
+use device_controller::model::probe::{AlarmState, AlarmThreshold, Probe, ProfileRearm};
+use std::time::Duration;
 
 pub struct ProbeView {
     inner: TextView,
@@ -48,11 +49,45 @@ impl ProbeView {
             }
         };
 
+        let r = match p.profile_rearm {
+            ProfileRearm::NotNeeded => None,
+            ProfileRearm::Pending => Some("Re-arming alarm..."),
+            ProfileRearm::Rearmed => Some("Alarm re-armed"),
+            ProfileRearm::Restored => Some("Alarm restored"),
+            ProfileRearm::Failed => Some("ALARM NOT RE-ARMED"),
+        };
+
+        let e = p.cook_estimate.map(|e| {
+            let mut l = StyledString::plain(format!("{} ", Temperature(e.target, *temp_mode)));
+            match (e.remaining, e.earliest, e.latest) {
+                (Some(remaining), Some(earliest), Some(latest)) => l.append(format!(
+                    "in {} ({}-{})",
+                    format_minutes(remaining),
+                    format_minutes(earliest),
+                    format_minutes(latest)
+                )),
+                (Some(remaining), _, _) => l.append(format!("in {}?", format_minutes(remaining))),
+                _ => l.append("not in sight"),
+            }
+            if e.stalled {
+                l.append(" STALLED");
+            }
+            l
+        });
+
         let mut styled = t;
         styled.append("\n");
         styled.append(a);
         styled.append("\n");
         styled.append(at);
+        if let Some(r) = r {
+            styled.append("\n");
+            styled.append(r);
+        }
+        if let Some(e) = e {
+            styled.append("\n");
+            styled.append(e);
+        }
 
         styled
     }
@@ -63,6 +98,12 @@ impl ProbeView {
     }
 }
 
+/// Format a duration as hours and minutes, e.g. "2h05".
+fn format_minutes(d: Duration) -> String {
+    let minutes = d.as_secs() / 60;
+    format!("{}h{:02}", minutes / 60, minutes % 60)
+}
+
 impl ViewWrapper for ProbeView {
     cursive::wrap_impl!(self.inner: TextView);
 }
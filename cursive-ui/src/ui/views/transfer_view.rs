@@ -3,6 +3,7 @@ use bytes::Bytes;
 use cursive::utils::markup::StyledString;
 use cursive::view::ViewWrapper;
 use cursive::views::TextView;
+use device_controller::peripheral::notification::Notification;
 use device_controller::peripheral::transfer::Transfer;
 
 pub struct TransferView {
@@ -22,6 +23,13 @@ impl TransferView {
         styled.append(name);
         styled.append("\n");
         styled.append(bytes_to_str(&raw));
+        if let Transfer::Notification(Notification {
+            decoded: Err(e), ..
+        }) = transfer
+        {
+            styled.append("\n");
+            styled.append(format!("Decode failed: {}", e));
+        }
         Self {
             inner: TextView::new(styled),
         }
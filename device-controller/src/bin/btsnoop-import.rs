@@ -0,0 +1,85 @@
+use device_controller::peripheral::btsnoop::{import, AttHandles};
+use device_controller::peripheral::capture::{write_capture, Direction};
+use std::fs::File;
+use std::io::BufWriter;
+use std::process;
+
+const USAGE: &str = "Usage: btsnoop-import <btsnoop_hci.log> [--export <capture.jsonl>] \
+    [--write-handle <handle>] [--notify-handle <handle>]";
+
+/// A handle given on the command line, in decimal or hex. Anything else is a usage error.
+fn parse_handle(arg: Option<String>) -> u16 {
+    let Some(s) = arg else { usage() };
+    let handle = match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    handle.unwrap_or_else(|_| usage())
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+/*
+ * Picks the conversation with a TP25 out of an Android btsnoop HCI log, prints it, and optionally exports it as a
+ * capture file that can be replayed.
+ *
+ * The characteristic handles are normally found from the service discovery in the log. If the log was started after
+ * the app connected, give them with --write-handle and --notify-handle (in decimal, or hex with a leading 0x).
+ */
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut log_path = None;
+    let mut export_path = None;
+    let mut handles = AttHandles::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--export" => export_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--write-handle" => handles.write = Some(parse_handle(args.next())),
+            "--notify-handle" => handles.notify = Some(parse_handle(args.next())),
+            _ if log_path.is_none() && !arg.starts_with("--") => log_path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let Some(log_path) = log_path else { usage() };
+
+    let log = std::fs::read(&log_path).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", log_path, e);
+        process::exit(1);
+    });
+    let records = import(&log, handles).unwrap_or_else(|e| {
+        eprintln!("Unable to import {}: {}", log_path, e);
+        process::exit(1);
+    });
+
+    if records.is_empty() {
+        eprintln!(
+            "No TP25 traffic found. If the log doesn't include the app connecting, give the handles with \
+             --write-handle and --notify-handle."
+        );
+    }
+    for record in &records {
+        let arrow = match record.dir {
+            Direction::Command => "->",
+            Direction::Notification => "<-",
+        };
+        println!(
+            "{:>10.3}  {}  {:<40}  {}",
+            record.t_ms as f64 / 1000.0,
+            arrow,
+            record.hex,
+            record.summary.as_deref().unwrap_or_default()
+        );
+    }
+
+    if let Some(export_path) = export_path {
+        let result =
+            File::create(&export_path).and_then(|f| write_capture(&records, BufWriter::new(f)));
+        if let Err(e) = result {
+            eprintln!("Unable to export to {}: {}", export_path, e);
+            process::exit(1);
+        }
+    }
+}
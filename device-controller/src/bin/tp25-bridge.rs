@@ -0,0 +1,105 @@
+use device_controller::dev_finder::{DeviceFinder, BRIDGE_SECRET_VAR, FINDER_USAGE};
+use device_controller::peripheral::remote::bridge::Bridge;
+use device_controller::peripheral::remote::RemoteAddress;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::process;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+
+/// Writes log messages to stderr, as there's no UI to show them in.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{:<5} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Wait for Ctrl-C, or SIGTERM where there is such a thing.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: tp25-bridge --listen <host:port|unix:path> {}",
+        FINDER_USAGE
+    );
+    process::exit(1);
+}
+
+/*
+ * Owns the Bluetooth connection to a TP25, and relays it over TCP or a Unix socket to a controller running elsewhere
+ * with `--backend remote:<address>`. One client is served at a time.
+ *
+ * If the TP25_BRIDGE_SECRET environment variable is set, clients must have it set to the same value.
+ *
+ * The thermometer is found as usual, optionally narrowed down with --address. Another --backend (such as `simulator`)
+ * can be relayed instead, for trying things out without a thermometer.
+ */
+#[tokio::main]
+async fn main() {
+    let _ = log::set_logger(&StderrLogger);
+    log::set_max_level(LevelFilter::Info);
+
+    let (finder, others) = DeviceFinder::from_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        usage()
+    });
+    let mut listen = None;
+    for (option, value) in others {
+        match option.as_str() {
+            "--listen" => listen = Some(RemoteAddress::parse(&value).unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+    let Some(listen) = listen else { usage() };
+    let secret = std::env::var(BRIDGE_SECRET_VAR).ok();
+    if secret.is_none() {
+        eprintln!(
+            "{} isn't set, so anyone who can reach {} can use the thermometer.",
+            BRIDGE_SECRET_VAR, listen
+        );
+    }
+
+    let listener = listen.bind().await.unwrap_or_else(|e| {
+        eprintln!("Unable to listen on {}: {}", listen, e);
+        process::exit(1);
+    });
+    println!("Listening on {}", listen);
+
+    let cancel = CancellationToken::new();
+    let bridge = Bridge::new(finder, secret);
+    let run = bridge.run(listener, &cancel);
+    tokio::pin!(run);
+    tokio::select! {
+        _ = &mut run => {},
+        _ = shutdown_signal() => {
+            // Finish with the client cleanly, so that the thermometer is disconnected from too.
+            cancel.cancel();
+            run.await;
+        }
+    }
+    #[cfg(unix)]
+    if let RemoteAddress::Unix(path) = &listen {
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,3 +1,11 @@
 pub mod command_request;
 pub mod connection_handler;
 pub mod connection_mgr;
+pub mod device_event;
+pub mod device_manager;
+mod expected_response;
+pub mod handle;
+pub mod history;
+pub mod host_alarm;
+pub mod session;
+pub mod transfer_recorder;
@@ -1,4 +1,6 @@
 use crate::model::probe::{AlarmThreshold, ProbeIdx};
+use crate::Error;
+use tokio::sync::{mpsc, oneshot};
 
 pub enum CommandRequest {
     ToggleTempMode,
@@ -7,5 +9,80 @@ pub enum CommandRequest {
     ReportProfile(ProbeIdx),
     SetProfile(ProbeIdx, AlarmThreshold),
     AckAlarm,
+    ReportAltTemperatures,
     CustomCommand(Vec<u8>),
 }
+
+/// Whether a command request took effect.
+pub type CommandResult = Result<(), Error>;
+
+/// A `CommandRequest` waiting to be handled by the controller, and optionally somewhere to report the outcome.
+pub struct PendingRequest {
+    pub request: CommandRequest,
+    responder: Option<oneshot::Sender<CommandResult>>,
+}
+
+impl PendingRequest {
+    pub fn respond(self, result: CommandResult) {
+        if let Some(responder) = self.responder {
+            // The requester may not care about the result any more, which is fine.
+            let _ = responder.send(result);
+        }
+    }
+}
+
+pub type CommandReceiver = mpsc::Receiver<PendingRequest>;
+
+/// Sends command requests to the controller.
+#[derive(Clone)]
+pub struct CommandSender {
+    tx: mpsc::Sender<PendingRequest>,
+}
+
+impl CommandSender {
+    /// Queue a request for the controller, without waiting to find out if it took effect.
+    pub async fn send(&self, request: CommandRequest) -> CommandResult {
+        self.tx
+            .send(PendingRequest {
+                request,
+                responder: None,
+            })
+            .await
+            .map_err(|_| Error::Shutdown)
+    }
+
+    /// As `send`, but for use outside of an async context.
+    pub fn blocking_send(&self, request: CommandRequest) -> CommandResult {
+        self.tx
+            .blocking_send(PendingRequest {
+                request,
+                responder: None,
+            })
+            .map_err(|_| Error::Shutdown)
+    }
+
+    /// Queue a request for the controller, and wait until the thermometer has acknowledged it.
+    ///
+    /// Requests that result in several commands (such as `ReportAllProfiles`) complete when all the commands are
+    /// acknowledged, or fail as soon as any one of them fails. Custom commands complete as soon as they are sent, as
+    /// there is no way of knowing what response to expect.
+    pub async fn request(&self, request: CommandRequest) -> CommandResult {
+        let (responder, result) = oneshot::channel();
+        self.tx
+            .send(PendingRequest {
+                request,
+                responder: Some(responder),
+            })
+            .await
+            .map_err(|_| Error::Shutdown)?;
+
+        // The controller drops requests it was part way through if the connection drops.
+        result.await.unwrap_or(Err(Error::Disconnected))
+    }
+}
+
+/// Create a channel for sending command requests to the controller.
+pub fn command_channel(buffer: usize) -> (CommandSender, CommandReceiver) {
+    let (tx, rx) = mpsc::channel(buffer);
+    (CommandSender { tx }, rx)
+}
@@ -1,221 +1,546 @@
-use crate::controller::command_request::CommandRequest;
+use crate::controller::command_request::{CommandReceiver, CommandRequest, CommandResult};
 use crate::controller::connection_mgr::ProtectedDeviceState;
+use crate::controller::expected_response::{ExpectedResponseSlot, ResponseKind, ResponseResult};
+use crate::controller::handle::{ControllerEvent, Publisher};
+use crate::model::cook_estimate::{CookEstimator, EstimatorSettings};
 use crate::model::device::{TP25State, TemperatureMode};
+use crate::model::device_info::{DeviceInfo, SyncResponse};
+use crate::model::device_temperature::InRangeDeviceTemperature;
 use crate::model::probe::ProbeIdx::{Probe1, Probe2, Probe3, Probe4};
-use crate::model::probe::{AlarmState, AlarmThreshold, ProbeIdx};
+use crate::model::probe::{AlarmState, AlarmThreshold, ProbeIdx, ProfileRearm};
 use crate::peripheral::command::{
-    build_alarm_ack_cmd, build_custom_cmd, build_report_profile_cmd, build_set_profile_cmd,
-    build_set_temp_mode_command, build_startup_command, Command,
+    build_alarm_ack_cmd, build_alt_temp_report_cmd, build_custom_cmd,
+    build_report_extra_profile_cmd, build_report_profile_cmd, build_set_profile_cmd,
+    build_set_temp_mode_command, build_startup_command, build_temp_report_cmd,
+    build_unknown_26_cmd, build_unknown_41_cmd, Command,
 };
 use crate::peripheral::interface::{TP25Receiver, TP25Writer};
-use crate::peripheral::notification::{Decoded, Notification, ProbeProfileData, TemperatureData};
+use crate::peripheral::notification::{
+    AltTemperatureData, Decoded, Notification, ProbeProfileData, TemperatureData,
+};
 use crate::peripheral::transfer::Transfer;
-use log::{debug, warn};
+use crate::Error;
+use log::{debug, info, warn};
 use std::sync::Arc;
-use tokio::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio::task::JoinSet;
+use tokio::time::{timeout, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// How commands are retried if the thermometer doesn't acknowledge them.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// How long to wait for the thermometer to respond to a command before sending it again.
+    pub response_timeout: Duration,
+    /// The maximum number of times to send a command, including the first attempt.
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        // The official app has been seen sending a command five times before it was acknowledged, but that seems
+        // excessive for a first attempt.
+        Self {
+            response_timeout: Duration::from_secs(2),
+            max_attempts: 3,
+        }
+    }
+}
+
+/// The queries sent to the thermometer straight after connecting, so that the device state is filled in without
+/// waiting for the thermometer to volunteer it. By default this mirrors what the official app does: 0x26, 0x24 for
+/// each probe, 0x41 and then 0x25.
+#[derive(Clone, Copy, Debug)]
+pub struct InitialSync {
+    /// If not enabled, the device state is only filled in as notifications arrive.
+    pub enabled: bool,
+    /// Also ask for the profiles of probes 5 and 6, as the app does, even though the TP25 doesn't have them.
+    pub query_extra_probes: bool,
+}
+
+impl Default for InitialSync {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            query_extra_probes: true,
+        }
+    }
+}
+
+/// How the handler notices that the thermometer has stopped talking. The thermometer pushes temperature reports by
+/// itself, so if nothing arrives for a while it's asked for one with a 0x30 command. Only if several of those go
+/// unanswered is the link treated as dead.
+#[derive(Clone, Copy, Debug)]
+pub struct Liveness {
+    /// How long the thermometer can go without sending anything before it is polled.
+    pub notification_interval: Duration,
+    /// How long to wait for anything to arrive after each poll.
+    pub poll_timeout: Duration,
+    /// How many polls in a row can go unanswered before the connection is given up on.
+    pub max_missed_polls: u32,
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        // The thermometer normally reports every couple of seconds, and the connection used to be dropped after 4
+        // seconds of silence, so start polling then.
+        Self {
+            notification_interval: Duration::from_secs(4),
+            poll_timeout: Duration::from_secs(2),
+            max_missed_polls: 3,
+        }
+    }
+}
+
+/// The alarm profiles most recently set on each probe, kept between connections so that they can be re-armed.
+type RememberedProfiles = Arc<std::sync::Mutex<[Option<AlarmThreshold>; 4]>>;
+
+/// Each probe's cook estimator, kept between connections so that a dropped connection doesn't lose the history.
+type CookEstimators = Arc<std::sync::Mutex<[CookEstimator; 4]>>;
+
+/// Changes the probes' cook targets for a `ControllerHandle`. The targets only affect the cook estimates, so nothing is
+/// sent to the thermometer, and they can be changed whether or not it's connected.
+#[derive(Clone)]
+pub(crate) struct CookTargets {
+    protected_device_state: ProtectedDeviceState,
+    publisher: Publisher,
+    cook_estimators: CookEstimators,
+    cook_estimates: EstimatorSettings,
+}
+
+impl CookTargets {
+    /// Estimate when probe `idx` will reach `target`, rather than its alarm threshold (or go back to the alarm
+    /// threshold if `None`).
+    pub(crate) async fn set(&self, idx: ProbeIdx, target: Option<InRangeDeviceTemperature>) {
+        let device_state = &mut self.protected_device_state.lock().await;
+        device_state.probes[idx.as_zero_based() as usize].cook_target = target;
+        update_cook_estimates(
+            &self.cook_estimators,
+            &self.cook_estimates,
+            device_state,
+            None,
+        );
+        self.publisher.publish_state(device_state.clone());
+    }
+}
 
 /// Communicates with a TP25 and keeps a record of its state, promulgating updates as required.
-pub struct ConnectionHandler {}
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionHandler {
+    pub retry_policy: RetryPolicy,
+    pub initial_sync: InitialSync,
+    pub liveness: Liveness,
+    pub cook_estimates: EstimatorSettings,
+    remembered_profiles: RememberedProfiles,
+    cook_estimators: CookEstimators,
+}
 
 impl ConnectionHandler {
+    /// A handler with the same settings as this one, but which remembers its own alarm profiles. Clones share
+    /// remembered profiles, which is only right when they're talking to the same thermometer.
+    pub fn for_another_device(&self) -> Self {
+        Self {
+            retry_policy: self.retry_policy,
+            initial_sync: self.initial_sync,
+            liveness: self.liveness,
+            cook_estimates: self.cook_estimates,
+            remembered_profiles: RememberedProfiles::default(),
+            cook_estimators: CookEstimators::default(),
+        }
+    }
+
+    /// Somewhere to change the cook targets in `protected_device_state`, using this handler's cook estimators.
+    pub(crate) fn cook_targets(
+        &self,
+        protected_device_state: &ProtectedDeviceState,
+        publisher: &Publisher,
+    ) -> CookTargets {
+        CookTargets {
+            protected_device_state: protected_device_state.clone(),
+            publisher: publisher.clone(),
+            cook_estimators: self.cook_estimators.clone(),
+            cook_estimates: self.cook_estimates,
+        }
+    }
+
     /// Control a provided connection to a TP25 (given as `peripheral_rx` and `peripheral_tx`). This means sending it
     /// commands and listening for notifications. The combination of these allows an internal record of the device
     /// state to be updated and sent to any listeners.
     ///
-    /// This function will exit either due to an error, or when the connection to the device is dropped.
+    /// This function will exit either due to an error, when the connection to the device is dropped, or when `cancel`
+    /// is cancelled. The device is disconnected before it returns, and the reason the connection ended is returned
+    /// (`Error::Shutdown` if it was cancelled).
     pub async fn handle_one_connection(
         &self,
         mut peripheral_rx: impl TP25Receiver + 'static,
         peripheral_tx: impl TP25Writer + 'static + Sync,
         protected_device_state: &ProtectedDeviceState,
-        state_update_tx: &Sender<TP25State>,
-        transfer_tx: &Sender<Transfer>,
-        command_request_rx: Arc<Mutex<Receiver<CommandRequest>>>,
-    ) {
-        if send_startup_cmd(&peripheral_tx, transfer_tx).await.is_err() {
-            warn!("Unable to send startup command, connection will abort");
-            return;
-        };
+        publisher: &Publisher,
+        command_request_rx: Arc<Mutex<CommandReceiver>>,
+        cancel: &CancellationToken,
+    ) -> Error {
+        let expected_response = ExpectedResponseSlot::default();
+        let sink = Arc::new(CommandSink {
+            device: peripheral_tx,
+            publisher: publisher.clone(),
+            expected_response: expected_response.clone(),
+            retry_policy: self.retry_policy,
+        });
+
+        let initial_sync = self.initial_sync;
+        let liveness = self.liveness;
+        let cook_estimates = self.cook_estimates;
+        let cook_estimators = self.cook_estimators.clone();
+        let remembered_profiles = self.remembered_profiles.clone();
+        mark_rearm_pending(protected_device_state, &remembered_profiles).await;
+        protected_device_state.lock().await.device_info = DeviceInfo::default();
 
         let protected_device_state = protected_device_state.clone();
         let protected_device_state_b = protected_device_state.clone();
-        let transfer_tx = transfer_tx.clone();
-        let transfer_tx_b = transfer_tx.clone();
-        let state_update_tx = state_update_tx.clone();
+        let publisher = publisher.clone();
 
         let mut tasks = JoinSet::new();
 
         // Receive notifications from the thermometer. Use them to update our model of the
         // thermometer, and send updated state to the UI.
-        tasks.spawn(async move {
+        let poll_sink = sink.clone();
+        let receiver_task = tasks.spawn(async move {
             debug!("Starting receiver task");
+            let mut missed_polls = 0;
+            let mut started_up = false;
             loop {
-                let Some(n) = peripheral_rx.get_notification().await else {
-                    // If we stop receiving notifications then just exit. The outer loop in `run`
-                    // will attempt to reconnect, and will set appropriate state for the UI to
-                    // understand what is happening.
-                    debug!("Device receiver task exiting - notification failure");
-                    return;
+                let wait = if missed_polls == 0 {
+                    liveness.notification_interval
+                } else {
+                    liveness.poll_timeout
                 };
+                let n = match timeout(wait, peripheral_rx.get_notification()).await {
+                    Ok(Ok(n)) => n,
+                    Err(_) if missed_polls >= liveness.max_missed_polls => {
+                        warn!(
+                            "Thermometer did not answer {} polls, connection will abort",
+                            missed_polls
+                        );
+                        return Error::Timeout;
+                    }
+                    Err(_) => {
+                        missed_polls += 1;
+                        debug!(
+                            "Thermometer has gone quiet, polling it (poll {})",
+                            missed_polls
+                        );
+                        if let Err(e) = write_cmd(&poll_sink, build_temp_report_cmd()).await {
+                            debug!("Device receiver task exiting - unable to poll: {}", e);
+                            return e;
+                        }
+                        continue;
+                    }
+                    Ok(Err(e)) => {
+                        // If we stop receiving notifications then just exit. The outer loop in `run`
+                        // will attempt to reconnect, and will set appropriate state for the UI to
+                        // understand what is happening.
+                        debug!("Device receiver task exiting - notification failure: {}", e);
+                        return e;
+                    }
+                };
+                missed_polls = 0;
                 let device_state = &mut protected_device_state.lock().await;
-                if transfer_tx
-                    .send(Transfer::Notification(n.clone()))
-                    .await
-                    .is_err()
-                {
-                    debug!("Device receiver task exiting - UI state update failure");
-                    return;
+                if let Ok(Decoded::Startup) = n.decoded {
+                    if started_up {
+                        info!("Thermometer started up again without the link dropping");
+                        device_state.reboots += 1;
+                    }
+                    started_up = true;
                 }
-                handle_notification(n, &state_update_tx, device_state).await;
+                publisher
+                    .publish_event(ControllerEvent::Transfer(Transfer::Notification(n.clone())));
+                handle_notification(
+                    &n,
+                    &publisher,
+                    device_state,
+                    &cook_estimators,
+                    &cook_estimates,
+                );
+
+                // Only tell anyone waiting for this response after the state has been updated, so that they see the
+                // effect of their command.
+                expected_response.resolve(&n);
             }
         });
 
         // Receive "command requests" from the UI, process them and send relevant commands to the
         // thermometer.
+        let task_sink = sink.clone();
+        let task_cancel = cancel.clone();
         tasks.spawn(async move {
             debug!("Starting UI command request task");
+            let sink = &*task_sink;
             let mut command_request_rx = command_request_rx.lock().await;
 
+            // No requests are being handled yet, so this can simply be abandoned on shutdown.
+            let set_up = tokio::select! {
+                set_up = set_up_connection(
+                    sink,
+                    initial_sync,
+                    &remembered_profiles,
+                    &protected_device_state_b,
+                ) => set_up,
+                _ = task_cancel.cancelled() => return Error::Shutdown,
+            };
+            if let Err(e) = set_up {
+                debug!("UI command request task exiting (command send failure)");
+                return e;
+            }
+
             loop {
-                let Some(r) = command_request_rx.recv().await else {
+                let r = tokio::select! {
+                    r = command_request_rx.recv() => r,
+                    _ = task_cancel.cancelled() => return Error::Shutdown,
+                };
+                let Some(r) = r else {
                     debug!("UI command request task exiting (request receive failure)");
-                    return;
+                    return Error::Shutdown;
                 };
-                if handle_command_request(
-                    r,
-                    &peripheral_tx,
-                    &transfer_tx_b,
-                    get_device_state(&protected_device_state_b).await,
-                )
-                .await
-                .is_err()
-                {
+                let result = tokio::select! {
+                    result = handle_command_request(
+                        &r.request,
+                        sink,
+                        get_device_state(&protected_device_state_b).await,
+                    ) => result,
+                    _ = task_cancel.cancelled() => Err(Error::Shutdown),
+                };
+                if let (Ok(()), CommandRequest::SetProfile(idx, profile)) = (&result, &r.request) {
+                    remember_profile(&remembered_profiles, *idx, *profile);
+                }
+                let link_failure = result
+                    .as_ref()
+                    .err()
+                    .filter(|e| is_link_failure(e))
+                    .cloned();
+                r.respond(result);
+
+                if let Some(e) = link_failure {
                     debug!("UI command request task exiting (command send failure)");
-                    return;
+                    return e;
                 }
             }
         });
 
-        // Letting `tasks` fall out of scope will drop the other task. This will hopefully allow
-        // the device to reconnect properly.
-        tasks.join_next().await;
-        debug!("Connection failed - at least one task exited");
+        let reason = tokio::select! {
+            reason = tasks.join_next() => {
+                debug!("Connection failed - at least one task exited");
+                // Dropping the other task will hopefully allow the device to reconnect properly.
+                tasks.shutdown().await;
+                match reason {
+                    Some(Ok(reason)) => reason,
+                    _ => Error::transport("Connection task failed"),
+                }
+            }
+            _ = cancel.cancelled() => {
+                info!("Shutting down connection");
+                // The command task exits by itself once it has failed the request it was working on, if any.
+                receiver_task.abort();
+                while tasks.join_next().await.is_some() {}
+                Error::Shutdown
+            }
+        };
+
+        if let Err(e) = sink.device.disconnect().await {
+            debug!("Unable to disconnect cleanly: {}", e);
+        }
+        reason
+    }
+}
+
+/// Get a newly connected thermometer ready for use: start it up, find out about it and re-arm its alarms. Only gives
+/// an error if the connection is no longer usable.
+async fn set_up_connection(
+    sink: &CommandSink<impl TP25Writer>,
+    initial_sync: InitialSync,
+    remembered_profiles: &RememberedProfiles,
+    protected_device_state: &ProtectedDeviceState,
+) -> CommandResult {
+    // The thermometer ignores everything else until it has seen this.
+    send_cmd(sink, build_startup_command())
+        .await
+        .inspect_err(|e| {
+            warn!(
+                "Unable to send startup command, connection will abort: {}",
+                e
+            )
+        })?;
+    {
+        let device_state = &mut protected_device_state.lock().await;
+        device_state.sessions += 1;
+        sink.publisher.publish_state(device_state.clone());
+    }
+
+    if initial_sync.enabled {
+        sync_device_info(sink, initial_sync, protected_device_state, &sink.publisher).await?;
     }
+
+    // Deal with this before any requests, so nothing can change the profiles while they're being compared.
+    rearm_profiles(
+        sink,
+        remembered_profiles,
+        protected_device_state,
+        &sink.publisher,
+    )
+    .await
 }
 
-async fn handle_notification(
-    notification: Notification,
-    ui_cmd_tx: &Sender<TP25State>,
+/// Whether `e` means there's no point sending any more commands over this connection.
+fn is_link_failure(e: &Error) -> bool {
+    matches!(e, Error::Transport(_))
+}
+
+/// Everything needed to send commands to the thermometer and wait for them to be acknowledged.
+struct CommandSink<W: TP25Writer> {
+    device: W,
+    publisher: Publisher,
+    expected_response: ExpectedResponseSlot,
+    retry_policy: RetryPolicy,
+}
+
+fn handle_notification(
+    notification: &Notification,
+    publisher: &Publisher,
     device_state: &mut TP25State,
+    cook_estimators: &CookEstimators,
+    cook_estimates: &EstimatorSettings,
 ) {
-    update_model_from_notification(&notification, device_state);
-    send_state_update(ui_cmd_tx, device_state.clone()).await;
+    let now = Instant::now();
+    update_model_from_notification(notification, device_state);
+    if let Ok(Decoded::Temperatures(_)) = notification.decoded {
+        update_cook_estimates(cook_estimators, cook_estimates, device_state, Some(now));
+    }
+    device_state.link.last_notification_at = Some(now);
+    publisher.publish_state(device_state.clone());
+}
+
+/// Work out each probe's cook estimate again, first recording the probes' temperatures as readings taken at
+/// `reading_at` if given.
+fn update_cook_estimates(
+    cook_estimators: &CookEstimators,
+    settings: &EstimatorSettings,
+    device_state: &mut TP25State,
+    reading_at: Option<Instant>,
+) {
+    let mut estimators = cook_estimators.lock().unwrap();
+    for (probe, estimator) in device_state.probes.iter_mut().zip(estimators.iter_mut()) {
+        if let Some(at) = reading_at {
+            estimator.add_reading(at, probe.temperature, settings);
+        }
+        probe.cook_estimate = probe
+            .estimate_target()
+            .and_then(|target| estimator.estimate(target, settings));
+    }
 }
 
 fn update_model_from_notification(
     notification: &Notification,
     device_state: &mut TP25State,
 ) -> bool {
-    match &notification.decoded {
-        Decoded::Unknown => false,
+    let decoded = match &notification.decoded {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            debug!("Ignoring notification that could not be decoded: {}", e);
+            return false;
+        }
+    };
+
+    match decoded {
         Decoded::Startup => false,
         Decoded::SetTempMode => false,
         Decoded::ReportProbeProfile(profile_data) => {
             handle_probe_profile(profile_data, device_state);
             true
         }
+        Decoded::AltTemperatures(temps) => {
+            handle_alt_temps(temps, device_state);
+            true
+        }
         Decoded::Temperatures(temps) => {
             handle_temps(temps, device_state);
             true
         }
-        Decoded::SetProbeProfile => false,
+        Decoded::SetProbeProfile(_) => false,
+        // The sync stage records these in the device info, but otherwise they don't affect the model.
+        Decoded::ReportExtraProbeProfile(_) => false,
+        Decoded::Unknown26(_) => false,
+        Decoded::Unknown41(_) => false,
+        Decoded::AlarmAck => false,
         Decoded::Error => false,
     }
 }
 
 async fn handle_command_request(
-    command_request: CommandRequest,
-    device: &impl TP25Writer,
-    transfer_tx: &Sender<Transfer>,
+    command_request: &CommandRequest,
+    sink: &CommandSink<impl TP25Writer>,
     device_state: TP25State,
-) -> btleplug::Result<()> {
+) -> CommandResult {
     match command_request {
         CommandRequest::ToggleTempMode => {
             let mode = match device_state.temperature_mode {
                 Some(TemperatureMode::Celsius) => TemperatureMode::Fahrenheit,
                 _ => TemperatureMode::Celsius,
             };
-            send_temp_mode_cmd(mode, device, transfer_tx).await?;
+            send_temp_mode_cmd(sink, mode).await?;
         }
         CommandRequest::SetTempMode(celsius) => {
             send_temp_mode_cmd(
-                if celsius {
+                sink,
+                if *celsius {
                     TemperatureMode::Celsius
                 } else {
                     TemperatureMode::Fahrenheit
                 },
-                device,
-                transfer_tx,
             )
             .await?;
         }
         CommandRequest::ReportAllProfiles => {
-            send_query_profile(device, transfer_tx, Probe1).await?;
-            send_query_profile(device, transfer_tx, Probe2).await?;
-            send_query_profile(device, transfer_tx, Probe3).await?;
-            send_query_profile(device, transfer_tx, Probe4).await?;
+            send_query_profile(sink, Probe1).await?;
+            send_query_profile(sink, Probe2).await?;
+            send_query_profile(sink, Probe3).await?;
+            send_query_profile(sink, Probe4).await?;
         }
         CommandRequest::ReportProfile(idx) => {
-            send_query_profile(device, transfer_tx, idx).await?;
+            send_query_profile(sink, *idx).await?;
         }
         CommandRequest::SetProfile(idx, profile) => {
-            send_set_profile(device, transfer_tx, idx, profile).await?;
+            send_set_profile(sink, *idx, *profile).await?;
         }
         CommandRequest::AckAlarm => {
-            send_alarm_ack_cmd(device, transfer_tx).await?;
+            send_alarm_ack_cmd(sink).await?;
+        }
+        CommandRequest::ReportAltTemperatures => {
+            send_alt_temp_report_cmd(sink).await?;
         }
         CommandRequest::CustomCommand(bytes) => {
-            send_custom_cmd(device, transfer_tx, bytes).await?;
+            send_custom_cmd(sink, bytes.clone()).await?;
         }
     };
     Ok(())
 }
 
-async fn send_startup_cmd(
-    device: &impl TP25Writer,
-    transfer_tx: &Sender<Transfer>,
-) -> btleplug::Result<()> {
-    send_cmd(device, transfer_tx, build_startup_command()).await
-}
-
 async fn send_temp_mode_cmd(
+    sink: &CommandSink<impl TP25Writer>,
     mode: TemperatureMode,
-    device: &impl TP25Writer,
-    transfer_tx: &Sender<Transfer>,
-) -> btleplug::Result<()> {
-    send_cmd(device, transfer_tx, build_set_temp_mode_command(mode)).await
+) -> CommandResult {
+    send_cmd(sink, build_set_temp_mode_command(mode)).await
 }
 
-async fn send_state_update(ui_cmd_tx: &Sender<TP25State>, device_state: TP25State) {
-    // TODO: handle when this send fails, as the receiver has gone away
-    let _ = ui_cmd_tx.send(device_state).await;
+async fn send_alarm_ack_cmd(sink: &CommandSink<impl TP25Writer>) -> CommandResult {
+    send_cmd(sink, build_alarm_ack_cmd()).await
 }
 
-async fn send_alarm_ack_cmd(
-    device: &impl TP25Writer,
-    transfer_tx: &Sender<Transfer>,
-) -> btleplug::Result<()> {
-    send_cmd(device, transfer_tx, build_alarm_ack_cmd()).await
+async fn send_alt_temp_report_cmd(sink: &CommandSink<impl TP25Writer>) -> CommandResult {
+    send_cmd(sink, build_alt_temp_report_cmd()).await
 }
 
-async fn send_custom_cmd(
-    device: &impl TP25Writer,
-    transfer_tx: &Sender<Transfer>,
-    bytes: Vec<u8>,
-) -> btleplug::Result<()> {
-    send_cmd(device, transfer_tx, build_custom_cmd(bytes)).await
+async fn send_custom_cmd(sink: &CommandSink<impl TP25Writer>, bytes: Vec<u8>) -> CommandResult {
+    send_cmd(sink, build_custom_cmd(bytes)).await
 }
 
 async fn get_device_state(protected: &ProtectedDeviceState) -> TP25State {
@@ -234,37 +559,641 @@ fn handle_temps(temps: &TemperatureData, device_state: &mut TP25State) {
     device_state.temperature_mode = Some(temps.temp_mode);
 }
 
+// The 0x25 report only carries temperatures, so the alarm state and temperature mode are left as they were.
+fn handle_alt_temps(temps: &AltTemperatureData, device_state: &mut TP25State) {
+    for (probe, temp) in device_state.probes.iter_mut().zip(temps.temps) {
+        probe.temperature = temp;
+    }
+}
+
 fn handle_probe_profile(profile_data: &ProbeProfileData, device_state: &mut TP25State) {
     device_state.probes[profile_data.idx.as_zero_based() as usize].alarm_threshold =
         Some(profile_data.threshold);
 }
 
-async fn send_cmd(
-    device: &impl TP25Writer,
-    transfer_tx: &Sender<Transfer>,
+/// Ask the thermometer for everything the official app asks for after connecting, and record the responses in the
+/// device info. Queries that go unanswered are skipped, and the sync is only marked complete if none were.
+async fn sync_device_info(
+    sink: &CommandSink<impl TP25Writer>,
+    config: InitialSync,
+    protected: &ProtectedDeviceState,
+    publisher: &Publisher,
+) -> CommandResult {
+    let mut info = DeviceInfo::default();
+    let mut complete = true;
+
+    match sync_query(sink, build_unknown_26_cmd()).await? {
+        Some((raw, Decoded::Unknown26(v))) => {
+            info.unknown_26 = Some(SyncResponse { raw, decoded: v })
+        }
+        _ => complete = false,
+    }
+
+    let probe_count = if config.query_extra_probes { 6 } else { 4 };
+    for index in 1..=probe_count {
+        let command = match ProbeIdx::try_from_one_based(index) {
+            Ok(idx) => build_report_profile_cmd(idx),
+            Err(_) => build_report_extra_profile_cmd(index),
+        };
+        let threshold = match sync_query(sink, command).await? {
+            Some((raw, Decoded::ReportProbeProfile(d))) => Some((raw, d.threshold)),
+            Some((raw, Decoded::ReportExtraProbeProfile(d))) => Some((raw, d.threshold)),
+            _ => None,
+        };
+        match threshold {
+            Some((raw, threshold)) => {
+                info.probe_profiles[index as usize - 1] = Some(SyncResponse {
+                    raw,
+                    decoded: threshold,
+                })
+            }
+            None => complete = false,
+        }
+    }
+
+    match sync_query(sink, build_unknown_41_cmd()).await? {
+        Some((raw, Decoded::Unknown41(v))) => {
+            info.unknown_41 = Some(SyncResponse { raw, decoded: v })
+        }
+        _ => complete = false,
+    }
+
+    match sync_query(sink, build_alt_temp_report_cmd()).await? {
+        Some((raw, Decoded::AltTemperatures(d))) => {
+            info.alt_temperatures = Some(SyncResponse {
+                raw,
+                decoded: d.temps,
+            })
+        }
+        _ => complete = false,
+    }
+
+    info.sync_complete = complete;
+    if !complete {
+        warn!("Initial sync incomplete - the thermometer did not answer every query");
+    }
+
+    let device_state = &mut protected.lock().await;
+    device_state.device_info = info;
+    publisher.publish_state(device_state.clone());
+    Ok(())
+}
+
+/// Send one of the initial sync queries, giving back the response. A query that wasn't answered gives `None`, so that
+/// the sync can carry on - only failing to send anything at all is treated as an error.
+async fn sync_query(
+    sink: &CommandSink<impl TP25Writer>,
     command: Command,
-) -> btleplug::Result<()> {
-    transfer_tx
-        .send(Transfer::Command(command.clone()))
-        .await
-        // TODO: Remove an unwrap here
-        .unwrap();
-    device.send_cmd(command).await
+) -> Result<Option<(Vec<u8>, Decoded)>, Error> {
+    let kind = ResponseKind::for_command(&command.decoded)
+        .expect("Initial sync queries always have a known response");
+    match send_query(sink, command, kind).await {
+        Ok(Notification {
+            raw,
+            decoded: Ok(decoded),
+        }) => Ok(Some((raw.to_vec(), decoded))),
+        Ok(_) => Ok(None),
+        Err(e) if is_link_failure(&e) => Err(e),
+        Err(e) => {
+            debug!("Initial sync query failed: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+fn remember_profile(remembered: &RememberedProfiles, idx: ProbeIdx, profile: AlarmThreshold) {
+    // A cleared profile doesn't need re-arming.
+    remembered.lock().unwrap()[idx.as_zero_based() as usize] = match profile {
+        AlarmThreshold::NoneSet => None,
+        p => Some(p),
+    };
+}
+
+async fn mark_rearm_pending(protected: &ProtectedDeviceState, remembered: &RememberedProfiles) {
+    let remembered = *remembered.lock().unwrap();
+    let device_state = &mut protected.lock().await;
+    for (probe, profile) in device_state.probes.iter_mut().zip(remembered) {
+        probe.profile_rearm = match profile {
+            Some(_) => ProfileRearm::Pending,
+            None => ProfileRearm::NotNeeded,
+        };
+    }
 }
 
-async fn send_query_profile(
-    device: &impl TP25Writer,
-    transfer_tx: &Sender<Transfer>,
+/// Set the remembered profiles on the thermometer again, as it doesn't alarm after being switched off and on until
+/// that is done. The outcome for each probe is recorded in the device state.
+async fn rearm_profiles(
+    sink: &CommandSink<impl TP25Writer>,
+    remembered: &RememberedProfiles,
+    protected: &ProtectedDeviceState,
+    publisher: &Publisher,
+) -> CommandResult {
+    let remembered = *remembered.lock().unwrap();
+    for (i, profile) in remembered.into_iter().enumerate() {
+        let Some(profile) = profile else {
+            continue;
+        };
+        let idx = ProbeIdx::from_zero_based(i as u8);
+        let result = rearm_profile(sink, idx, profile, protected).await;
+
+        let device_state = &mut protected.lock().await;
+        device_state.probes[i].profile_rearm = match &result {
+            Ok(outcome) => {
+                info!("Re-armed alarm profile for probe {}", idx.as_one_based());
+                *outcome
+            }
+            Err(e) => {
+                warn!(
+                    "Unable to re-arm alarm profile for probe {}: {}",
+                    idx.as_one_based(),
+                    e
+                );
+                ProfileRearm::Failed
+            }
+        };
+        publisher.publish_state(device_state.clone());
+
+        match result {
+            Err(e) if is_link_failure(&e) => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+async fn rearm_profile(
+    sink: &CommandSink<impl TP25Writer>,
     idx: ProbeIdx,
-) -> btleplug::Result<()> {
-    send_cmd(device, transfer_tx, build_report_profile_cmd(idx)).await
+    profile: AlarmThreshold,
+    protected: &ProtectedDeviceState,
+) -> Result<ProfileRearm, Error> {
+    send_query_profile(sink, idx).await?;
+    let reported = protected.lock().await.probes[idx.as_zero_based() as usize].alarm_threshold;
+
+    send_set_profile(sink, idx, profile).await?;
+    // Read it back again, so the device state shows what the thermometer now has.
+    send_query_profile(sink, idx).await?;
+
+    Ok(if reported == Some(profile) {
+        ProfileRearm::Rearmed
+    } else {
+        ProfileRearm::Restored
+    })
+}
+
+/// Send a command, and wait for the thermometer to acknowledge it - retrying according to the retry policy if it
+/// doesn't.
+async fn send_cmd(sink: &CommandSink<impl TP25Writer>, command: Command) -> CommandResult {
+    match ResponseKind::for_command(&command.decoded) {
+        Some(kind) => send_query(sink, command, kind).await.map(|_| ()),
+        None => write_cmd(sink, command).await,
+    }
+}
+
+/// As `send_cmd`, but gives back the response that acknowledged the command.
+async fn send_query(
+    sink: &CommandSink<impl TP25Writer>,
+    command: Command,
+    kind: ResponseKind,
+) -> ResponseResult {
+    for attempt in 1..=sink.retry_policy.max_attempts {
+        let response = sink.expected_response.expect(kind);
+        if let Err(e) = write_cmd(sink, command.clone()).await {
+            sink.expected_response.clear();
+            return Err(e);
+        }
+
+        match timeout(sink.retry_policy.response_timeout, response).await {
+            Ok(Ok(result)) => return result,
+            Ok(Err(_)) => return Err(Error::Disconnected),
+            Err(_) => debug!(
+                "No response to {:?} command (attempt {} of {})",
+                kind, attempt, sink.retry_policy.max_attempts
+            ),
+        }
+    }
+
+    sink.expected_response.clear();
+    Err(Error::Timeout)
+}
+
+/// Send a command without waiting for any response.
+async fn write_cmd(sink: &CommandSink<impl TP25Writer>, command: Command) -> CommandResult {
+    sink.publisher
+        .publish_event(ControllerEvent::Transfer(Transfer::Command(
+            command.clone(),
+        )));
+    sink.device.send_cmd(command).await.inspect_err(|e| {
+        warn!("Failed to send command: {}", e);
+    })
+}
+
+async fn send_query_profile(sink: &CommandSink<impl TP25Writer>, idx: ProbeIdx) -> CommandResult {
+    send_cmd(sink, build_report_profile_cmd(idx)).await
 }
 
 async fn send_set_profile(
-    device: &impl TP25Writer,
-    transfer_tx: &Sender<Transfer>,
+    sink: &CommandSink<impl TP25Writer>,
     idx: ProbeIdx,
     threshold: AlarmThreshold,
-) -> btleplug::Result<()> {
-    send_cmd(device, transfer_tx, build_set_profile_cmd(idx, threshold)).await
+) -> CommandResult {
+    send_cmd(sink, build_set_profile_cmd(idx, threshold)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::command_request::command_channel;
+    use crate::model::device_temperature::{DeviceTemperature, InRangeDeviceTemperature};
+    use crate::model::probe::UpperLimitThreshold;
+    use crate::peripheral::command::Decoded as CommandDecoded;
+    use crate::peripheral::notification::{ExtraProbeProfileData, ProbeTemperature};
+    use assert_matches::assert_matches;
+    use bytes::Bytes;
+    use std::sync::Mutex as StdMutex;
+    use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+    /// Records commands, and acknowledges them (via `expected_response`) only once `ack_on_attempt` have been sent.
+    struct FlakyWriter {
+        sent: Arc<StdMutex<u32>>,
+        ack_on_attempt: Option<u32>,
+        expected_response: ExpectedResponseSlot,
+    }
+
+    impl TP25Writer for FlakyWriter {
+        async fn send_cmd(&self, _command: Command) -> Result<(), Error> {
+            let mut sent = self.sent.lock().unwrap();
+            *sent += 1;
+            if Some(*sent) == self.ack_on_attempt {
+                self.expected_response
+                    .resolve(&Notification::from(Bytes::from_static(&[0x27, 0x00, 0x27])));
+            }
+            Ok(())
+        }
+
+        async fn disconnect(&self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn sink(ack_on_attempt: Option<u32>) -> (CommandSink<FlakyWriter>, Arc<StdMutex<u32>>) {
+        let sent = Arc::new(StdMutex::new(0));
+        let expected_response = ExpectedResponseSlot::default();
+        let sink = CommandSink {
+            device: FlakyWriter {
+                sent: sent.clone(),
+                ack_on_attempt,
+                expected_response: expected_response.clone(),
+            },
+            publisher: Publisher::new(TP25State::default()),
+            expected_response,
+            retry_policy: RetryPolicy::default(),
+        };
+        (sink, sent)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_until_acknowledged() {
+        let (sink, sent) = sink(Some(2));
+        assert_matches!(send_alarm_ack_cmd(&sink).await, Ok(()));
+        assert_eq!(*sent.lock().unwrap(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_after_max_attempts() {
+        let (sink, sent) = sink(None);
+        assert_matches!(send_alarm_ack_cmd(&sink).await, Err(Error::Timeout));
+        assert_eq!(*sent.lock().unwrap(), RetryPolicy::default().max_attempts);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_wait_for_custom_commands() {
+        let (sink, sent) = sink(None);
+        assert_matches!(send_custom_cmd(&sink, vec![0x33, 0x00, 0x33]).await, Ok(()));
+        assert_eq!(*sent.lock().unwrap(), 1);
+    }
+
+    /// Acknowledges commands in the same way as a real thermometer, remembering the profiles it is sent.
+    #[derive(Clone)]
+    struct FakeThermometer {
+        thresholds: Arc<StdMutex<[AlarmThreshold; 4]>>,
+        notification_tx: Sender<Notification>,
+        disconnected: Arc<StdMutex<bool>>,
+        /// How many 0x30 polls have been sent, and whether they get a reply.
+        polls: Arc<StdMutex<u32>>,
+        answer_polls: bool,
+    }
+
+    struct FakeReceiver {
+        notification_rx: Receiver<Notification>,
+    }
+
+    impl TP25Receiver for FakeReceiver {
+        async fn get_notification(&mut self) -> Result<Notification, Error> {
+            self.notification_rx.recv().await.ok_or(Error::Disconnected)
+        }
+    }
+
+    impl TP25Writer for FakeThermometer {
+        async fn send_cmd(&self, command: Command) -> Result<(), Error> {
+            let decoded = match command.decoded {
+                CommandDecoded::ReportProfile(idx) => {
+                    Decoded::ReportProbeProfile(ProbeProfileData {
+                        idx,
+                        threshold: self.thresholds.lock().unwrap()[idx.as_zero_based() as usize],
+                    })
+                }
+                CommandDecoded::ReportExtraProfile(index) => {
+                    Decoded::ReportExtraProbeProfile(ExtraProbeProfileData {
+                        index,
+                        threshold: AlarmThreshold::NoneSet,
+                    })
+                }
+                CommandDecoded::SetProbeProfile(idx, threshold) => {
+                    self.thresholds.lock().unwrap()[idx.as_zero_based() as usize] = threshold;
+                    Decoded::SetProbeProfile(idx)
+                }
+                CommandDecoded::AltTempReport => Decoded::AltTemperatures(AltTemperatureData {
+                    temps: [DeviceTemperature::OutOfRange; 4],
+                }),
+                CommandDecoded::Unknown26 => Decoded::Unknown26([0x0c, 0x0c, 0x5a, 0x03, 0x0f]),
+                CommandDecoded::Unknown41 => Decoded::Unknown41([0x31, 0x11]),
+                CommandDecoded::TempReport => {
+                    *self.polls.lock().unwrap() += 1;
+                    if !self.answer_polls {
+                        return Ok(());
+                    }
+                    Decoded::Temperatures(TemperatureData {
+                        temps: [ProbeTemperature::default(); 4],
+                        temp_mode: TemperatureMode::Celsius,
+                    })
+                }
+                _ => Decoded::Startup,
+            };
+            let _ = self
+                .notification_tx
+                .send(Notification {
+                    raw: Bytes::new(),
+                    decoded: Ok(decoded),
+                })
+                .await;
+            Ok(())
+        }
+
+        async fn disconnect(&self) -> Result<(), Error> {
+            *self.disconnected.lock().unwrap() = true;
+            Ok(())
+        }
+    }
+
+    fn fake_thermometer() -> (FakeReceiver, FakeThermometer) {
+        let (notification_tx, notification_rx) = channel(10);
+        (
+            FakeReceiver { notification_rx },
+            FakeThermometer {
+                thresholds: Arc::new(StdMutex::new([AlarmThreshold::NoneSet; 4])),
+                notification_tx,
+                disconnected: Arc::default(),
+                polls: Arc::default(),
+                answer_polls: true,
+            },
+        )
+    }
+
+    /// Connect `handler` to `thermometer`, and wait until the initial sync is done and every probe's profile has been
+    /// re-armed (or not).
+    async fn connect_and_wait(
+        handler: &ConnectionHandler,
+        rx: FakeReceiver,
+        thermometer: &FakeThermometer,
+    ) -> TP25State {
+        let handler = handler.clone();
+        let thermometer = thermometer.clone();
+        let publisher = Publisher::new(TP25State::default());
+        let mut state_rx = publisher.subscribe_state();
+        tokio::spawn(async move {
+            let (_command_tx, command_rx) = command_channel(10);
+            handler
+                .handle_one_connection(
+                    rx,
+                    thermometer,
+                    &ProtectedDeviceState::default(),
+                    &publisher,
+                    Arc::new(Mutex::new(command_rx)),
+                    &CancellationToken::new(),
+                )
+                .await;
+        });
+
+        loop {
+            state_rx.changed().await.unwrap();
+            let state = state_rx.borrow_and_update().clone();
+            if state.device_info.sync_complete
+                && state
+                    .probes
+                    .iter()
+                    .all(|p| p.profile_rearm != ProfileRearm::Pending)
+            {
+                return state;
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restores_lost_profiles_on_connect() {
+        let handler = ConnectionHandler::default();
+        let profile = AlarmThreshold::UpperLimit(UpperLimitThreshold {
+            max: InRangeDeviceTemperature::new(60, 5),
+        });
+        remember_profile(&handler.remembered_profiles, Probe2, profile);
+
+        let (rx, thermometer) = fake_thermometer();
+        let state = connect_and_wait(&handler, rx, &thermometer).await;
+
+        assert_eq!(state.probes[0].profile_rearm, ProfileRearm::NotNeeded);
+        assert_eq!(state.probes[1].profile_rearm, ProfileRearm::Restored);
+        assert_eq!(state.probes[1].alarm_threshold, Some(profile));
+        assert_eq!(thermometer.thresholds.lock().unwrap()[1], profile);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rearms_kept_profiles_on_connect() {
+        let handler = ConnectionHandler::default();
+        let profile = AlarmThreshold::UpperLimit(UpperLimitThreshold {
+            max: InRangeDeviceTemperature::new(60, 5),
+        });
+        remember_profile(&handler.remembered_profiles, Probe4, profile);
+
+        let (rx, thermometer) = fake_thermometer();
+        thermometer.thresholds.lock().unwrap()[3] = profile;
+        let state = connect_and_wait(&handler, rx, &thermometer).await;
+
+        assert_eq!(state.probes[3].profile_rearm, ProfileRearm::Rearmed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn syncs_device_info_on_connect() {
+        let handler = ConnectionHandler::default();
+        let (rx, thermometer) = fake_thermometer();
+        thermometer.thresholds.lock().unwrap()[2] =
+            AlarmThreshold::UpperLimit(UpperLimitThreshold {
+                max: InRangeDeviceTemperature::new(71, 0),
+            });
+        let state = connect_and_wait(&handler, rx, &thermometer).await;
+
+        assert!(state.device_info.sync_complete);
+        assert_matches!(
+            state.device_info.unknown_26,
+            Some(SyncResponse {
+                decoded: [0x0c, 0x0c, 0x5a, 0x03, 0x0f],
+                ..
+            })
+        );
+        assert_matches!(
+            state.device_info.unknown_41,
+            Some(SyncResponse {
+                decoded: [0x31, 0x11],
+                ..
+            })
+        );
+        assert!(state.device_info.alt_temperatures.is_some());
+        assert!(state.device_info.probe_profiles.iter().all(Option::is_some));
+
+        // Every probe's profile is known, not just the ones the thermometer happened to report.
+        assert!(state.probes.iter().all(|p| p.alarm_threshold.is_some()));
+        assert_eq!(
+            state.probes[2].alarm_threshold,
+            Some(thermometer.thresholds.lock().unwrap()[2])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_fails_pending_requests_and_disconnects() {
+        let (rx, thermometer) = fake_thermometer();
+        let (command_tx, command_rx) = command_channel(10);
+        let cancel = CancellationToken::new();
+        let connection = tokio::spawn({
+            let thermometer = thermometer.clone();
+            let cancel = cancel.clone();
+            async move {
+                ConnectionHandler::default()
+                    .handle_one_connection(
+                        rx,
+                        thermometer,
+                        &ProtectedDeviceState::default(),
+                        &Publisher::new(TP25State::default()),
+                        Arc::new(Mutex::new(command_rx)),
+                        &cancel,
+                    )
+                    .await
+            }
+        });
+
+        // The fake thermometer never acknowledges alarm acks properly, so this is still waiting when shutdown begins.
+        let request =
+            tokio::spawn(async move { command_tx.request(CommandRequest::AckAlarm).await });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        cancel.cancel();
+
+        assert_matches!(request.await.unwrap(), Err(Error::Shutdown));
+        connection.await.unwrap();
+        assert!(*thermometer.disconnected.lock().unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn counts_startups_on_a_live_link_as_reboots() {
+        let (rx, thermometer) = fake_thermometer();
+        let publisher = Publisher::new(TP25State::default());
+        let state_rx = publisher.subscribe_state();
+        let (_command_tx, command_rx) = command_channel(10);
+        tokio::spawn({
+            let thermometer = thermometer.clone();
+            async move {
+                ConnectionHandler::default()
+                    .handle_one_connection(
+                        rx,
+                        thermometer,
+                        &ProtectedDeviceState::default(),
+                        &publisher,
+                        Arc::new(Mutex::new(command_rx)),
+                        &CancellationToken::new(),
+                    )
+                    .await
+            }
+        });
+
+        // Answering the startup command doesn't count.
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(state_rx.borrow().sessions, 1);
+        assert_eq!(state_rx.borrow().reboots, 0);
+
+        thermometer
+            .notification_tx
+            .send(Notification {
+                raw: Bytes::new(),
+                decoded: Ok(Decoded::Startup),
+            })
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(state_rx.borrow().sessions, 1);
+        assert_eq!(state_rx.borrow().reboots, 1);
+    }
+
+    /// Run a connection to `thermometer` until it ends, or until `limit` has passed.
+    async fn run_connection(
+        rx: FakeReceiver,
+        thermometer: &FakeThermometer,
+        limit: Duration,
+    ) -> Option<Error> {
+        let (_command_tx, command_rx) = command_channel(10);
+        timeout(
+            limit,
+            ConnectionHandler::default().handle_one_connection(
+                rx,
+                thermometer.clone(),
+                &ProtectedDeviceState::default(),
+                &Publisher::new(TP25State::default()),
+                Arc::new(Mutex::new(command_rx)),
+                &CancellationToken::new(),
+            ),
+        )
+        .await
+        .ok()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn answered_polls_keep_quiet_link_up() {
+        let (rx, thermometer) = fake_thermometer();
+        assert_matches!(
+            run_connection(rx, &thermometer, Duration::from_secs(60)).await,
+            None
+        );
+        assert!(*thermometer.polls.lock().unwrap() > 10);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn link_is_dead_after_missed_polls() {
+        let (rx, thermometer) = fake_thermometer();
+        let thermometer = FakeThermometer {
+            answer_polls: false,
+            ..thermometer
+        };
+        let liveness = Liveness::default();
+        let started = Instant::now();
+        assert_matches!(
+            run_connection(rx, &thermometer, Duration::from_secs(60)).await,
+            Some(Error::Timeout)
+        );
+        assert_eq!(
+            *thermometer.polls.lock().unwrap(),
+            liveness.max_missed_polls
+        );
+        // Setting up the connection doesn't take any time, as the fake thermometer responds immediately.
+        assert_eq!(
+            started.elapsed(),
+            liveness.notification_interval + liveness.poll_timeout * liveness.max_missed_polls
+        );
+        assert!(*thermometer.disconnected.lock().unwrap());
+    }
 }
@@ -1,75 +1,302 @@
-use crate::controller::command_request::CommandRequest;
+use crate::controller::command_request::{command_channel, CommandReceiver};
 use crate::controller::connection_handler::ConnectionHandler;
+use crate::controller::handle::{ControllerHandle, Publisher};
 use crate::dev_finder::DeviceFinder;
 use crate::model::device::TP25State;
-use crate::peripheral::transfer::Transfer;
-use log::{debug, info, trace};
+use crate::Error;
+use log::{debug, info, trace, warn};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher, RandomState};
 use std::sync::Arc;
-use tokio::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tokio::time::{sleep, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// How many command requests can be queued before senders have to wait.
+const COMMAND_BUFFER: usize = 10;
 
 /// Manages connecting and maintaining communication with the device.
 ///
 /// Once communication is established, actual communication with device is done by a `ConnectionHandler` object.
-pub struct ConnectionManager {}
+pub struct ConnectionManager {
+    finder: DeviceFinder,
+    handler: ConnectionHandler,
+    publisher: Publisher,
+    protected_device_state: ProtectedDeviceState,
+    command_request_rx: CommandReceiver,
+    shutdown: CancellationToken,
+    reconnect_policy: ReconnectPolicy,
+}
 
 pub type ProtectedDeviceState = Arc<Mutex<TP25State>>;
 
 impl ConnectionManager {
-    /// Find and communicate with a TP25 device. Do this until the task that called `run` is aborted.
+    /// Create a controller that will connect to a TP25 using `finder`, and deal with it using `handler`. Nothing
+    /// happens until `run` is called, but the returned handle can be used to subscribe to state and events, and to
+    /// send commands, straight away.
+    pub fn new(finder: DeviceFinder, handler: ConnectionHandler) -> (Self, ControllerHandle) {
+        let publisher = Publisher::new(TP25State::default());
+        let protected_device_state = ProtectedDeviceState::default();
+        let (command_tx, command_request_rx) = command_channel(COMMAND_BUFFER);
+        let shutdown = CancellationToken::new();
+        let handle = ControllerHandle::new(
+            publisher.clone(),
+            command_tx,
+            handler.cook_targets(&protected_device_state, &publisher),
+            shutdown.clone(),
+        );
+
+        (
+            Self {
+                finder,
+                handler,
+                publisher,
+                protected_device_state,
+                command_request_rx,
+                shutdown,
+                reconnect_policy: ReconnectPolicy::default(),
+            },
+            handle,
+        )
+    }
+
+    /// Use `policy` to decide how long to wait between attempts to connect, and when to give up.
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = policy;
+        self
+    }
+
+    /// Find and communicate with a TP25 device. Do this until `ControllerHandle::shutdown` is called (or the task
+    /// that called `run` is aborted), or until the reconnect policy gives up.
     ///
     /// This function is essentially a loop that connects to a TP25 using `finder` and then offloads actually dealing
-    /// with it to `handler`. Then when `handler` returns, it goes back to looking for a device with `finder`.
-    pub async fn run(
-        finder: DeviceFinder,
-        handler: ConnectionHandler,
-        state_update_tx: Sender<TP25State>,
-        transfer_tx: Sender<Transfer>,
-        command_request_rx: Receiver<CommandRequest>,
-    ) {
+    /// with it to `handler`. Then when `handler` returns, it waits as long as the reconnect policy says, and goes back
+    /// to looking for a device with `finder`.
+    ///
+    /// Returns `Ok` after a shutdown, or the last error if the reconnect policy gave up.
+    pub async fn run(self) -> Result<(), Error> {
         info!("Starting Controller");
-        let device_state = TP25State {
-            connected: false,
-            ..TP25State::default()
-        };
-        let protected_device_state = Arc::new(Mutex::new(device_state));
-        let saved_cmd_rqst_rx = Arc::new(Mutex::new(command_request_rx));
+        let protected_device_state = self.protected_device_state;
+        let saved_cmd_rqst_rx = Arc::new(Mutex::new(self.command_request_rx));
 
-        loop {
+        // Attempts in a row that haven't resulted in a working connection.
+        let mut failures = 0;
+        let mut last_error: Option<Error> = None;
+
+        let result = loop {
             trace!("Controller - start of loop");
-            if state_update_tx
-                .send(protected_device_state.lock().await.clone())
-                .await
-                .is_err()
-            {
-                debug!("Controller stopping as unable to send state update");
-                return;
+            self.publisher
+                .publish_state(protected_device_state.lock().await.clone());
+
+            if failures > 0 {
+                if let (Some(max_attempts), Some(e)) =
+                    (self.reconnect_policy.max_attempts, &last_error)
+                {
+                    if failures >= max_attempts {
+                        warn!("Giving up after {} attempts to connect: {}", failures, e);
+                        let device_state = &mut protected_device_state.lock().await;
+                        device_state.link.gave_up = true;
+                        self.publisher.publish_state(device_state.clone());
+                        break Err(e.clone());
+                    }
+                }
+
+                let delay = self.reconnect_policy.jittered_delay(failures);
+                debug!("Reconnecting in {:?}", delay);
+                let mut command_request_rx = saved_cmd_rqst_rx.lock().await;
+                let wait = async {
+                    tokio::select! {
+                        _ = sleep(delay) => Ok(()),
+                        _ = self.shutdown.cancelled() => Err(Error::Shutdown),
+                    }
+                };
+                if fail_requests_while(wait, &mut command_request_rx)
+                    .await
+                    .is_err()
+                {
+                    break Ok(());
+                }
             }
 
-            let Ok((peripheral_rx, peripheral_tx)) = finder.get_device().await else {
-                // `get_device` only errors for unrecoverable errors such as no Bluetooth adapters.
-                // If it merely can't find a decice, it keeps waiting. Therefore an error return
-                // means there's no point continuing.
-                debug!("Device search failed, so controller exiting");
-                return;
-            };
             {
-                protected_device_state.lock().await.connected = true;
+                let device_state = &mut protected_device_state.lock().await;
+                device_state.link.connect_attempts += 1;
+                self.publisher.publish_state(device_state.clone());
             }
+            let found = {
+                let mut command_request_rx = saved_cmd_rqst_rx.lock().await;
+                fail_requests_while(
+                    self.finder.get_device(&self.shutdown),
+                    &mut command_request_rx,
+                )
+                .await
+            };
+            let (peripheral_rx, peripheral_tx) = match found {
+                Ok(found) => found,
+                Err(Error::Shutdown) => break Ok(()),
+                Err(e) => {
+                    // `get_device` only errors for serious problems such as no Bluetooth adapters. If it merely
+                    // can't find a device, it keeps waiting. The problem may be temporary though (e.g. an adapter
+                    // being plugged back in), so try again later.
+                    warn!("Device search failed: {}", e);
+                    failures += 1;
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            let sessions = {
+                let device_state = &mut protected_device_state.lock().await;
+                device_state.connected = true;
+                device_state.link.connected_at = Some(Instant::now());
+                device_state.sessions
+            };
 
-            handler
+            let reason = self
+                .handler
                 .handle_one_connection(
                     peripheral_rx,
                     peripheral_tx,
                     &protected_device_state,
-                    &state_update_tx,
-                    &transfer_tx,
+                    &self.publisher,
                     saved_cmd_rqst_rx.clone(),
+                    &self.shutdown,
                 )
                 .await;
-            {
-                protected_device_state.lock().await.connected = false;
+            let started_up = {
+                let device_state = &mut protected_device_state.lock().await;
+                device_state.connected = false;
+                device_state.link.connected_at = None;
+                device_state.link.last_disconnect_reason = Some(reason.clone());
+                device_state.sessions > sessions
+            };
+            if self.shutdown.is_cancelled() {
+                break Ok(());
             }
+
+            // A connection that got as far as starting up counts as a success, so the next attempt starts afresh.
+            info!("Connection ended: {}", reason);
+            failures = if started_up { 1 } else { failures + 1 };
+            last_error = Some(reason);
+        };
+
+        // Fail anything still queued, and stop anything else being queued.
+        let mut command_request_rx = saved_cmd_rqst_rx.lock().await;
+        command_request_rx.close();
+        while let Some(r) = command_request_rx.recv().await {
+            r.respond(Err(Error::Shutdown));
+        }
+        self.publisher
+            .publish_state(protected_device_state.lock().await.clone());
+        info!("Controller stopped");
+        result
+    }
+}
+
+/// Wait for `f`, failing any requests that arrive meanwhile. Requests can't be carried out while there's no device, so
+/// this is better than leaving callers waiting until one turns up.
+async fn fail_requests_while<T>(
+    f: impl Future<Output = T>,
+    command_request_rx: &mut CommandReceiver,
+) -> T {
+    tokio::pin!(f);
+    loop {
+        tokio::select! {
+            t = &mut f => return t,
+            Some(r) = command_request_rx.recv() => r.respond(Err(Error::Disconnected)),
+        }
+    }
+}
+
+/// How long to wait between attempts to connect to the thermometer, and when to give up.
+///
+/// The wait starts at `initial_delay`, and is multiplied by `multiplier` after each failed attempt, up to `max_delay`.
+#[derive(Clone, Copy, Debug)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// Each wait is randomly lengthened or shortened by up to this fraction of itself, so that several controllers
+    /// don't all retry at once.
+    pub jitter: f64,
+    /// Give up after this many attempts in a row fail. If `None`, never give up.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.1,
+            max_attempts: None,
         }
     }
 }
+
+impl ReconnectPolicy {
+    /// How long to wait after `failures` attempts in a row have failed, before jitter is applied. If the `multiplier`
+    /// doesn't give a sensible wait (e.g. it's negative), this is `max_delay`.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        Duration::try_from_secs_f64(delay.min(self.max_delay.as_secs_f64()))
+            .unwrap_or(self.max_delay)
+    }
+
+    fn jittered_delay(&self, failures: u32) -> Duration {
+        // Somewhere between -1 and 1.
+        let random =
+            RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64 * 2.0 - 1.0;
+        let factor = (1.0 + random * self.jitter).max(0.0);
+        Duration::try_from_secs_f64(self.delay(failures).as_secs_f64() * factor)
+            .unwrap_or(self.max_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_backs_off_up_to_max() {
+        let policy = ReconnectPolicy::default();
+        let delays: Vec<_> = [1, 2, 3, 6, 7, 100]
+            .into_iter()
+            .map(|failures| policy.delay(failures).as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 32, 60, 60]);
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let policy = ReconnectPolicy {
+            jitter: 0.5,
+            ..ReconnectPolicy::default()
+        };
+        for _ in 0..100 {
+            let delay = policy.jittered_delay(3);
+            assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(6));
+        }
+    }
+
+    #[test]
+    fn nonsensical_policies_do_not_panic() {
+        let max_delay = Duration::from_secs(60);
+        for multiplier in [-2.0, f64::NAN, f64::INFINITY] {
+            let policy = ReconnectPolicy {
+                multiplier,
+                jitter: f64::MAX,
+                ..ReconnectPolicy::default()
+            };
+            assert!(policy.delay(2) <= max_delay);
+            policy.jittered_delay(2);
+        }
+        let policy = ReconnectPolicy {
+            multiplier: -2.0,
+            ..ReconnectPolicy::default()
+        };
+        assert_eq!(policy.delay(2), max_delay);
+    }
+}
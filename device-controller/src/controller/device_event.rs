@@ -0,0 +1,170 @@
+use crate::model::device::{TP25State, TemperatureMode};
+use crate::model::device_temperature::DeviceTemperature;
+use crate::model::probe::{AlarmState, AlarmThreshold, ProbeIdx};
+use std::time::SystemTime;
+
+/// Something that happened to the thermometer, worked out by comparing successive device states.
+#[derive(Clone, Debug)]
+pub struct DeviceEvent {
+    /// When the change was seen by the controller.
+    pub timestamp: SystemTime,
+    pub kind: DeviceEventKind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeviceEventKind {
+    Connected,
+    Disconnected,
+    /// A probe started reporting a temperature.
+    ProbeInserted(ProbeIdx),
+    /// A probe stopped reporting a temperature.
+    ProbeRemoved(ProbeIdx),
+    AlarmRaised(ProbeIdx),
+    AlarmCleared(ProbeIdx),
+    /// The thermometer reported a different alarm profile for a probe.
+    ThresholdChanged(ProbeIdx, AlarmThreshold),
+    TemperatureModeChanged(TemperatureMode),
+    /// The thermometer went through its startup handshake again after a previous connection. It may have been switched
+    /// off and on, but it doesn't say so - the connection may just have dropped and been re-established.
+    Reconnected,
+    /// The thermometer announced that it started up without the link dropping, so it must have restarted and
+    /// forgotten which alarms were armed.
+    DeviceRebooted,
+}
+
+/// The events that explain how `old` became `new`.
+pub fn events_between(old: &TP25State, new: &TP25State) -> Vec<DeviceEventKind> {
+    let mut events = Vec::new();
+
+    match (old.connected, new.connected) {
+        (false, true) => events.push(DeviceEventKind::Connected),
+        (true, false) => events.push(DeviceEventKind::Disconnected),
+        _ => {}
+    }
+
+    if new.sessions > old.sessions && old.sessions > 0 {
+        events.push(DeviceEventKind::Reconnected);
+    }
+    if new.reboots > old.reboots {
+        events.push(DeviceEventKind::DeviceRebooted);
+    }
+
+    if new.temperature_mode != old.temperature_mode {
+        if let Some(mode) = new.temperature_mode {
+            events.push(DeviceEventKind::TemperatureModeChanged(mode));
+        }
+    }
+
+    for (i, (old_probe, new_probe)) in old.probes.iter().zip(&new.probes).enumerate() {
+        let idx = ProbeIdx::from_zero_based(i as u8);
+
+        match (old_probe.temperature, new_probe.temperature) {
+            (DeviceTemperature::OutOfRange, DeviceTemperature::InRange(_)) => {
+                events.push(DeviceEventKind::ProbeInserted(idx))
+            }
+            (DeviceTemperature::InRange(_), DeviceTemperature::OutOfRange) => {
+                events.push(DeviceEventKind::ProbeRemoved(idx))
+            }
+            _ => {}
+        }
+
+        match (old_probe.alarm, new_probe.alarm) {
+            (AlarmState::Alarm, AlarmState::Alarm) => {}
+            (_, AlarmState::Alarm) => events.push(DeviceEventKind::AlarmRaised(idx)),
+            (AlarmState::Alarm, AlarmState::NoAlarm) => {
+                events.push(DeviceEventKind::AlarmCleared(idx))
+            }
+            _ => {}
+        }
+
+        if new_probe.alarm_threshold != old_probe.alarm_threshold {
+            if let Some(threshold) = new_probe.alarm_threshold {
+                events.push(DeviceEventKind::ThresholdChanged(idx, threshold));
+            }
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::device_temperature::InRangeDeviceTemperature;
+
+    fn connected() -> TP25State {
+        TP25State {
+            connected: true,
+            sessions: 1,
+            ..TP25State::default()
+        }
+    }
+
+    #[test]
+    fn no_events_without_changes() {
+        assert_eq!(events_between(&connected(), &connected()), vec![]);
+    }
+
+    #[test]
+    fn connection_changes() {
+        let disconnected = TP25State::default();
+        assert_eq!(
+            events_between(&disconnected, &connected()),
+            vec![DeviceEventKind::Connected]
+        );
+        assert_eq!(
+            events_between(&connected(), &disconnected),
+            vec![DeviceEventKind::Disconnected]
+        );
+
+        // Only a second startup handshake counts as reconnecting.
+        let reconnected = TP25State {
+            sessions: 2,
+            ..connected()
+        };
+        assert_eq!(
+            events_between(&connected(), &reconnected),
+            vec![DeviceEventKind::Reconnected]
+        );
+
+        let rebooted = TP25State {
+            reboots: 1,
+            ..connected()
+        };
+        assert_eq!(
+            events_between(&connected(), &rebooted),
+            vec![DeviceEventKind::DeviceRebooted]
+        );
+    }
+
+    #[test]
+    fn probe_changes() {
+        let mut old = connected();
+        old.probes[1].alarm = AlarmState::NoAlarm;
+        let mut new = old.clone();
+        new.probes[0].temperature =
+            DeviceTemperature::InRange(InRangeDeviceTemperature::new(20, 0));
+        new.probes[1].alarm = AlarmState::Alarm;
+        new.probes[2].alarm_threshold = Some(AlarmThreshold::NoneSet);
+        new.temperature_mode = Some(TemperatureMode::Fahrenheit);
+
+        assert_eq!(
+            events_between(&old, &new),
+            vec![
+                DeviceEventKind::TemperatureModeChanged(TemperatureMode::Fahrenheit),
+                DeviceEventKind::ProbeInserted(ProbeIdx::Probe1),
+                DeviceEventKind::AlarmRaised(ProbeIdx::Probe2),
+                DeviceEventKind::ThresholdChanged(ProbeIdx::Probe3, AlarmThreshold::NoneSet),
+            ]
+        );
+
+        // Going back to an unknown threshold or temperature mode isn't an event.
+        assert_eq!(
+            events_between(&new, &old),
+            vec![
+                DeviceEventKind::ProbeRemoved(ProbeIdx::Probe1),
+                DeviceEventKind::AlarmCleared(ProbeIdx::Probe2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use crate::controller::command_request::CommandRequest;
+use crate::controller::connection_handler::ConnectionHandler;
+use crate::controller::connection_mgr::ConnectionManager;
+use crate::controller::handle::ControllerHandle;
+use crate::dev_finder::DeviceFinder;
+use crate::model::device::TP25State;
+use crate::Error;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Reasons the `DeviceManager` couldn't do what was asked.
+#[derive(Clone, Debug)]
+pub enum DeviceManagerError {
+    /// A device with this address or alias is already being managed.
+    AlreadyKnown(String),
+    /// No device with this address or alias is being managed.
+    UnknownDevice(String),
+    /// The device was found, but the command did not take effect.
+    Command(Error),
+}
+
+impl Display for DeviceManagerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceManagerError::AlreadyKnown(key) => write!(f, "device {key} is already known"),
+            DeviceManagerError::UnknownDevice(key) => write!(f, "no device called {key}"),
+            DeviceManagerError::Command(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for DeviceManagerError {}
+
+/// A snapshot of one of the devices a `DeviceManager` is looking after.
+#[derive(Clone, Debug)]
+pub struct DeviceSummary {
+    pub address: String,
+    pub alias: String,
+    pub state: TP25State,
+}
+
+struct ManagedDevice {
+    address: String,
+    handle: ControllerHandle,
+    task: JoinHandle<Result<(), Error>>,
+}
+
+/// Looks after several thermometers at once.
+///
+/// Each device is identified by its BLE address, and can be given an alias (e.g. "smoker") to make it easier to refer
+/// to. Anywhere a device key is taken, either the address or the alias will do. Every device gets its own
+/// `ConnectionManager`, so each has its own connection loop, state and command channel, and one device dropping out
+/// doesn't affect the others.
+pub struct DeviceManager {
+    finder: DeviceFinder,
+    handler: ConnectionHandler,
+    devices: Mutex<BTreeMap<String, ManagedDevice>>,
+}
+
+impl DeviceManager {
+    /// Create a manager whose devices are found using copies of `finder`, narrowed down to each device's address, and
+    /// dealt with using copies of `handler`'s settings. Each device has its own
+    /// [backend](crate::dev_finder::Backend::for_another_device) and remembers its own alarm profiles, rather than
+    /// sharing `finder`'s and `handler`'s.
+    pub fn new(finder: DeviceFinder, handler: ConnectionHandler) -> Self {
+        Self {
+            finder,
+            handler,
+            devices: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Start looking after the thermometer at `address`, known as `alias` if given (otherwise by its address).
+    ///
+    /// The device's connection loop is spawned straight away, so this must be called from within a tokio runtime.
+    pub fn add_device(
+        &self,
+        address: &str,
+        alias: Option<&str>,
+    ) -> Result<ControllerHandle, DeviceManagerError> {
+        let alias = alias.unwrap_or(address);
+        let mut devices = self.devices.lock().unwrap();
+        for key in [address, alias] {
+            if find_key(&devices, key).is_some() {
+                return Err(DeviceManagerError::AlreadyKnown(key.to_string()));
+            }
+        }
+
+        let finder = DeviceFinder {
+            backend: self.finder.backend.for_another_device(),
+            addresses: vec![address.to_string()],
+            ..self.finder.clone()
+        };
+        let (manager, handle) = ConnectionManager::new(finder, self.handler.for_another_device());
+        info!("Managing device {alias} ({address})");
+        devices.insert(
+            alias.to_string(),
+            ManagedDevice {
+                address: address.to_string(),
+                handle: handle.clone(),
+                task: tokio::spawn(manager.run()),
+            },
+        );
+
+        Ok(handle)
+    }
+
+    /// Stop looking after a device, disconnecting from it. Returns false if there was no such device.
+    pub fn remove_device(&self, key: &str) -> bool {
+        let mut devices = self.devices.lock().unwrap();
+        let Some(alias) = find_key(&devices, key) else {
+            return false;
+        };
+        let device = devices.remove(&alias).unwrap();
+        info!("No longer managing device {alias} ({})", device.address);
+        device.handle.shutdown();
+        true
+    }
+
+    /// Shut down every device's controller, and wait until they have all disconnected.
+    pub async fn shutdown(&self) {
+        let devices = std::mem::take(&mut *self.devices.lock().unwrap());
+        for device in devices.values() {
+            device.handle.shutdown();
+        }
+        for (alias, device) in devices {
+            if device.task.await.is_err() {
+                warn!("Controller for device {alias} did not shut down cleanly");
+            }
+        }
+    }
+
+    /// All the devices being looked after, ordered by alias.
+    pub fn devices(&self) -> Vec<DeviceSummary> {
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(alias, device)| DeviceSummary {
+                address: device.address.clone(),
+                alias: alias.clone(),
+                state: device.handle.state(),
+            })
+            .collect()
+    }
+
+    /// Get the handle for a single device, to subscribe to it or send it commands.
+    pub fn device(&self, key: &str) -> Option<ControllerHandle> {
+        let devices = self.devices.lock().unwrap();
+        find_key(&devices, key).map(|alias| devices[&alias].handle.clone())
+    }
+
+    /// Send a command to a single device, and wait until it has been acknowledged.
+    pub async fn request(
+        &self,
+        key: &str,
+        request: CommandRequest,
+    ) -> Result<(), DeviceManagerError> {
+        let handle = self
+            .device(key)
+            .ok_or_else(|| DeviceManagerError::UnknownDevice(key.to_string()))?;
+        handle
+            .commands()
+            .request(request)
+            .await
+            .map_err(DeviceManagerError::Command)
+    }
+}
+
+impl Drop for DeviceManager {
+    fn drop(&mut self) {
+        for device in self.devices.get_mut().unwrap().values() {
+            device.task.abort();
+        }
+    }
+}
+
+/// Find the alias of the device with `key` as its alias or address.
+fn find_key(devices: &BTreeMap<String, ManagedDevice>, key: &str) -> Option<String> {
+    if devices.contains_key(key) {
+        return Some(key.to_string());
+    }
+    devices
+        .iter()
+        .find(|(_, device)| device.address.eq_ignore_ascii_case(key))
+        .map(|(alias, _)| alias.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dev_finder::Backend;
+    use assert_matches::assert_matches;
+    use std::time::Duration;
+
+    const SMOKER: &str = "AA:BB:CC:DD:EE:01";
+    const OVEN: &str = "AA:BB:CC:DD:EE:02";
+
+    fn simulated_manager() -> DeviceManager {
+        DeviceManager::new(
+            DeviceFinder::for_backend(Backend::from_spec("simulator").unwrap()),
+            ConnectionHandler::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn devices_can_be_found_by_alias_or_address() {
+        let manager = simulated_manager();
+        manager.add_device(SMOKER, Some("smoker")).unwrap();
+        manager.add_device(OVEN, None).unwrap();
+
+        let devices = manager.devices();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(
+            (devices[0].alias.as_str(), devices[0].address.as_str()),
+            (OVEN, OVEN)
+        );
+        assert_eq!(
+            (devices[1].alias.as_str(), devices[1].address.as_str()),
+            ("smoker", SMOKER)
+        );
+
+        assert!(manager.device("smoker").is_some());
+        assert!(manager.device(&SMOKER.to_lowercase()).is_some());
+        assert!(manager.device(OVEN).is_some());
+        assert!(manager.device("grill").is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn devices_are_connected_side_by_side() {
+        let manager = simulated_manager();
+        let smoker = manager.add_device(SMOKER, Some("smoker")).unwrap();
+        let oven = manager.add_device(OVEN, Some("oven")).unwrap();
+
+        for _ in 0..10 {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            for device in [&smoker, &oven] {
+                let state = device.state();
+                assert!(state.connected);
+                assert_eq!(state.sessions, 1);
+                assert!(
+                    state.link.time_since_last_notification().unwrap() < Duration::from_secs(2)
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicate_devices_are_rejected() {
+        let manager = simulated_manager();
+        manager.add_device(SMOKER, Some("smoker")).unwrap();
+
+        assert_matches!(
+            manager.add_device(SMOKER, Some("other")).err(),
+            Some(DeviceManagerError::AlreadyKnown(key)) if key == SMOKER
+        );
+        assert_matches!(
+            manager.add_device(OVEN, Some("smoker")).err(),
+            Some(DeviceManagerError::AlreadyKnown(key)) if key == "smoker"
+        );
+        assert_eq!(manager.devices().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn removed_devices_can_not_be_commanded() {
+        let manager = simulated_manager();
+        manager.add_device(SMOKER, Some("smoker")).unwrap();
+
+        assert!(manager.remove_device(SMOKER));
+        assert!(!manager.remove_device("smoker"));
+        assert!(manager.devices().is_empty());
+        assert_matches!(
+            manager.request("smoker", CommandRequest::AckAlarm).await,
+            Err(DeviceManagerError::UnknownDevice(key)) if key == "smoker"
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_every_device() {
+        let manager = simulated_manager();
+        let smoker = manager.add_device(SMOKER, Some("smoker")).unwrap();
+        let oven = manager.add_device(OVEN, Some("oven")).unwrap();
+
+        manager.shutdown().await;
+        assert!(smoker.is_shut_down() && oven.is_shut_down());
+        assert!(manager.devices().is_empty());
+        assert_matches!(
+            smoker.commands().request(CommandRequest::AckAlarm).await,
+            Err(Error::Shutdown)
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use crate::model::probe::ProbeIdx;
+use crate::peripheral::command::Decoded as CommandDecoded;
+use crate::peripheral::notification::{Decoded, Notification};
+use crate::Error;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// The response that acknowledges a particular command.
+#[derive(Clone, Copy, Debug)]
+pub enum ResponseKind {
+    Startup,
+    SetTempMode,
+    SetProbeProfile(ProbeIdx),
+    ReportProbeProfile(ProbeIdx),
+    ReportExtraProbeProfile(u8),
+    AlarmAck,
+    AltTemperatures,
+    Unknown26,
+    Unknown41,
+}
+
+impl ResponseKind {
+    /// The response expected for `command`, or `None` if we can't know what to expect.
+    pub fn for_command(command: &CommandDecoded) -> Option<ResponseKind> {
+        match command {
+            CommandDecoded::Startup => Some(ResponseKind::Startup),
+            CommandDecoded::SetTempMode(_) => Some(ResponseKind::SetTempMode),
+            CommandDecoded::ReportProfile(idx) => Some(ResponseKind::ReportProbeProfile(*idx)),
+            CommandDecoded::ReportExtraProfile(index) => {
+                Some(ResponseKind::ReportExtraProbeProfile(*index))
+            }
+            CommandDecoded::SetProbeProfile(idx, _) => Some(ResponseKind::SetProbeProfile(*idx)),
+            CommandDecoded::AlarmAck => Some(ResponseKind::AlarmAck),
+            CommandDecoded::AltTempReport => Some(ResponseKind::AltTemperatures),
+            CommandDecoded::Unknown26 => Some(ResponseKind::Unknown26),
+            CommandDecoded::Unknown41 => Some(ResponseKind::Unknown41),
+            // The reply can't be told apart from the reports the thermometer pushes anyway.
+            CommandDecoded::TempReport => None,
+            CommandDecoded::Custom(_) => None,
+        }
+    }
+
+    /// The first byte of a response of this kind.
+    fn type_byte(&self) -> u8 {
+        match self {
+            ResponseKind::Startup => 0x01,
+            ResponseKind::SetTempMode => 0x20,
+            ResponseKind::SetProbeProfile(_) => 0x23,
+            ResponseKind::ReportProbeProfile(_) | ResponseKind::ReportExtraProbeProfile(_) => 0x24,
+            ResponseKind::AltTemperatures => 0x25,
+            ResponseKind::Unknown26 => 0x26,
+            ResponseKind::AlarmAck => 0x27,
+            ResponseKind::Unknown41 => 0x41,
+        }
+    }
+
+    fn matches(&self, decoded: &Decoded) -> bool {
+        match (self, decoded) {
+            (ResponseKind::Startup, Decoded::Startup) => true,
+            (ResponseKind::SetTempMode, Decoded::SetTempMode) => true,
+            (ResponseKind::SetProbeProfile(a), Decoded::SetProbeProfile(b)) => a == b,
+            (ResponseKind::ReportProbeProfile(a), Decoded::ReportProbeProfile(b)) => *a == b.idx,
+            (ResponseKind::ReportExtraProbeProfile(a), Decoded::ReportExtraProbeProfile(b)) => {
+                *a == b.index
+            }
+            (ResponseKind::AlarmAck, Decoded::AlarmAck) => true,
+            (ResponseKind::AltTemperatures, Decoded::AltTemperatures(_)) => true,
+            (ResponseKind::Unknown26, Decoded::Unknown26(_)) => true,
+            (ResponseKind::Unknown41, Decoded::Unknown41(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The response that acknowledged a command, or why there wasn't one.
+pub type ResponseResult = Result<Notification, Error>;
+
+struct ExpectedResponse {
+    kind: ResponseKind,
+    tx: oneshot::Sender<ResponseResult>,
+}
+
+/// Shared between the task sending commands and the task receiving notifications, so that the sender can find out
+/// when its command has been acknowledged.
+///
+/// Commands are sent one at a time, so only a single response is ever waited for.
+#[derive(Clone, Default)]
+pub struct ExpectedResponseSlot {
+    inner: Arc<Mutex<Option<ExpectedResponse>>>,
+}
+
+impl ExpectedResponseSlot {
+    /// Start waiting for a response of type `kind`. The returned receiver completes when `resolve` sees a matching
+    /// response (or an error response).
+    pub fn expect(&self, kind: ResponseKind) -> oneshot::Receiver<ResponseResult> {
+        let (tx, rx) = oneshot::channel();
+        *self.inner.lock().unwrap() = Some(ExpectedResponse { kind, tx });
+        rx
+    }
+
+    /// Stop waiting for a response, for example because it timed out.
+    pub fn clear(&self) {
+        *self.inner.lock().unwrap() = None;
+    }
+
+    /// Check whether `notification` is the response being waited for, and if so, complete the wait.
+    pub fn resolve(&self, notification: &Notification) {
+        let mut inner = self.inner.lock().unwrap();
+        let result = match (&*inner, &notification.decoded) {
+            (Some(_), Ok(Decoded::Error)) => Err(Error::DeviceError),
+            (Some(expected), Ok(decoded)) if expected.kind.matches(decoded) => {
+                Ok(notification.clone())
+            }
+            // The right type of response, but garbled.
+            (Some(expected), Err(e))
+                if notification.raw.first() == Some(&expected.kind.type_byte()) =>
+            {
+                Err(Error::Decode(*e))
+            }
+            _ => return,
+        };
+
+        if let Some(expected) = inner.take() {
+            // If the sender has stopped waiting, there's nobody to tell.
+            let _ = expected.tx.send(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripheral::notification::DecodeError;
+    use assert_matches::assert_matches;
+    use bytes::Bytes;
+
+    fn notification(raw: &'static [u8]) -> Notification {
+        Notification::from(Bytes::from_static(raw))
+    }
+
+    #[test]
+    fn resolves_matching_response() {
+        let slot = ExpectedResponseSlot::default();
+        let mut rx = slot.expect(ResponseKind::SetProbeProfile(ProbeIdx::Probe4));
+
+        // Unrelated notifications, including acknowledgements for other probes, are ignored.
+        slot.resolve(&notification(&[0x20, 0x00, 0x20]));
+        slot.resolve(&notification(&[0x23, 0x02, 0x01, 0x00, 0x26]));
+        assert_matches!(rx.try_recv(), Err(oneshot::error::TryRecvError::Empty));
+
+        slot.resolve(&notification(&[0x23, 0x02, 0x04, 0xfa, 0x23]));
+        assert_matches!(rx.try_recv(), Ok(Ok(n)) if n.raw[0] == 0x23);
+    }
+
+    #[test]
+    fn resolves_error_response() {
+        let slot = ExpectedResponseSlot::default();
+        let mut rx = slot.expect(ResponseKind::AlarmAck);
+
+        slot.resolve(&notification(&[0xe0, 0x02, 0x30, 0x04, 0x16]));
+        assert_matches!(rx.try_recv(), Ok(Err(Error::DeviceError)));
+    }
+
+    #[test]
+    fn resolves_garbled_response() {
+        let slot = ExpectedResponseSlot::default();
+        let mut rx = slot.expect(ResponseKind::AlarmAck);
+
+        // Garbled notifications of other types are ignored.
+        slot.resolve(&notification(&[0x20, 0x00, 0x21]));
+        assert_matches!(rx.try_recv(), Err(oneshot::error::TryRecvError::Empty));
+
+        slot.resolve(&notification(&[0x27, 0x00, 0x28]));
+        assert_matches!(
+            rx.try_recv(),
+            Ok(Err(Error::Decode(DecodeError::BadChecksum { .. })))
+        );
+    }
+
+    #[test]
+    fn ignores_responses_after_clear() {
+        let slot = ExpectedResponseSlot::default();
+        let mut rx = slot.expect(ResponseKind::AlarmAck);
+        slot.clear();
+
+        slot.resolve(&notification(&[0x27, 0x00, 0x27]));
+        assert_matches!(rx.try_recv(), Err(oneshot::error::TryRecvError::Closed));
+    }
+}
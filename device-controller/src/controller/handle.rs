@@ -0,0 +1,330 @@
+use crate::controller::command_request::CommandSender;
+use crate::controller::connection_handler::CookTargets;
+use crate::controller::device_event::{events_between, DeviceEvent};
+use crate::controller::host_alarm::HostAlarmEvent;
+use crate::model::device::TP25State;
+use crate::model::device_temperature::InRangeDeviceTemperature;
+use crate::model::probe::ProbeIdx;
+use crate::peripheral::transfer::Transfer;
+use log::warn;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio_util::sync::CancellationToken;
+
+/// How many events can be buffered for each subscriber before the oldest are dropped. (Rounded up to a power of two
+/// by `broadcast` anyway.)
+const EVENT_CAPACITY: usize = 128;
+
+/// Something that happened in the controller, sent to every subscriber of `ControllerHandle::subscribe_events`.
+#[derive(Clone, Debug)]
+pub enum ControllerEvent {
+    /// A command was sent to the thermometer, or a notification received from it.
+    Transfer(Transfer),
+    /// The device state changed in a way that's worth reacting to.
+    Device(DeviceEvent),
+    /// One of the alarms run by `HostAlarms` changed.
+    HostAlarm(HostAlarmEvent),
+}
+
+/// Fans out state and events from the controller to any number of subscribers.
+///
+/// Publishing never waits for subscribers: state subscribers only ever see the latest state, and event subscribers
+/// that fall too far behind miss events (and are told how many they missed).
+#[derive(Clone)]
+pub struct Publisher {
+    state_tx: Arc<watch::Sender<TP25State>>,
+    event_tx: broadcast::Sender<ControllerEvent>,
+}
+
+impl Publisher {
+    pub fn new(initial_state: TP25State) -> Self {
+        Self {
+            state_tx: Arc::new(watch::Sender::new(initial_state)),
+            event_tx: broadcast::Sender::new(EVENT_CAPACITY),
+        }
+    }
+
+    /// Publish a new state, along with events for anything that changed since the last one.
+    pub fn publish_state(&self, state: TP25State) {
+        let events = events_between(&self.state_tx.borrow(), &state);
+        self.state_tx.send_replace(state);
+
+        let timestamp = SystemTime::now();
+        for kind in events {
+            self.publish_event(ControllerEvent::Device(DeviceEvent { timestamp, kind }));
+        }
+    }
+
+    pub fn publish_event(&self, event: ControllerEvent) {
+        // It doesn't matter if nobody is listening.
+        let _ = self.event_tx.send(event);
+    }
+
+    pub fn subscribe_state(&self) -> watch::Receiver<TP25State> {
+        self.state_tx.subscribe()
+    }
+
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ControllerEvent> {
+        self.event_tx.subscribe()
+    }
+}
+
+/// Something that follows a controller's events until it's shut down, such as a recorder. See
+/// `ControllerHandle::follow_events`.
+pub trait EventFollower {
+    /// What to call the follower in log messages, e.g. "Transfer recorder".
+    const NAME: &'static str;
+
+    fn on_event(&mut self, event: ControllerEvent);
+
+    /// Catch up after falling behind and missing events, if possible. By default this does nothing.
+    fn on_missed(&mut self) {}
+}
+
+/// Used to observe and control a running controller. Clones all control the same controller.
+#[derive(Clone)]
+pub struct ControllerHandle {
+    publisher: Publisher,
+    commands: CommandSender,
+    cook_targets: CookTargets,
+    shutdown: CancellationToken,
+}
+
+impl ControllerHandle {
+    pub(crate) fn new(
+        publisher: Publisher,
+        commands: CommandSender,
+        cook_targets: CookTargets,
+        shutdown: CancellationToken,
+    ) -> Self {
+        Self {
+            publisher,
+            commands,
+            cook_targets,
+            shutdown,
+        }
+    }
+
+    /// Watch the device state. The receiver always holds the latest state, and can wait for it to change.
+    pub fn subscribe_state(&self) -> watch::Receiver<TP25State> {
+        self.publisher.subscribe_state()
+    }
+
+    /// Receive every event from now on. If the receiver falls more than `EVENT_CAPACITY` events behind, the oldest
+    /// are dropped, and the next `recv` returns `RecvError::Lagged` with the number missed.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ControllerEvent> {
+        self.publisher.subscribe_events()
+    }
+
+    /// A snapshot of the current device state.
+    pub fn state(&self) -> TP25State {
+        self.publisher.subscribe_state().borrow().clone()
+    }
+
+    /// Send command requests to the thermometer.
+    pub fn commands(&self) -> &CommandSender {
+        &self.commands
+    }
+
+    /// Estimate when a probe will reach `target`, rather than the top of its alarm threshold (or go back to the alarm
+    /// threshold if `None`). This only affects the cook estimate, so nothing is sent to the thermometer, and it can be
+    /// set while the thermometer isn't connected. The state is published with the new estimate before this returns.
+    pub async fn set_cook_target(&self, idx: ProbeIdx, target: Option<InRangeDeviceTemperature>) {
+        self.cook_targets.set(idx, target).await
+    }
+
+    /// Ask the controller to stop. It stops scanning, disconnects from the thermometer and fails any requests that
+    /// haven't completed with `Error::Shutdown`, and then `ConnectionManager::run` returns.
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Whether `shutdown` has been called.
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+
+    /// Wait until `shutdown` has been called.
+    pub async fn wait_for_shutdown(&self) {
+        self.shutdown.cancelled().await
+    }
+
+    /// Give `follower` every event from now on, until the controller is shut down. If it falls behind, a warning is
+    /// logged and it gets the chance to catch up.
+    pub async fn follow_events<F: EventFollower>(&self, follower: &mut F) {
+        let mut events = self.subscribe_events();
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                _ = self.wait_for_shutdown() => break,
+            };
+            match event {
+                Ok(event) => follower.on_event(event),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("{} fell behind, {} events missed", F::NAME, missed);
+                    follower.on_missed();
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    pub(crate) fn publisher(&self) -> &Publisher {
+        &self.publisher
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::command_request::command_channel;
+    use crate::controller::connection_handler::ConnectionHandler;
+    use crate::controller::connection_mgr::ProtectedDeviceState;
+    use crate::controller::device_event::DeviceEventKind;
+    use crate::peripheral::command::build_alarm_ack_cmd;
+    use assert_matches::assert_matches;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    fn handle() -> (Publisher, ControllerHandle) {
+        let publisher = Publisher::new(TP25State::default());
+        let (commands, _) = command_channel(1);
+        (
+            publisher.clone(),
+            ControllerHandle::new(
+                publisher.clone(),
+                commands,
+                ConnectionHandler::default()
+                    .cook_targets(&ProtectedDeviceState::default(), &publisher),
+                CancellationToken::new(),
+            ),
+        )
+    }
+
+    #[test]
+    fn every_subscriber_sees_the_latest_state() {
+        let (publisher, handle) = handle();
+        let mut a = handle.subscribe_state();
+        let mut b = handle.subscribe_state();
+
+        for _ in 0..3 {
+            publisher.publish_state(TP25State {
+                connected: true,
+                ..TP25State::default()
+            });
+        }
+
+        assert!(a.has_changed().unwrap());
+        assert!(a.borrow_and_update().connected);
+        assert!(b.borrow_and_update().connected);
+        assert!(handle.state().connected);
+    }
+
+    #[test]
+    fn state_changes_are_published_as_events() {
+        let (publisher, handle) = handle();
+        let mut events = handle.subscribe_events();
+
+        publisher.publish_state(TP25State {
+            connected: true,
+            ..TP25State::default()
+        });
+
+        assert_matches!(
+            events.try_recv(),
+            Ok(ControllerEvent::Device(DeviceEvent {
+                kind: DeviceEventKind::Connected,
+                ..
+            }))
+        );
+        assert_matches!(events.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tokio::test]
+    async fn slow_event_subscribers_are_told_they_lagged() {
+        let (publisher, handle) = handle();
+        let mut slow = handle.subscribe_events();
+
+        // Publishing never blocks, even though nobody is receiving.
+        for _ in 0..EVENT_CAPACITY + 5 {
+            publisher.publish_event(ControllerEvent::Transfer(Transfer::Command(
+                build_alarm_ack_cmd(),
+            )));
+        }
+
+        assert_matches!(slow.recv().await, Err(RecvError::Lagged(5)));
+        assert_matches!(slow.recv().await, Ok(ControllerEvent::Transfer(_)));
+
+        // Subscribers only see events published after they subscribed.
+        assert_matches!(
+            handle.subscribe_events().try_recv(),
+            Err(TryRecvError::Empty)
+        );
+    }
+
+    /// Counts what it's given, and shuts the controller down after `stop_after` events.
+    struct Counter {
+        handle: ControllerHandle,
+        stop_after: usize,
+        events: usize,
+        missed: usize,
+    }
+
+    impl EventFollower for Counter {
+        const NAME: &'static str = "Counter";
+
+        fn on_event(&mut self, _event: ControllerEvent) {
+            self.events += 1;
+            if self.events == self.stop_after {
+                self.handle.shutdown();
+            }
+        }
+
+        fn on_missed(&mut self) {
+            self.missed += 1;
+        }
+    }
+
+    #[tokio::test]
+    async fn followers_catch_up_and_stop_on_shutdown() {
+        let (publisher, handle) = handle();
+        let mut counter = Counter {
+            handle: handle.clone(),
+            stop_after: EVENT_CAPACITY,
+            events: 0,
+            missed: 0,
+        };
+
+        let following = handle.follow_events(&mut counter);
+        let publishing = async {
+            // Give the follower a chance to subscribe, then publish more than it can keep up with.
+            tokio::task::yield_now().await;
+            for _ in 0..EVENT_CAPACITY + 5 {
+                publisher.publish_event(ControllerEvent::Transfer(Transfer::Command(
+                    build_alarm_ack_cmd(),
+                )));
+            }
+        };
+        tokio::join!(following, publishing);
+
+        assert_eq!((counter.events, counter.missed), (EVENT_CAPACITY, 1));
+    }
+
+    #[tokio::test]
+    async fn cook_targets_can_be_set_while_disconnected() {
+        let (_, handle) = handle();
+        let mut state = handle.subscribe_state();
+        let target = InRangeDeviceTemperature::from(63.0);
+
+        handle.set_cook_target(ProbeIdx::Probe2, Some(target)).await;
+        assert!(state.has_changed().unwrap());
+        assert_eq!(
+            state.borrow_and_update().probes[1].cook_target,
+            Some(target)
+        );
+
+        handle.set_cook_target(ProbeIdx::Probe2, None).await;
+        assert_eq!(handle.state().probes[1].cook_target, None);
+    }
+}
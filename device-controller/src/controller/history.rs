@@ -0,0 +1,510 @@
+use crate::controller::handle::{ControllerEvent, ControllerHandle, EventFollower};
+use crate::model::device_temperature::DeviceTemperature;
+use crate::model::probe::ProbeIdx;
+use crate::peripheral::notification::{Decoded, TemperatureData};
+use crate::peripheral::transfer::Transfer;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How much temperature history is kept.
+///
+/// Every reading is kept for a while, and each reading is also summarised into the downsampled tiers, which are kept
+/// for longer. Each tier holds a fixed number of points, so memory use is bounded however long the controller runs.
+#[derive(Clone, Debug)]
+pub struct HistorySettings {
+    /// How long every reading is kept for.
+    pub full_resolution: Duration,
+    /// The most readings kept at full resolution, in case readings arrive much faster than expected.
+    pub max_full_resolution_readings: usize,
+    /// The downsampled tiers, finest first.
+    pub tiers: Vec<TierSettings>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TierSettings {
+    /// How long each point in this tier covers.
+    pub resolution: Duration,
+    /// How long points are kept for.
+    pub keep: Duration,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            full_resolution: Duration::from_secs(10 * 60),
+            max_full_resolution_readings: 10_000,
+            tiers: vec![
+                TierSettings {
+                    resolution: Duration::from_secs(1),
+                    keep: Duration::from_secs(60 * 60),
+                },
+                TierSettings {
+                    resolution: Duration::from_secs(10),
+                    keep: Duration::from_secs(12 * 60 * 60),
+                },
+                TierSettings {
+                    resolution: Duration::from_secs(60),
+                    keep: Duration::from_secs(7 * 24 * 60 * 60),
+                },
+            ],
+        }
+    }
+}
+
+/// Reasons the `HistorySettings` can't be used. Tiers are numbered from zero, finest first.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HistoryError {
+    /// The tier's points would cover no time at all.
+    ZeroResolution(usize),
+    /// The tier keeps points for less time than each one covers, so it couldn't hold any.
+    KeepShorterThanResolution(usize),
+}
+
+impl Display for HistoryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryError::ZeroResolution(tier) => {
+                write!(f, "history tier {tier} has a resolution of zero")
+            }
+            HistoryError::KeepShorterThanResolution(tier) => write!(
+                f,
+                "history tier {tier} keeps points for less time than each one covers"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+/// A probe's temperature over a period - or at an instant, for a full resolution reading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HistoryPoint {
+    /// When the period starts.
+    pub at: SystemTime,
+    /// How long the period is. Zero for a full resolution reading.
+    pub period: Duration,
+    /// The temperatures in the period, in degrees Celsius. `None` if the probe was unplugged throughout.
+    pub temperature: Option<TemperatureSummary>,
+    /// Whether the thermometer was alarming for this probe at any time in the period.
+    pub alarm: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureSummary {
+    pub min: f32,
+    pub mean: f32,
+    pub max: f32,
+}
+
+/// Which of the stored resolutions a query should use.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Resolution {
+    /// Every reading. Only recent history is kept at this resolution.
+    Full,
+    /// The finest tier whose points cover at least this long, or the coarsest tier if none do.
+    AtLeast(Duration),
+    /// The finest resolution that still reaches back to the start of the range.
+    Auto,
+}
+
+/// Timestamped temperature readings for each probe, at several resolutions.
+pub struct TemperatureHistory {
+    settings: HistorySettings,
+    probes: [ProbeHistory; 4],
+}
+
+struct ProbeHistory {
+    readings: VecDeque<HistoryPoint>,
+    tiers: Vec<Tier>,
+}
+
+struct Tier {
+    resolution: Duration,
+    capacity: usize,
+    points: VecDeque<HistoryPoint>,
+    /// The point still being filled in.
+    current: Option<Accumulator>,
+}
+
+struct Accumulator {
+    at: SystemTime,
+    min: f32,
+    max: f32,
+    sum: f32,
+    count: u32,
+    alarm: bool,
+}
+
+impl TemperatureHistory {
+    pub fn new(settings: HistorySettings) -> Result<Self, HistoryError> {
+        for (i, tier) in settings.tiers.iter().enumerate() {
+            if tier.resolution.is_zero() {
+                return Err(HistoryError::ZeroResolution(i));
+            }
+            if tier.keep < tier.resolution {
+                return Err(HistoryError::KeepShorterThanResolution(i));
+            }
+        }
+
+        let probes = std::array::from_fn(|_| ProbeHistory {
+            readings: VecDeque::new(),
+            tiers: settings
+                .tiers
+                .iter()
+                .map(|tier| Tier {
+                    resolution: tier.resolution,
+                    capacity: (tier.keep.as_secs_f64() / tier.resolution.as_secs_f64()).ceil()
+                        as usize,
+                    points: VecDeque::new(),
+                    current: None,
+                })
+                .collect(),
+        });
+        Ok(Self { settings, probes })
+    }
+
+    /// Record the temperatures reported by the thermometer at `at`.
+    pub fn record(&mut self, temps: &TemperatureData, at: SystemTime) {
+        for (probe, reading) in self.probes.iter_mut().zip(&temps.temps) {
+            let temperature = match reading.temp {
+                DeviceTemperature::InRange(t) => Some(f32::from(t)),
+                DeviceTemperature::OutOfRange => None,
+            };
+
+            probe.readings.push_back(HistoryPoint {
+                at,
+                period: Duration::ZERO,
+                temperature: temperature.map(|t| TemperatureSummary {
+                    min: t,
+                    mean: t,
+                    max: t,
+                }),
+                alarm: reading.alarm,
+            });
+            while probe.readings.len() > self.settings.max_full_resolution_readings
+                || probe.readings.front().is_some_and(|first| {
+                    at.duration_since(first.at)
+                        .is_ok_and(|age| age > self.settings.full_resolution)
+                })
+            {
+                probe.readings.pop_front();
+            }
+
+            for tier in &mut probe.tiers {
+                tier.record(temperature, reading.alarm, at);
+            }
+        }
+    }
+
+    /// The history of `probe` between `from` and `to`, oldest first. Downsampled points are included if their period
+    /// overlaps the range, including the point currently being filled in.
+    pub fn query(
+        &self,
+        probe: ProbeIdx,
+        from: SystemTime,
+        to: SystemTime,
+        resolution: Resolution,
+    ) -> Vec<HistoryPoint> {
+        let probe = &self.probes[probe.as_zero_based() as usize];
+        let tier = match resolution {
+            Resolution::Full => None,
+            Resolution::AtLeast(wanted) => probe
+                .tiers
+                .iter()
+                .find(|tier| tier.resolution >= wanted)
+                .or(probe.tiers.last()),
+            Resolution::Auto => {
+                let reaches_back =
+                    |first: Option<&HistoryPoint>| first.is_some_and(|p| p.at <= from);
+                if reaches_back(probe.readings.front()) {
+                    None
+                } else {
+                    probe
+                        .tiers
+                        .iter()
+                        .find(|tier| reaches_back(tier.points.front()))
+                        .or(probe.tiers.last())
+                }
+            }
+        };
+
+        let overlaps = |p: &HistoryPoint| p.at <= to && p.at + p.period >= from;
+        match tier {
+            None => probe
+                .readings
+                .iter()
+                .filter(|p| overlaps(p))
+                .copied()
+                .collect(),
+            Some(tier) => tier
+                .points
+                .iter()
+                .copied()
+                .chain(tier.current.as_ref().map(|c| c.point(tier.resolution)))
+                .filter(overlaps)
+                .collect(),
+        }
+    }
+}
+
+impl Tier {
+    fn record(&mut self, temperature: Option<f32>, alarm: bool, at: SystemTime) {
+        let start = period_start(at, self.resolution);
+        if self.current.as_ref().is_some_and(|c| c.at != start) {
+            let finished = self.current.take().unwrap().point(self.resolution);
+            self.points.push_back(finished);
+            while self.points.len() > self.capacity {
+                self.points.pop_front();
+            }
+        }
+
+        let current = self.current.get_or_insert(Accumulator {
+            at: start,
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            sum: 0.0,
+            count: 0,
+            alarm: false,
+        });
+        current.alarm |= alarm;
+        if let Some(t) = temperature {
+            current.min = current.min.min(t);
+            current.max = current.max.max(t);
+            current.sum += t;
+            current.count += 1;
+        }
+    }
+}
+
+impl Accumulator {
+    fn point(&self, period: Duration) -> HistoryPoint {
+        HistoryPoint {
+            at: self.at,
+            period,
+            temperature: (self.count > 0).then(|| TemperatureSummary {
+                min: self.min,
+                mean: self.sum / self.count as f32,
+                max: self.max,
+            }),
+            alarm: self.alarm,
+        }
+    }
+}
+
+/// The start of the period of length `resolution` that `at` falls into. Periods are aligned to the Unix epoch, so
+/// that minutes start on the minute.
+fn period_start(at: SystemTime, resolution: Duration) -> SystemTime {
+    let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let resolution = resolution.as_nanos().max(1);
+    let start = since_epoch - since_epoch % resolution;
+    UNIX_EPOCH + Duration::from_nanos(start as u64)
+}
+
+/// Records the temperatures from a controller into a `TemperatureHistory`, which clones share so that they can query
+/// it while one of them records.
+#[derive(Clone)]
+pub struct HistoryRecorder {
+    controller: ControllerHandle,
+    history: Arc<Mutex<TemperatureHistory>>,
+}
+
+impl HistoryRecorder {
+    pub fn new(
+        controller: ControllerHandle,
+        settings: HistorySettings,
+    ) -> Result<Self, HistoryError> {
+        Ok(Self {
+            controller,
+            history: Arc::new(Mutex::new(TemperatureHistory::new(settings)?)),
+        })
+    }
+
+    /// Record temperatures until the controller is shut down.
+    pub async fn run(mut self) {
+        let controller = self.controller.clone();
+        controller.follow_events(&mut self).await
+    }
+
+    /// See `TemperatureHistory::query`.
+    pub fn query(
+        &self,
+        probe: ProbeIdx,
+        from: SystemTime,
+        to: SystemTime,
+        resolution: Resolution,
+    ) -> Vec<HistoryPoint> {
+        self.history
+            .lock()
+            .unwrap()
+            .query(probe, from, to, resolution)
+    }
+}
+
+impl EventFollower for HistoryRecorder {
+    const NAME: &'static str = "History recorder";
+
+    fn on_event(&mut self, event: ControllerEvent) {
+        if let ControllerEvent::Transfer(Transfer::Notification(n)) = event {
+            if let Ok(Decoded::Temperatures(temps)) = &n.decoded {
+                self.history
+                    .lock()
+                    .unwrap()
+                    .record(temps, SystemTime::now());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::device::TemperatureMode;
+    use crate::model::device_temperature::InRangeDeviceTemperature;
+    use crate::model::probe::ProbeIdx::{Probe1, Probe2};
+    use crate::peripheral::notification::ProbeTemperature;
+    use assert_matches::assert_matches;
+
+    fn temps(probe_1: Option<f32>, alarm: bool) -> TemperatureData {
+        let mut data = TemperatureData {
+            temps: [ProbeTemperature::default(); 4],
+            temp_mode: TemperatureMode::Celsius,
+        };
+        if let Some(t) = probe_1 {
+            data.temps[0].temp = DeviceTemperature::InRange(InRangeDeviceTemperature::from(t));
+        }
+        data.temps[0].alarm = alarm;
+        data
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+    }
+
+    /// A reading of `secs` degrees every second for `count` seconds.
+    fn history(settings: HistorySettings, count: u64) -> TemperatureHistory {
+        let mut history = TemperatureHistory::new(settings).unwrap();
+        for secs in 0..count {
+            history.record(&temps(Some(secs as f32), secs == 15), at(secs));
+        }
+        history
+    }
+
+    #[test]
+    fn full_resolution_range_query() {
+        let history = history(HistorySettings::default(), 60);
+        let points = history.query(Probe1, at(10), at(12), Resolution::Full);
+        let temps: Vec<_> = points.iter().map(|p| p.temperature.unwrap().mean).collect();
+        assert_eq!(temps, vec![10.0, 11.0, 12.0]);
+
+        // Other probes have their own history.
+        let points = history.query(Probe2, at(10), at(12), Resolution::Full);
+        assert_eq!(points.len(), 3);
+        assert!(points.iter().all(|p| p.temperature.is_none()));
+    }
+
+    #[test]
+    fn downsampled_tiers_summarise_readings() {
+        let history = history(HistorySettings::default(), 60);
+        let points = history.query(
+            Probe1,
+            at(0),
+            at(59),
+            Resolution::AtLeast(Duration::from_secs(10)),
+        );
+
+        // Six ten second periods, the last still being filled in. 1_700_000_000 is a whole number of tens.
+        assert_eq!(points.len(), 6);
+        assert_eq!(points[1].at, at(10));
+        assert_eq!(points[1].period, Duration::from_secs(10));
+        assert_eq!(
+            points[1].temperature,
+            Some(TemperatureSummary {
+                min: 10.0,
+                mean: 14.5,
+                max: 19.0
+            })
+        );
+        let alarms: Vec<_> = points.iter().map(|p| p.alarm).collect();
+        assert_eq!(alarms, vec![false, true, false, false, false, false]);
+    }
+
+    #[test]
+    fn unusable_tiers_are_rejected() {
+        let tier = |resolution, keep| HistorySettings {
+            tiers: vec![
+                TierSettings {
+                    resolution: Duration::from_secs(1),
+                    keep: Duration::from_secs(60),
+                },
+                TierSettings {
+                    resolution: Duration::from_secs(resolution),
+                    keep: Duration::from_secs(keep),
+                },
+            ],
+            ..HistorySettings::default()
+        };
+        assert_matches!(
+            TemperatureHistory::new(tier(0, 60)).err(),
+            Some(HistoryError::ZeroResolution(1))
+        );
+        assert_matches!(
+            TemperatureHistory::new(tier(60, 59)).err(),
+            Some(HistoryError::KeepShorterThanResolution(1))
+        );
+        assert!(TemperatureHistory::new(tier(60, 60)).is_ok());
+    }
+
+    #[test]
+    fn memory_is_bounded() {
+        let settings = HistorySettings {
+            full_resolution: Duration::from_secs(30),
+            max_full_resolution_readings: 10_000,
+            tiers: vec![TierSettings {
+                resolution: Duration::from_secs(10),
+                keep: Duration::from_secs(60),
+            }],
+        };
+        let history = history(settings, 1000);
+
+        let all = history.query(Probe1, at(0), at(1000), Resolution::Full);
+        assert_eq!(all.first().map(|p| p.at), Some(at(969)));
+        let all = history.query(Probe1, at(0), at(1000), Resolution::AtLeast(Duration::ZERO));
+        // Six finished periods, and the one being filled in.
+        assert_eq!(all.len(), 7);
+    }
+
+    #[test]
+    fn auto_resolution_reaches_back_far_enough() {
+        let settings = HistorySettings {
+            full_resolution: Duration::from_secs(30),
+            ..HistorySettings::default()
+        };
+        let history = history(settings, 120);
+
+        let recent = history.query(Probe1, at(100), at(119), Resolution::Auto);
+        assert!(recent.iter().all(|p| p.period == Duration::ZERO));
+        let older = history.query(Probe1, at(0), at(119), Resolution::Auto);
+        assert_eq!(older[0].period, Duration::from_secs(1));
+        assert_eq!(older[0].at, at(0));
+    }
+
+    #[test]
+    fn unplugged_periods_have_no_temperature() {
+        let mut history = TemperatureHistory::new(HistorySettings::default()).unwrap();
+        for secs in 0..10 {
+            history.record(&temps(None, false), at(secs));
+        }
+        history.record(&temps(Some(20.0), false), at(10));
+
+        let points = history.query(
+            Probe1,
+            at(0),
+            at(10),
+            Resolution::AtLeast(Duration::from_secs(10)),
+        );
+        assert_eq!(points[0].temperature, None);
+        assert!(points[1].temperature.is_some());
+    }
+}
@@ -0,0 +1,720 @@
+use crate::controller::handle::{ControllerEvent, ControllerHandle, EventFollower};
+use crate::model::device::TP25State;
+use crate::model::device_temperature::DeviceTemperature;
+use crate::model::probe::ProbeIdx;
+use crate::peripheral::notification::{Decoded, TemperatureData};
+use crate::peripheral::transfer::Transfer;
+use log::info;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+
+/// How often the host alarms are checked when no temperatures are arriving, so that "no data" alarms go off on time.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a host alarm watches for. Temperatures are in degrees Celsius, whichever mode the thermometer displays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlarmCondition {
+    /// The probe is at or above `limit`.
+    Above { probe: ProbeIdx, limit: f32 },
+    /// The probe is at or below `limit`.
+    Below { probe: ProbeIdx, limit: f32 },
+    /// A pre-alarm: the probe has come within `margin` degrees of `target` (e.g. "within 5°C of done").
+    Approaching {
+        probe: ProbeIdx,
+        target: f32,
+        margin: f32,
+    },
+    /// The probe's temperature is rising at least this fast.
+    RisingFaster {
+        probe: ProbeIdx,
+        degrees_per_minute: f32,
+    },
+    /// The probe's temperature is falling at least this fast.
+    FallingFaster {
+        probe: ProbeIdx,
+        degrees_per_minute: f32,
+    },
+    /// A probe that had been reporting temperatures has stopped.
+    Unplugged { probe: ProbeIdx },
+    /// No temperatures have arrived for this long, whether because the thermometer is out of range or switched off.
+    NoData { after: Duration },
+    /// `hotter` is less than `limit` degrees above `cooler` (e.g. the pit is barely hotter than the meat).
+    DifferenceBelow {
+        hotter: ProbeIdx,
+        cooler: ProbeIdx,
+        limit: f32,
+    },
+    /// `hotter` is at least `limit` degrees above `cooler`.
+    DifferenceAbove {
+        hotter: ProbeIdx,
+        cooler: ProbeIdx,
+        limit: f32,
+    },
+}
+
+/// An alarm evaluated by the controller rather than the thermometer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlarmRule {
+    /// Identifies the alarm, for example when acknowledging it.
+    pub name: String,
+    pub condition: AlarmCondition,
+    /// Once raised, the alarm only clears when the reading has gone back past the limit by this much, so a reading
+    /// hovering around the limit doesn't raise it over and over. In the same units as the condition's limit.
+    pub hysteresis: f32,
+}
+
+impl AlarmRule {
+    pub fn new(name: &str, condition: AlarmCondition) -> Self {
+        Self {
+            name: name.to_string(),
+            condition,
+            hysteresis: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HostAlarmState {
+    Clear,
+    Raised {
+        since: SystemTime,
+        /// Acknowledging only silences this raising of the alarm. If it clears and is raised again, it needs
+        /// acknowledging again.
+        acknowledged: bool,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostAlarmStatus {
+    pub name: String,
+    pub state: HostAlarmState,
+}
+
+/// A change to a host alarm, sent to subscribers of `ControllerHandle::subscribe_events`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HostAlarmEvent {
+    pub timestamp: SystemTime,
+    pub name: String,
+    pub kind: HostAlarmEventKind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HostAlarmEventKind {
+    Raised,
+    Cleared,
+    Acknowledged,
+}
+
+/// Evaluates alarm rules against the temperatures reported by the thermometer.
+///
+/// This knows nothing about where the temperatures come from, and is told when they arrived - see `HostAlarms` for
+/// something that feeds it from a running controller.
+pub struct AlarmEngine {
+    rules: Vec<(AlarmRule, HostAlarmState)>,
+    /// Rates are worked out over this much history.
+    rate_window: Duration,
+    probes: [ProbeHistory; 4],
+    /// When temperatures last arrived, or when the engine was created if they never have.
+    last_data_at: Instant,
+}
+
+#[derive(Default)]
+struct ProbeHistory {
+    /// Readings within the rate window, oldest first. Empty while the probe is unplugged.
+    samples: VecDeque<(Instant, f32)>,
+    /// Whether the probe has ever reported a temperature.
+    seen: bool,
+}
+
+impl AlarmEngine {
+    pub fn new(rules: Vec<AlarmRule>, now: Instant) -> Self {
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|rule| (rule, HostAlarmState::Clear))
+                .collect(),
+            rate_window: Duration::from_secs(60),
+            probes: Default::default(),
+            last_data_at: now,
+        }
+    }
+
+    /// Work rates of rise and fall out over `window`, rather than the default of a minute. Longer windows smooth out
+    /// noise, but react more slowly.
+    pub fn with_rate_window(mut self, window: Duration) -> Self {
+        self.rate_window = window;
+        self
+    }
+
+    /// Record temperatures reported by the thermometer, and re-evaluate every rule.
+    pub fn on_temperatures(
+        &mut self,
+        temps: &TemperatureData,
+        now: Instant,
+    ) -> Vec<HostAlarmEvent> {
+        self.on_readings(temps.temps.iter().map(|t| t.temp), now)
+    }
+
+    /// Catch up with the temperatures in the controller's `state`, after missing some notifications. They're taken as
+    /// read when the last notification arrived, so this does nothing if they've been seen already.
+    pub fn on_state(&mut self, state: &TP25State) -> Vec<HostAlarmEvent> {
+        match state.link.last_notification_at {
+            Some(at) if at > self.last_data_at => {
+                self.on_readings(state.probes.iter().map(|p| p.temperature), at)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn on_readings(
+        &mut self,
+        temps: impl IntoIterator<Item = DeviceTemperature>,
+        now: Instant,
+    ) -> Vec<HostAlarmEvent> {
+        self.last_data_at = now;
+        for (probe, temp) in self.probes.iter_mut().zip(temps) {
+            match temp {
+                DeviceTemperature::InRange(t) => {
+                    probe.seen = true;
+                    probe.samples.push_back((now, f32::from(t)));
+                }
+                DeviceTemperature::OutOfRange => probe.samples.clear(),
+            }
+            while probe
+                .samples
+                .front()
+                .is_some_and(|(at, _)| now.duration_since(*at) > self.rate_window)
+            {
+                probe.samples.pop_front();
+            }
+        }
+        self.evaluate(now)
+    }
+
+    /// Re-evaluate every rule without any new temperatures. Call this regularly so that "no data" alarms go off.
+    pub fn evaluate(&mut self, now: Instant) -> Vec<HostAlarmEvent> {
+        let mut events = Vec::new();
+        for i in 0..self.rules.len() {
+            let (rule, state) = &self.rules[i];
+            // If the condition can't be evaluated at the moment (e.g. the probe is unplugged), leave the alarm as is.
+            let Some(excess) = self.excess(&rule.condition, now) else {
+                continue;
+            };
+            let new_state = match *state {
+                HostAlarmState::Clear if excess >= 0.0 => HostAlarmState::Raised {
+                    since: SystemTime::now(),
+                    acknowledged: false,
+                },
+                HostAlarmState::Raised { .. } if excess < -rule.hysteresis => HostAlarmState::Clear,
+                state => state,
+            };
+            if new_state != *state {
+                let kind = match new_state {
+                    HostAlarmState::Clear => HostAlarmEventKind::Cleared,
+                    HostAlarmState::Raised { .. } => HostAlarmEventKind::Raised,
+                };
+                events.push(HostAlarmEvent {
+                    timestamp: SystemTime::now(),
+                    name: rule.name.clone(),
+                    kind,
+                });
+                self.rules[i].1 = new_state;
+            }
+        }
+        events
+    }
+
+    /// Acknowledge the raised alarm called `name`. Returns `None` if there is no such alarm or it isn't raised (or has
+    /// already been acknowledged).
+    pub fn acknowledge(&mut self, name: &str) -> Option<HostAlarmEvent> {
+        let (rule, state) = self.rules.iter_mut().find(|(rule, _)| rule.name == name)?;
+        let HostAlarmState::Raised {
+            acknowledged: acknowledged @ false,
+            ..
+        } = state
+        else {
+            return None;
+        };
+        *acknowledged = true;
+        Some(HostAlarmEvent {
+            timestamp: SystemTime::now(),
+            name: rule.name.clone(),
+            kind: HostAlarmEventKind::Acknowledged,
+        })
+    }
+
+    pub fn statuses(&self) -> Vec<HostAlarmStatus> {
+        self.rules
+            .iter()
+            .map(|(rule, state)| HostAlarmStatus {
+                name: rule.name.clone(),
+                state: *state,
+            })
+            .collect()
+    }
+
+    /// How far past its limit the reading `condition` watches is, in the condition's units. Zero or more means the
+    /// condition holds. Conditions that are simply true or false give zero or negative infinity.
+    fn excess(&self, condition: &AlarmCondition, now: Instant) -> Option<f32> {
+        let flag = |holds: bool| if holds { 0.0 } else { f32::NEG_INFINITY };
+        match *condition {
+            AlarmCondition::Above { probe, limit } => Some(self.latest(probe)? - limit),
+            AlarmCondition::Below { probe, limit } => Some(limit - self.latest(probe)?),
+            AlarmCondition::Approaching {
+                probe,
+                target,
+                margin,
+            } => Some(self.latest(probe)? - (target - margin)),
+            AlarmCondition::RisingFaster {
+                probe,
+                degrees_per_minute,
+            } => Some(self.rate(probe)? - degrees_per_minute),
+            AlarmCondition::FallingFaster {
+                probe,
+                degrees_per_minute,
+            } => Some(-self.rate(probe)? - degrees_per_minute),
+            AlarmCondition::Unplugged { probe } => {
+                let history = &self.probes[probe.as_zero_based() as usize];
+                Some(flag(history.seen && history.samples.is_empty()))
+            }
+            AlarmCondition::NoData { after } => {
+                Some(flag(now.duration_since(self.last_data_at) >= after))
+            }
+            AlarmCondition::DifferenceBelow {
+                hotter,
+                cooler,
+                limit,
+            } => Some(limit - (self.latest(hotter)? - self.latest(cooler)?)),
+            AlarmCondition::DifferenceAbove {
+                hotter,
+                cooler,
+                limit,
+            } => Some(self.latest(hotter)? - self.latest(cooler)? - limit),
+        }
+    }
+
+    fn latest(&self, probe: ProbeIdx) -> Option<f32> {
+        self.probes[probe.as_zero_based() as usize]
+            .samples
+            .back()
+            .map(|(_, t)| *t)
+    }
+
+    /// The probe's rate of change in degrees per minute, once there's at least half a window of history to go on.
+    fn rate(&self, probe: ProbeIdx) -> Option<f32> {
+        let samples = &self.probes[probe.as_zero_based() as usize].samples;
+        let ((first_at, first), (last_at, last)) = (samples.front()?, samples.back()?);
+        let span = last_at.duration_since(*first_at);
+        if span < self.rate_window / 2 {
+            return None;
+        }
+        Some((last - first) / span.as_secs_f32() * 60.0)
+    }
+}
+
+/// Runs an `AlarmEngine` against the temperatures from a controller, publishing changes to its alarms as
+/// `ControllerEvent::HostAlarm` events. Clones share the alarms, so one clone can run them while others acknowledge
+/// them or watch their states.
+#[derive(Clone)]
+pub struct HostAlarms {
+    controller: ControllerHandle,
+    engine: Arc<Mutex<AlarmEngine>>,
+    statuses_tx: Arc<watch::Sender<Vec<HostAlarmStatus>>>,
+}
+
+impl HostAlarms {
+    pub fn new(controller: ControllerHandle, engine: AlarmEngine) -> Self {
+        let statuses = engine.statuses();
+        Self {
+            controller,
+            engine: Arc::new(Mutex::new(engine)),
+            statuses_tx: Arc::new(watch::Sender::new(statuses)),
+        }
+    }
+
+    /// Evaluate the alarms until the controller is shut down.
+    pub async fn run(self) {
+        let checks = async {
+            let mut check = interval(CHECK_INTERVAL);
+            check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = check.tick() => {
+                        let events = self.engine.lock().unwrap().evaluate(Instant::now());
+                        self.publish(events);
+                    }
+                    _ = self.controller.wait_for_shutdown() => break,
+                }
+            }
+        };
+        let mut follower = self.clone();
+        tokio::join!(self.controller.follow_events(&mut follower), checks);
+    }
+
+    /// Acknowledge the raised alarm called `name`. Returns false if there is no such alarm or it isn't raised.
+    pub fn acknowledge(&self, name: &str) -> bool {
+        let event = self.engine.lock().unwrap().acknowledge(name);
+        let acknowledged = event.is_some();
+        self.publish(event.into_iter().collect());
+        acknowledged
+    }
+
+    /// A snapshot of every alarm's state.
+    pub fn statuses(&self) -> Vec<HostAlarmStatus> {
+        self.statuses_tx.borrow().clone()
+    }
+
+    /// Watch the state of every alarm.
+    pub fn subscribe(&self) -> watch::Receiver<Vec<HostAlarmStatus>> {
+        self.statuses_tx.subscribe()
+    }
+
+    fn publish(&self, events: Vec<HostAlarmEvent>) {
+        if events.is_empty() {
+            return;
+        }
+        self.statuses_tx
+            .send_replace(self.engine.lock().unwrap().statuses());
+        for event in events {
+            info!("Host alarm {} {:?}", event.name, event.kind);
+            self.controller
+                .publisher()
+                .publish_event(ControllerEvent::HostAlarm(event));
+        }
+    }
+}
+
+impl EventFollower for HostAlarms {
+    const NAME: &'static str = "Host alarms";
+
+    fn on_event(&mut self, event: ControllerEvent) {
+        if let ControllerEvent::Transfer(Transfer::Notification(n)) = event {
+            if let Ok(Decoded::Temperatures(temps)) = &n.decoded {
+                let events = self
+                    .engine
+                    .lock()
+                    .unwrap()
+                    .on_temperatures(temps, Instant::now());
+                self.publish(events);
+            }
+        }
+    }
+
+    fn on_missed(&mut self) {
+        let events = self
+            .engine
+            .lock()
+            .unwrap()
+            .on_state(&self.controller.state());
+        self.publish(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::device::TemperatureMode;
+    use crate::model::device_temperature::InRangeDeviceTemperature;
+    use crate::model::probe::ProbeIdx::{Probe1, Probe2};
+    use crate::peripheral::notification::ProbeTemperature;
+    use assert_matches::assert_matches;
+
+    /// Temperatures with the first probes reading `temps`, and the rest unplugged.
+    fn temps(temps: &[f32]) -> TemperatureData {
+        let mut data = TemperatureData {
+            temps: [ProbeTemperature::default(); 4],
+            temp_mode: TemperatureMode::Celsius,
+        };
+        for (probe, t) in data.temps.iter_mut().zip(temps) {
+            probe.temp = DeviceTemperature::InRange(InRangeDeviceTemperature::from(*t));
+        }
+        data
+    }
+
+    fn kinds(events: Vec<HostAlarmEvent>) -> Vec<(String, HostAlarmEventKind)> {
+        events.into_iter().map(|e| (e.name, e.kind)).collect()
+    }
+
+    fn raised(name: &str) -> Vec<(String, HostAlarmEventKind)> {
+        vec![(name.to_string(), HostAlarmEventKind::Raised)]
+    }
+
+    fn cleared(name: &str) -> Vec<(String, HostAlarmEventKind)> {
+        vec![(name.to_string(), HostAlarmEventKind::Cleared)]
+    }
+
+    #[test]
+    fn hysteresis_stops_alarm_flapping() {
+        let start = Instant::now();
+        let mut engine = AlarmEngine::new(
+            vec![AlarmRule {
+                hysteresis: 2.0,
+                ..AlarmRule::new(
+                    "pit hot",
+                    AlarmCondition::Above {
+                        probe: Probe1,
+                        limit: 120.0,
+                    },
+                )
+            }],
+            start,
+        );
+
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[119.0]), start)),
+            vec![]
+        );
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[120.5]), start)),
+            raised("pit hot")
+        );
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[119.0]), start)),
+            vec![]
+        );
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[121.0]), start)),
+            vec![]
+        );
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[117.5]), start)),
+            cleared("pit hot")
+        );
+    }
+
+    #[test]
+    fn pre_alarm_goes_off_near_target() {
+        let start = Instant::now();
+        let mut engine = AlarmEngine::new(
+            vec![AlarmRule::new(
+                "nearly done",
+                AlarmCondition::Approaching {
+                    probe: Probe2,
+                    target: 63.0,
+                    margin: 5.0,
+                },
+            )],
+            start,
+        );
+
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[110.0, 57.9]), start)),
+            vec![]
+        );
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[110.0, 58.0]), start)),
+            raised("nearly done")
+        );
+    }
+
+    #[test]
+    fn rate_of_rise_and_fall() {
+        let start = Instant::now();
+        let mut engine = AlarmEngine::new(
+            vec![
+                AlarmRule::new(
+                    "rising",
+                    AlarmCondition::RisingFaster {
+                        probe: Probe1,
+                        degrees_per_minute: 5.0,
+                    },
+                ),
+                AlarmRule::new(
+                    "falling",
+                    AlarmCondition::FallingFaster {
+                        probe: Probe1,
+                        degrees_per_minute: 5.0,
+                    },
+                ),
+            ],
+            start,
+        );
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // Not enough history to tell yet, however fast it looks.
+        engine.on_temperatures(&temps(&[20.0]), at(0));
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[25.0]), at(10))),
+            vec![]
+        );
+
+        // 6 degrees over the last minute.
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[26.0]), at(60))),
+            raised("rising")
+        );
+        // Only the last minute counts, so this is a fall of 1 degree.
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[24.0]), at(70))),
+            cleared("rising")
+        );
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[15.0]), at(130))),
+            raised("falling")
+        );
+    }
+
+    #[test]
+    fn unplugged_only_after_probe_was_used() {
+        let start = Instant::now();
+        let mut engine = AlarmEngine::new(
+            vec![
+                AlarmRule::new("probe 1 out", AlarmCondition::Unplugged { probe: Probe1 }),
+                AlarmRule::new("probe 2 out", AlarmCondition::Unplugged { probe: Probe2 }),
+            ],
+            start,
+        );
+
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[50.0]), start)),
+            vec![]
+        );
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[]), start)),
+            raised("probe 1 out")
+        );
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[50.0]), start)),
+            cleared("probe 1 out")
+        );
+    }
+
+    #[test]
+    fn no_data_alarm_needs_no_temperatures() {
+        let start = Instant::now();
+        let mut engine = AlarmEngine::new(
+            vec![AlarmRule::new(
+                "silent",
+                AlarmCondition::NoData {
+                    after: Duration::from_secs(300),
+                },
+            )],
+            start,
+        );
+
+        assert_eq!(
+            kinds(engine.evaluate(start + Duration::from_secs(299))),
+            vec![]
+        );
+        assert_eq!(
+            kinds(engine.evaluate(start + Duration::from_secs(300))),
+            raised("silent")
+        );
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[]), start + Duration::from_secs(301))),
+            cleared("silent")
+        );
+    }
+
+    #[test]
+    fn catches_up_from_the_controller_state() {
+        let start = Instant::now();
+        let mut engine = AlarmEngine::new(
+            vec![AlarmRule::new(
+                "pit hot",
+                AlarmCondition::Above {
+                    probe: Probe1,
+                    limit: 120.0,
+                },
+            )],
+            start,
+        );
+
+        let mut state = TP25State::default();
+        state.probes[0].temperature =
+            DeviceTemperature::InRange(InRangeDeviceTemperature::from(125.0));
+        state.link.last_notification_at = Some(start + Duration::from_secs(1));
+        assert_eq!(kinds(engine.on_state(&state)), raised("pit hot"));
+
+        // The same state again is nothing new, so doesn't count as data.
+        assert_eq!(kinds(engine.on_state(&state)), vec![]);
+        assert_eq!(engine.last_data_at, start + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn cross_probe_difference() {
+        let start = Instant::now();
+        let mut engine = AlarmEngine::new(
+            vec![AlarmRule::new(
+                "pit too cool",
+                AlarmCondition::DifferenceBelow {
+                    hotter: Probe1,
+                    cooler: Probe2,
+                    limit: 30.0,
+                },
+            )],
+            start,
+        );
+
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[110.0, 60.0]), start)),
+            vec![]
+        );
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[89.0, 60.0]), start)),
+            raised("pit too cool")
+        );
+        // Can't be worked out without both probes, so the alarm stays as it was.
+        assert_eq!(
+            kinds(engine.on_temperatures(&temps(&[89.0]), start)),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn each_alarm_is_acknowledged_separately() {
+        let start = Instant::now();
+        let mut engine = AlarmEngine::new(
+            vec![
+                AlarmRule::new(
+                    "a",
+                    AlarmCondition::Above {
+                        probe: Probe1,
+                        limit: 50.0,
+                    },
+                ),
+                AlarmRule::new(
+                    "b",
+                    AlarmCondition::Above {
+                        probe: Probe2,
+                        limit: 50.0,
+                    },
+                ),
+            ],
+            start,
+        );
+
+        assert!(engine.acknowledge("a").is_none());
+        engine.on_temperatures(&temps(&[60.0, 60.0]), start);
+        assert_eq!(
+            engine.acknowledge("a").map(|e| e.kind),
+            Some(HostAlarmEventKind::Acknowledged)
+        );
+        assert!(engine.acknowledge("a").is_none());
+        assert!(engine.acknowledge("c").is_none());
+
+        let acknowledged: Vec<_> = engine
+            .statuses()
+            .into_iter()
+            .map(|s| match s.state {
+                HostAlarmState::Raised { acknowledged, .. } => acknowledged,
+                HostAlarmState::Clear => panic!("{} should be raised", s.name),
+            })
+            .collect();
+        assert_eq!(acknowledged, vec![true, false]);
+
+        // Raising the alarm again needs a fresh acknowledgement.
+        engine.on_temperatures(&temps(&[40.0, 60.0]), start);
+        engine.on_temperatures(&temps(&[60.0, 60.0]), start);
+        assert_matches!(
+            engine.statuses()[0].state,
+            HostAlarmState::Raised {
+                acknowledged: false,
+                ..
+            }
+        );
+    }
+}
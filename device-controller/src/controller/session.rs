@@ -0,0 +1,573 @@
+use crate::controller::device_event::DeviceEventKind;
+use crate::controller::handle::{ControllerEvent, ControllerHandle, EventFollower};
+use crate::controller::host_alarm::HostAlarmEventKind;
+use crate::model::device_temperature::DeviceTemperature;
+use crate::model::probe::AlarmThreshold;
+use crate::peripheral::notification::Decoded;
+use crate::peripheral::transfer::Transfer;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The extension of session files.
+const EXTENSION: &str = "jsonl";
+
+/// Reasons a session couldn't be started, resumed or read.
+#[derive(Debug)]
+pub enum SessionError {
+    Io(std::io::Error),
+    /// Session names are used as file names, so are limited to letters, digits, spaces, `-` and `_`.
+    InvalidName(String),
+    AlreadyExists(String),
+    NotFound(String),
+    /// A session is already being recorded, and must be stopped before another is started.
+    AlreadyRecording(String),
+}
+
+impl Display for SessionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::Io(e) => write!(f, "session file error: {e}"),
+            SessionError::InvalidName(name) => write!(f, "{name:?} is not a valid session name"),
+            SessionError::AlreadyExists(name) => write!(f, "session {name} already exists"),
+            SessionError::NotFound(name) => write!(f, "no session called {name}"),
+            SessionError::AlreadyRecording(name) => write!(f, "already recording session {name}"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SessionError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SessionError {
+    fn from(e: std::io::Error) -> Self {
+        SessionError::Io(e)
+    }
+}
+
+/// One line of a session file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    /// Milliseconds since the Unix epoch.
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub entry: SessionEntry,
+}
+
+/// Something that happened during a session. Probes are numbered from 1, as on the thermometer, and temperatures are
+/// in degrees Celsius.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionEntry {
+    Started {
+        name: String,
+    },
+    /// Recording carried on after being interrupted, most likely by the process stopping without the session being
+    /// stopped.
+    Resumed,
+    Stopped,
+    Connected,
+    Disconnected,
+    /// Temperatures reported by the thermometer. `None` for probes that aren't plugged in.
+    Reading {
+        temps: [Option<f32>; 4],
+        alarms: [bool; 4],
+    },
+    /// The thermometer reported a probe's alarm threshold. Both limits are `None` if there is no alarm set.
+    Threshold {
+        probe: u8,
+        min: Option<f32>,
+        max: Option<f32>,
+    },
+    /// The thermometer started or stopped alarming for a probe.
+    Alarm {
+        probe: u8,
+        raised: bool,
+    },
+    /// One of the host alarms changed.
+    HostAlarm {
+        name: String,
+        kind: String,
+    },
+    /// A command sent to the thermometer.
+    Command {
+        hex: String,
+        summary: String,
+    },
+}
+
+/// A summary of a recorded session, as listed by `SessionStore::list`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionInfo {
+    pub name: String,
+    pub started_ms: Option<u64>,
+    /// When the last thing was recorded.
+    pub last_ms: Option<u64>,
+    pub readings: usize,
+    /// False if the session was never stopped - for example because the process crashed - so it can be resumed.
+    pub stopped: bool,
+}
+
+/// A directory of recorded cook sessions, one append-only JSON lines file per session.
+///
+/// Every record is written as a single line and flushed straight away, so after a crash at most the line being
+/// written is lost. Reading skips any line that can't be parsed.
+#[derive(Clone, Debug)]
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    /// Use the sessions in `dir`, creating it if need be.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, SessionError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Every session in the store, ordered by name.
+    pub fn list(&self) -> Result<Vec<SessionInfo>, SessionError> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == EXTENSION) {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        names.iter().map(|name| self.info(name)).collect()
+    }
+
+    /// Summarise the session called `name`.
+    pub fn info(&self, name: &str) -> Result<SessionInfo, SessionError> {
+        let records = self.read(name)?;
+        let mut info = SessionInfo {
+            name: name.to_string(),
+            started_ms: None,
+            last_ms: records.last().map(|r| r.at_ms),
+            readings: 0,
+            stopped: false,
+        };
+        for record in &records {
+            match record.entry {
+                SessionEntry::Started { .. } => info.started_ms = Some(record.at_ms),
+                SessionEntry::Reading { .. } => info.readings += 1,
+                SessionEntry::Resumed => info.stopped = false,
+                SessionEntry::Stopped => info.stopped = true,
+                _ => {}
+            }
+        }
+        Ok(info)
+    }
+
+    /// Everything recorded in the session called `name`, oldest first.
+    pub fn read(&self, name: &str) -> Result<Vec<SessionRecord>, SessionError> {
+        let file = File::open(self.path(name)?).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => SessionError::NotFound(name.to_string()),
+            _ => SessionError::Io(e),
+        })?;
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(record) => records.push(record),
+                // Most likely the last line, cut short by a crash.
+                Err(e) => warn!("Skipping unreadable line in session {}: {}", name, e),
+            }
+        }
+        Ok(records)
+    }
+
+    /// Write the readings from the session called `name` as CSV, with a row per reading: the time in milliseconds
+    /// since the Unix epoch, each probe's temperature (empty if unplugged), and then whether each probe was alarming.
+    pub fn export_csv(&self, name: &str, mut out: impl Write) -> Result<(), SessionError> {
+        writeln!(
+            out,
+            "time_ms,probe_1,probe_2,probe_3,probe_4,alarm_1,alarm_2,alarm_3,alarm_4"
+        )?;
+        for record in self.read(name)? {
+            if let SessionEntry::Reading { temps, alarms } = record.entry {
+                let temps = temps.map(|t| t.map(|t| format!("{t:.1}")).unwrap_or_default());
+                write!(out, "{},{}", record.at_ms, temps.join(","))?;
+                for alarm in alarms {
+                    write!(out, ",{}", alarm as u8)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a new session called `name`, ready to record into.
+    pub fn start(&self, name: &str) -> Result<SessionWriter, SessionError> {
+        let path = self.path(name)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => SessionError::AlreadyExists(name.to_string()),
+                _ => SessionError::Io(e),
+            })?;
+        let mut writer = SessionWriter::new(name, file);
+        writer.write(SessionEntry::Started {
+            name: name.to_string(),
+        })?;
+        Ok(writer)
+    }
+
+    /// Carry on recording into the existing session called `name`.
+    pub fn resume(&self, name: &str) -> Result<SessionWriter, SessionError> {
+        let path = self.path(name)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => SessionError::NotFound(name.to_string()),
+                _ => SessionError::Io(e),
+            })?;
+        let mut writer = SessionWriter::new(name, file);
+        // Make sure the new records start on a line of their own, even if the last one was cut short.
+        writer.file.write_all(b"\n")?;
+        writer.write(SessionEntry::Resumed)?;
+        Ok(writer)
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, SessionError> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'));
+        if !valid {
+            return Err(SessionError::InvalidName(name.to_string()));
+        }
+        Ok(self.dir.join(format!("{name}.{EXTENSION}")))
+    }
+}
+
+/// Appends records to a session file.
+pub struct SessionWriter {
+    name: String,
+    file: BufWriter<File>,
+}
+
+impl SessionWriter {
+    fn new(name: &str, file: File) -> Self {
+        Self {
+            name: name.to_string(),
+            file: BufWriter::new(file),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Record `entry` as happening now.
+    pub fn write(&mut self, entry: SessionEntry) -> Result<(), SessionError> {
+        self.write_at(entry, SystemTime::now())
+    }
+
+    pub fn write_at(&mut self, entry: SessionEntry, at: SystemTime) -> Result<(), SessionError> {
+        let record = SessionRecord {
+            at_ms: at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            entry,
+        };
+        serde_json::to_writer(&mut self.file, &record).map_err(std::io::Error::from)?;
+        self.file.write_all(b"\n")?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Record that the session has been stopped.
+    pub fn stop(mut self) -> Result<(), SessionError> {
+        self.write(SessionEntry::Stopped)
+    }
+}
+
+/// Records what happens to a controller into whichever session is current. A clone can start and stop sessions while
+/// another runs the recorder.
+#[derive(Clone)]
+pub struct SessionRecorder {
+    controller: ControllerHandle,
+    store: SessionStore,
+    current: Arc<Mutex<Option<SessionWriter>>>,
+}
+
+impl SessionRecorder {
+    pub fn new(controller: ControllerHandle, store: SessionStore) -> Self {
+        Self {
+            controller,
+            store,
+            current: Arc::default(),
+        }
+    }
+
+    pub fn store(&self) -> &SessionStore {
+        &self.store
+    }
+
+    /// The name of the session being recorded, if any.
+    pub fn current(&self) -> Option<String> {
+        self.current
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|w| w.name().to_string())
+    }
+
+    /// Start recording a new session called `name`.
+    pub fn start(&self, name: &str) -> Result<(), SessionError> {
+        self.begin(|store| store.start(name))
+    }
+
+    /// Carry on recording the existing session called `name`, for example after a crash. `SessionStore::list` shows
+    /// which sessions were never stopped.
+    pub fn resume(&self, name: &str) -> Result<(), SessionError> {
+        self.begin(|store| store.resume(name))
+    }
+
+    /// Stop recording the current session. Returns the session's name, or `None` if nothing was being recorded.
+    pub fn stop(&self) -> Result<Option<String>, SessionError> {
+        let Some(writer) = self.current.lock().unwrap().take() else {
+            return Ok(None);
+        };
+        let name = writer.name().to_string();
+        writer.stop()?;
+        Ok(Some(name))
+    }
+
+    /// Record events until the controller is shut down. The current session is left as it is, so it can be resumed
+    /// next time.
+    pub async fn run(mut self) {
+        let controller = self.controller.clone();
+        controller.follow_events(&mut self).await
+    }
+
+    fn begin(
+        &self,
+        open: impl FnOnce(&SessionStore) -> Result<SessionWriter, SessionError>,
+    ) -> Result<(), SessionError> {
+        let mut current = self.current.lock().unwrap();
+        if let Some(writer) = current.as_ref() {
+            return Err(SessionError::AlreadyRecording(writer.name().to_string()));
+        }
+        *current = Some(open(&self.store)?);
+        Ok(())
+    }
+
+    fn record(&self, entry: SessionEntry, at: SystemTime) {
+        if let Some(writer) = self.current.lock().unwrap().as_mut() {
+            if let Err(e) = writer.write_at(entry, at) {
+                warn!("Unable to record to session {}: {}", writer.name(), e);
+            }
+        }
+    }
+}
+
+impl EventFollower for SessionRecorder {
+    const NAME: &'static str = "Session recorder";
+
+    fn on_event(&mut self, event: ControllerEvent) {
+        if let Some((entry, at)) = entry_for_event(event) {
+            self.record(entry, at);
+        }
+    }
+}
+
+/// What to record for `event`, and when it happened. Most notifications are left out, as their effects are recorded
+/// through the device events they cause.
+fn entry_for_event(event: ControllerEvent) -> Option<(SessionEntry, SystemTime)> {
+    match event {
+        ControllerEvent::Transfer(Transfer::Notification(n)) => {
+            let Ok(Decoded::Temperatures(data)) = n.decoded else {
+                return None;
+            };
+            let entry = SessionEntry::Reading {
+                temps: data.temps.map(|t| match t.temp {
+                    DeviceTemperature::InRange(t) => Some(f32::from(t)),
+                    DeviceTemperature::OutOfRange => None,
+                }),
+                alarms: data.temps.map(|t| t.alarm),
+            };
+            Some((entry, SystemTime::now()))
+        }
+        ControllerEvent::Transfer(Transfer::Command(command)) => Some((
+            SessionEntry::Command {
+                hex: command.raw.iter().map(|b| format!("{b:02x}")).collect(),
+                summary: format!("{:?}", command.decoded),
+            },
+            SystemTime::now(),
+        )),
+        ControllerEvent::Device(event) => {
+            let entry = match event.kind {
+                DeviceEventKind::Connected => SessionEntry::Connected,
+                DeviceEventKind::Disconnected => SessionEntry::Disconnected,
+                DeviceEventKind::AlarmRaised(idx) => SessionEntry::Alarm {
+                    probe: idx.as_one_based(),
+                    raised: true,
+                },
+                DeviceEventKind::AlarmCleared(idx) => SessionEntry::Alarm {
+                    probe: idx.as_one_based(),
+                    raised: false,
+                },
+                DeviceEventKind::ThresholdChanged(idx, threshold) => {
+                    let (min, max) = match threshold {
+                        AlarmThreshold::NoneSet => (None, None),
+                        AlarmThreshold::UpperLimit(u) => (None, Some(f32::from(u.max))),
+                        AlarmThreshold::RangeLimit(r) => {
+                            (Some(f32::from(r.min)), Some(f32::from(r.max)))
+                        }
+                    };
+                    SessionEntry::Threshold {
+                        probe: idx.as_one_based(),
+                        min,
+                        max,
+                    }
+                }
+                _ => return None,
+            };
+            Some((entry, event.timestamp))
+        }
+        ControllerEvent::HostAlarm(event) => {
+            let kind = match event.kind {
+                HostAlarmEventKind::Raised => "raised",
+                HostAlarmEventKind::Cleared => "cleared",
+                HostAlarmEventKind::Acknowledged => "acknowledged",
+            };
+            Some((
+                SessionEntry::HostAlarm {
+                    name: event.name,
+                    kind: kind.to_string(),
+                },
+                event.timestamp,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    /// A fresh, empty session store, which is deleted when the returned directory is dropped.
+    fn store() -> (TempDir, SessionStore) {
+        let dir = TempDir::new().unwrap();
+        let store = SessionStore::new(dir.path()).unwrap();
+        (dir, store)
+    }
+
+    fn reading(t: f32) -> SessionEntry {
+        SessionEntry::Reading {
+            temps: [Some(t), None, None, None],
+            alarms: [false, false, false, true],
+        }
+    }
+
+    #[test]
+    fn sessions_are_listed_and_exported() {
+        let (_dir, store) = store();
+        let mut writer = store.start("brisket").unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        writer.write_at(reading(20.5), start).unwrap();
+        writer
+            .write_at(reading(21.0), start + Duration::from_secs(1))
+            .unwrap();
+        writer.stop().unwrap();
+        store.start("ribs").unwrap();
+
+        let sessions = store.list().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].name, "brisket");
+        assert_eq!(sessions[0].readings, 2);
+        assert!(sessions[0].stopped);
+        assert!(!sessions[1].stopped);
+
+        let mut csv = Vec::new();
+        store.export_csv("brisket", &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "time_ms,probe_1,probe_2,probe_3,probe_4,alarm_1,alarm_2,alarm_3,alarm_4\n\
+             1700000000000,20.5,,,,0,0,0,1\n\
+             1700000001000,21.0,,,,0,0,0,1\n"
+        );
+    }
+
+    #[test]
+    fn crashed_sessions_can_be_resumed() {
+        let (_dir, store) = store();
+        let mut writer = store.start("pork").unwrap();
+        writer.write(reading(50.0)).unwrap();
+        drop(writer);
+
+        // A crash part way through writing a line.
+        let path = store.path("pork").unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"at_ms":17000"#).unwrap();
+
+        assert!(!store.info("pork").unwrap().stopped);
+        let mut writer = store.resume("pork").unwrap();
+        writer.write(reading(51.0)).unwrap();
+        writer.stop().unwrap();
+
+        let entries: Vec<_> = store
+            .read("pork")
+            .unwrap()
+            .into_iter()
+            .map(|r| r.entry)
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                SessionEntry::Started {
+                    name: "pork".to_string()
+                },
+                reading(50.0),
+                SessionEntry::Resumed,
+                reading(51.0),
+                SessionEntry::Stopped,
+            ]
+        );
+    }
+
+    #[test]
+    fn names_are_checked() {
+        let (_dir, store) = store();
+        assert_matches!(
+            store.start("../oops").err(),
+            Some(SessionError::InvalidName(_))
+        );
+        assert_matches!(store.start("").err(), Some(SessionError::InvalidName(_)));
+        store.start("Sunday lunch").unwrap();
+        assert_matches!(
+            store.start("Sunday lunch").err(),
+            Some(SessionError::AlreadyExists(_))
+        );
+        assert_matches!(
+            store.resume("Monday").err(),
+            Some(SessionError::NotFound(_))
+        );
+    }
+}
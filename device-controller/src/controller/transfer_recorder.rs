@@ -0,0 +1,258 @@
+use crate::controller::handle::{ControllerEvent, ControllerHandle, EventFollower};
+use crate::peripheral::capture::CaptureRecord;
+use crate::peripheral::transfer::Transfer;
+use log::warn;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+const EXTENSION: &str = "jsonl";
+
+#[derive(Clone, Copy, Debug)]
+pub struct CaptureSettings {
+    /// Start a new file once the current one would grow beyond this many bytes.
+    pub max_file_size: u64,
+    /// Delete the oldest files once there are more than this many. `None` keeps them all.
+    pub max_files: Option<usize>,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            max_file_size: 10 * 1024 * 1024,
+            max_files: None,
+        }
+    }
+}
+
+/// Writes transfers to a series of [capture](crate::peripheral::capture) files called `<prefix>.<number>.jsonl`,
+/// starting a new one when the current one gets too big. Each file is a capture in its own right, with times measured
+/// from when it was started, so any of them can be replayed.
+pub struct CaptureWriter {
+    dir: PathBuf,
+    prefix: String,
+    settings: CaptureSettings,
+    /// The number of the newest file.
+    number: u32,
+    file: BufWriter<File>,
+    size: u64,
+    started: Instant,
+}
+
+impl CaptureWriter {
+    /// Start a new file in `dir`, numbered after any there already.
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        prefix: &str,
+        settings: CaptureSettings,
+    ) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let number = numbers(&dir, prefix)?.last().map_or(0, |n| n + 1);
+        let file = create(&dir, prefix, number)?;
+        Ok(Self {
+            dir,
+            prefix: prefix.to_string(),
+            settings,
+            number,
+            file,
+            size: 0,
+            started: Instant::now(),
+        })
+    }
+
+    /// The file being written to.
+    pub fn path(&self) -> PathBuf {
+        path(&self.dir, &self.prefix, self.number)
+    }
+
+    /// Record `transfer` as happening now.
+    pub fn write(&mut self, transfer: &Transfer) -> std::io::Result<()> {
+        self.write_at(transfer, Instant::now(), SystemTime::now())
+    }
+
+    pub fn write_at(
+        &mut self,
+        transfer: &Transfer,
+        at: Instant,
+        wall: SystemTime,
+    ) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(&self.record(transfer, at, wall))?;
+        line.push(b'\n');
+
+        if self.size > 0 && self.size + line.len() as u64 > self.settings.max_file_size {
+            self.rotate(at)?;
+            line = serde_json::to_vec(&self.record(transfer, at, wall))?;
+            line.push(b'\n');
+        }
+
+        self.file.write_all(&line)?;
+        self.file.flush()?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn record(&self, transfer: &Transfer, at: Instant, wall: SystemTime) -> CaptureRecord {
+        let t_ms = at.saturating_duration_since(self.started).as_millis() as u64;
+        CaptureRecord {
+            wall_ms: wall
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_millis() as u64),
+            ..CaptureRecord::for_transfer(t_ms, transfer)
+        }
+    }
+
+    fn rotate(&mut self, at: Instant) -> std::io::Result<()> {
+        self.number += 1;
+        self.file = create(&self.dir, &self.prefix, self.number)?;
+        self.size = 0;
+        self.started = at;
+
+        if let Some(max_files) = self.settings.max_files {
+            let numbers = numbers(&self.dir, &self.prefix)?;
+            let excess = numbers.len().saturating_sub(max_files.max(1));
+            for n in &numbers[..excess] {
+                std::fs::remove_file(path(&self.dir, &self.prefix, *n))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn path(dir: &Path, prefix: &str, number: u32) -> PathBuf {
+    dir.join(format!("{prefix}.{number:04}.{EXTENSION}"))
+}
+
+fn create(dir: &Path, prefix: &str, number: u32) -> std::io::Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path(dir, prefix, number))?;
+    Ok(BufWriter::new(file))
+}
+
+/// The numbers of the capture files in `dir` with `prefix`, in order.
+fn numbers(dir: &Path, prefix: &str) -> std::io::Result<Vec<u32>> {
+    let mut numbers = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let number: Option<u32> = name
+            .to_str()
+            .and_then(|n| n.strip_prefix(prefix))
+            .and_then(|n| n.strip_prefix('.'))
+            .and_then(|n| n.strip_suffix(EXTENSION))
+            .and_then(|n| n.strip_suffix('.'))
+            .and_then(|n| n.parse().ok());
+        numbers.extend(number);
+    }
+    numbers.sort();
+    Ok(numbers)
+}
+
+/// Records every transfer to and from a controller's thermometer to capture files, for the reverse engineering tools
+/// and the [replay](crate::peripheral::replay) peripheral.
+pub struct TransferRecorder {
+    controller: ControllerHandle,
+    writer: CaptureWriter,
+}
+
+impl TransferRecorder {
+    pub fn new(controller: ControllerHandle, writer: CaptureWriter) -> Self {
+        Self { controller, writer }
+    }
+
+    /// Record transfers until the controller is shut down.
+    pub async fn run(mut self) {
+        let controller = self.controller.clone();
+        controller.follow_events(&mut self).await
+    }
+}
+
+impl EventFollower for TransferRecorder {
+    const NAME: &'static str = "Transfer recorder";
+
+    fn on_event(&mut self, event: ControllerEvent) {
+        if let ControllerEvent::Transfer(transfer) = event {
+            if let Err(e) = self.writer.write(&transfer) {
+                warn!(
+                    "Unable to record transfer to {}: {}",
+                    self.writer.path().display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripheral::capture::{read_capture_file, Direction};
+    use crate::peripheral::command::build_alarm_ack_cmd;
+    use crate::peripheral::notification::Notification;
+    use bytes::Bytes;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn writes_timestamped_transfers() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let mut writer = CaptureWriter::new(dir, "cook", CaptureSettings::default()).unwrap();
+        let start = writer.started;
+        let wall = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        writer
+            .write_at(&Transfer::Command(build_alarm_ack_cmd()), start, wall)
+            .unwrap();
+        let notification = Notification::from(Bytes::from_static(&[0x27, 0x00, 0x27]));
+        writer
+            .write_at(
+                &Transfer::Notification(notification),
+                start + Duration::from_millis(84),
+                wall + Duration::from_millis(84),
+            )
+            .unwrap();
+
+        assert_eq!(writer.path(), dir.join("cook.0000.jsonl"));
+        let records = read_capture_file(writer.path()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].dir, Direction::Command);
+        assert_eq!(records[0].wall_ms, Some(1_700_000_000_000));
+        assert_eq!(records[0].summary.as_deref(), Some("AlarmAck"));
+        assert_eq!(records[1].t_ms, 84);
+        assert_eq!(records[1].hex, "270027");
+        assert_eq!(records[1].summary.as_deref(), Some("AlarmAck"));
+
+        // Starting again carries on numbering from there.
+        let writer = CaptureWriter::new(dir, "cook", CaptureSettings::default()).unwrap();
+        assert_eq!(writer.path(), dir.join("cook.0001.jsonl"));
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let settings = CaptureSettings {
+            max_file_size: 200,
+            max_files: Some(2),
+        };
+        let mut writer = CaptureWriter::new(dir, "cook", settings).unwrap();
+        for _ in 0..10 {
+            writer
+                .write(&Transfer::Command(build_alarm_ack_cmd()))
+                .unwrap();
+        }
+
+        let numbers = numbers(dir, "cook").unwrap();
+        assert_eq!(numbers.len(), 2);
+        assert_eq!(writer.path(), path(dir, "cook", numbers[1]));
+        for n in numbers {
+            let file = path(dir, "cook", n);
+            assert!(std::fs::metadata(&file).unwrap().len() <= 200);
+            assert!(!read_capture_file(&file).unwrap().is_empty());
+        }
+    }
+}
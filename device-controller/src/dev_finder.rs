@@ -1,22 +1,548 @@
-#[cfg(not(feature = "dummy_device"))]
 mod btleplug_device_finder;
+mod remote_device_finder;
+mod simulated_device_finder;
 
-#[cfg(not(feature = "dummy_device"))]
-use btleplug_device_finder::get_device;
+use crate::peripheral::capture::{read_capture_file, CaptureRecord};
+use crate::peripheral::interface::{BoxedReceiver, BoxedWriter};
+use crate::peripheral::remote::{RemoteAddress, RemoteOptions};
+use crate::peripheral::replay::{InvalidSpeed, ReplayOptions, ReplaySpeed, ReplayStepper};
+use crate::peripheral::simulator::{Scenario, ScenarioError, Simulator};
+use crate::Error;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
-#[cfg(feature = "dummy_device")]
-mod dummy_device_finder;
+/// The name TP25s advertise themselves with.
+const THERMOPRO_NAME_PATTERN: &str = "^Thermopro$";
 
-#[cfg(feature = "dummy_device")]
-use dummy_device_finder::get_device;
+/// The command line options understood by `DeviceFinder::from_args`, for usage messages.
+pub const FINDER_USAGE: &str =
+    "[--backend btleplug|simulator[:<scenario>]|replay:<capture>[?<options>]|remote:<address>] \
+    [--address <ble address>]...";
 
-use crate::peripheral::interface::{TP25Receiver, TP25Writer};
-use std::error::Error;
+/// Where `Backend::from_spec` gets the secret for a remote backend from, so that it doesn't show up in process listings.
+pub const BRIDGE_SECRET_VAR: &str = "TP25_BRIDGE_SECRET";
 
-pub struct DeviceFinder {}
+/// What the controller talks to.
+#[derive(Clone, Default)]
+pub enum Backend {
+    /// A real thermometer, over Bluetooth.
+    #[default]
+    Btleplug,
+    /// A [simulated](crate::peripheral::simulator) thermometer. It keeps running between connections, so link drops
+    /// and reboots happen when the scenario says.
+    Simulator(Simulator),
+    /// A [replay](crate::peripheral::replay) of a capture. Each connection plays it from the start. In
+    /// [ReplaySpeed::Step] mode, the stepper releases the notifications.
+    Replay(Arc<Vec<CaptureRecord>>, ReplayOptions, ReplayStepper),
+    /// A thermometer attached to another machine, through a [bridge](crate::peripheral::remote).
+    Remote(RemoteOptions),
+}
+
+#[derive(Debug)]
+pub enum BackendError {
+    /// The backend isn't one of `btleplug`, `simulator`, `replay` or `remote`.
+    Unknown(String),
+    Scenario(ScenarioError),
+    Capture(std::io::Error),
+    /// One of the options after a replay's `?` isn't `speed=<factor>`, `step`, `check` or `nowait`.
+    ReplayOption(String),
+    Speed(InvalidSpeed),
+}
+
+impl Display for BackendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendError::Unknown(spec) => write!(
+                f,
+                "unknown backend {spec:?}, expected btleplug, simulator[:<scenario>], replay:<capture> or remote:<address>"
+            ),
+            BackendError::Scenario(e) => write!(f, "{e}"),
+            BackendError::Capture(e) => write!(f, "unable to read capture: {e}"),
+            BackendError::ReplayOption(option) => write!(
+                f,
+                "unknown replay option {option:?}, expected speed=<factor>, step, check or nowait"
+            ),
+            BackendError::Speed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl Backend {
+    /// Choose a backend from a description such as might be given on the command line:
+    ///
+    /// * `btleplug` - a real thermometer.
+    /// * `simulator` - a simulated thermometer playing the default scenario.
+    /// * `simulator:<path>` - a simulated thermometer playing the scenario in a TOML or JSON file.
+    /// * `replay:<path>` - a replay of a capture file, in real time. Options can follow a `?`, separated by `&`:
+    ///   `speed=<factor>` to play back faster, `step` to play back one notification at a time, `check` to fail
+    ///   commands that don't match the capture, and `nowait` to stop notifications waiting for the commands they answer
+    ///   (e.g. `replay:cook.jsonl?speed=10&check`).
+    /// * `remote:<host>:<port>` or `remote:unix:<path>` - a thermometer attached to a bridge. If the bridge needs a
+    ///   secret, it's taken from the environment variable named by [BRIDGE_SECRET_VAR].
+    pub fn from_spec(spec: &str) -> Result<Self, BackendError> {
+        let (kind, arg) = match spec.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (spec, None),
+        };
+        match (kind, arg) {
+            ("btleplug", None) => Ok(Backend::Btleplug),
+            ("simulator", None) => Ok(Backend::Simulator(Simulator::new(Scenario::default()))),
+            ("simulator", Some(path)) => Scenario::load(path)
+                .map(|scenario| Backend::Simulator(Simulator::new(scenario)))
+                .map_err(BackendError::Scenario),
+            ("replay", Some(arg)) => {
+                let (path, options) = match arg.rsplit_once('?') {
+                    Some((path, options)) => (path, replay_options(options)?),
+                    None => (arg, ReplayOptions::default()),
+                };
+                read_capture_file(path)
+                    .map(|records| {
+                        Backend::Replay(Arc::new(records), options, ReplayStepper::default())
+                    })
+                    .map_err(BackendError::Capture)
+            }
+            ("remote", Some(address)) => match RemoteAddress::parse(address) {
+                Some(address) => Ok(Backend::Remote(RemoteOptions {
+                    secret: std::env::var(BRIDGE_SECRET_VAR).ok(),
+                    ..RemoteOptions::new(address)
+                })),
+                None => Err(BackendError::Unknown(spec.to_string())),
+            },
+            _ => Err(BackendError::Unknown(spec.to_string())),
+        }
+    }
+
+    /// A backend for a different thermometer. A simulator only lets its latest connection work, so each thermometer
+    /// gets a simulator of its own. Anything else is simply cloned, so every replayed thermometer shares a stepper.
+    pub fn for_another_device(&self) -> Self {
+        match self {
+            Backend::Simulator(simulator) => Backend::Simulator(simulator.another()),
+            backend => backend.clone(),
+        }
+    }
+
+    /// What releases the notifications, for a replay in [ReplaySpeed::Step] mode.
+    pub fn stepper(&self) -> Option<&ReplayStepper> {
+        match self {
+            Backend::Replay(_, options, stepper) if options.speed == ReplaySpeed::Step => {
+                Some(stepper)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parse the options in a replay spec, such as `speed=10&check`.
+fn replay_options(options: &str) -> Result<ReplayOptions, BackendError> {
+    let mut parsed = ReplayOptions::default();
+    for option in options.split('&') {
+        match option.split_once('=') {
+            Some(("speed", factor)) => {
+                let factor = factor
+                    .parse()
+                    .map_err(|_| BackendError::ReplayOption(option.to_string()))?;
+                parsed.speed = ReplaySpeed::accelerated(factor).map_err(BackendError::Speed)?;
+            }
+            None if option == "step" => parsed.speed = ReplaySpeed::Step,
+            None if option == "check" => parsed.check_commands = true,
+            None if option == "nowait" => parsed.wait_for_commands = false,
+            _ => return Err(BackendError::ReplayOption(option.to_string())),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Why `DeviceFinder::from_args` couldn't make sense of the command line.
+#[derive(Debug)]
+pub enum ArgsError {
+    /// An option was missing its value.
+    MissingValue(String),
+    Backend(BackendError),
+}
+
+impl Display for ArgsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgsError::MissingValue(option) => write!(f, "{option} needs a value"),
+            ArgsError::Backend(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+impl Debug for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Btleplug => write!(f, "Btleplug"),
+            Backend::Simulator(_) => write!(f, "Simulator"),
+            Backend::Replay(records, options, _) => f
+                .debug_struct("Replay")
+                .field("records", &records.len())
+                .field("options", options)
+                .finish(),
+            Backend::Remote(options) => write!(f, "Remote({})", options.address),
+        }
+    }
+}
+
+/// Finds a TP25 to connect to.
+///
+/// By default this connects over Bluetooth to the first device advertising itself as "Thermopro", which may well be the
+/// neighbour's. The fields narrow down which devices are acceptable, or choose a different backend.
+#[derive(Clone, Debug)]
+pub struct DeviceFinder {
+    pub backend: Backend,
+    /// Only connect to thermometers with one of these BLE addresses (e.g. `"AA:BB:CC:DD:EE:FF"`, in either case). If
+    /// empty, any address is acceptable.
+    pub addresses: Vec<String>,
+    /// Only connect to devices whose advertised name matches this.
+    pub name_pattern: Regex,
+    /// Scan on the adapter whose description contains this (e.g. `"hci1"`). If no adapter matches, all adapters are
+    /// scanned. If `None`, all adapters are scanned.
+    pub preferred_adapter: Option<String>,
+    /// Ignore devices whose signal strength is below this, in dBm. Devices that haven't reported a signal strength
+    /// are ignored too.
+    pub min_rssi: Option<i16>,
+}
+
+impl Default for DeviceFinder {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            addresses: Vec::new(),
+            name_pattern: Regex::new(THERMOPRO_NAME_PATTERN).unwrap(),
+            preferred_adapter: None,
+            min_rssi: None,
+        }
+    }
+}
+
+/// A device found by `DeviceFinder::scan`, along with what it advertised.
+#[derive(Clone, Debug, Default)]
+pub struct Candidate {
+    /// Description of the adapter the device was seen on.
+    pub adapter: String,
+    pub address: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    pub tx_power_level: Option<i16>,
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    pub services: Vec<Uuid>,
+}
 
 impl DeviceFinder {
-    pub async fn get_device(&self) -> Result<(impl TP25Receiver, impl TP25Writer), Box<dyn Error>> {
-        get_device().await
+    /// Create a finder that only connects to the thermometer with the given BLE address.
+    pub fn for_address(address: &str) -> Self {
+        Self {
+            addresses: vec![address.to_string()],
+            ..Self::default()
+        }
+    }
+
+    /// Create a finder that uses `backend`, accepting any device it offers.
+    pub fn for_backend(backend: Backend) -> Self {
+        Self {
+            backend,
+            ..Self::default()
+        }
+    }
+
+    /// Make a finder from command line options (without the program name), as described by [FINDER_USAGE]:
+    ///
+    /// * `--backend <spec>` - what to talk to, as given to [Backend::from_spec]. By default it's a thermometer over
+    ///   Bluetooth.
+    /// * `--address <address>` - only connect to the device with this address. Can be given more than once.
+    ///
+    /// Every option takes a value. Any others are handed back, with their values, for the caller to deal with.
+    pub fn from_args(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(Self, Vec<(String, String)>), ArgsError> {
+        let mut finder = Self::default();
+        let mut others = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(value) = args.next() else {
+                return Err(ArgsError::MissingValue(arg));
+            };
+            match arg.as_str() {
+                "--backend" => {
+                    finder.backend = Backend::from_spec(&value).map_err(ArgsError::Backend)?
+                }
+                "--address" => finder.addresses.push(value),
+                _ => others.push((arg, value)),
+            }
+        }
+        Ok((finder, others))
+    }
+
+    /// Wait until an acceptable thermometer is found and connected to. If `cancel` is cancelled first, scanning stops
+    /// and this gives `Error::Shutdown`.
+    pub async fn get_device(
+        &self,
+        cancel: &CancellationToken,
+    ) -> Result<(BoxedReceiver, BoxedWriter), Error> {
+        match &self.backend {
+            Backend::Btleplug => {
+                let (rx, tx) = btleplug_device_finder::get_device(self, cancel).await?;
+                Ok((BoxedReceiver::new(rx), BoxedWriter::new(tx)))
+            }
+            Backend::Simulator(simulator) => {
+                simulated_device_finder::get_simulated_device(simulator, cancel).await
+            }
+            Backend::Replay(records, options, stepper) => {
+                simulated_device_finder::get_replayed_device(records, *options, stepper, cancel)
+            }
+            Backend::Remote(options) => remote_device_finder::get_device(options, cancel).await,
+        }
+    }
+
+    /// Scan for `duration`, and list the acceptable devices seen. Nothing is connected to, so this is a safe way of
+    /// working out which address to put in `addresses`.
+    pub async fn scan(&self, duration: Duration) -> Result<Vec<Candidate>, Error> {
+        match &self.backend {
+            Backend::Btleplug => btleplug_device_finder::scan(self, duration).await,
+            Backend::Simulator(_) | Backend::Replay(..) => Ok(simulated_device_finder::scan(self)),
+            // Which thermometer the bridge connects to is up to the bridge.
+            Backend::Remote(_) => Ok(Vec::new()),
+        }
+    }
+
+    /// Whether a device that advertised itself as `candidate` is one this finder should connect to.
+    pub fn is_acceptable(&self, candidate: &Candidate) -> bool {
+        let address_ok = self.addresses.is_empty()
+            || self
+                .addresses
+                .iter()
+                .any(|a| a.eq_ignore_ascii_case(&candidate.address));
+        let name_ok = candidate
+            .name
+            .as_ref()
+            .is_some_and(|name| self.name_pattern.is_match(name));
+        let rssi_ok = match self.min_rssi {
+            None => true,
+            Some(min_rssi) => candidate.rssi.is_some_and(|rssi| rssi >= min_rssi),
+        };
+
+        address_ok && name_ok && rssi_ok
+    }
+
+    /// Whether the adapter described by `adapter` is the preferred one.
+    pub fn is_preferred_adapter(&self, adapter: &str) -> bool {
+        self.preferred_adapter
+            .as_ref()
+            .is_some_and(|preferred| adapter.contains(preferred.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripheral::capture::Direction;
+    use crate::peripheral::command::build_startup_command;
+    use crate::peripheral::interface::{TP25Receiver, TP25Writer};
+    use crate::peripheral::notification::Decoded;
+    use assert_matches::assert_matches;
+    use std::io::Write;
+    use tokio::time::timeout;
+
+    fn thermopro(address: &str, rssi: Option<i16>) -> Candidate {
+        Candidate {
+            address: address.to_string(),
+            name: Some("Thermopro".to_string()),
+            rssi,
+            ..Candidate::default()
+        }
+    }
+
+    #[test]
+    fn default_accepts_any_thermopro() {
+        let finder = DeviceFinder::default();
+        assert!(finder.is_acceptable(&thermopro("AA:BB:CC:DD:EE:FF", None)));
+        assert!(!finder.is_acceptable(&Candidate {
+            name: Some("Thermopro2".to_string()),
+            ..thermopro("AA:BB:CC:DD:EE:FF", None)
+        }));
+        assert!(!finder.is_acceptable(&Candidate {
+            name: None,
+            ..thermopro("AA:BB:CC:DD:EE:FF", None)
+        }));
+    }
+
+    #[test]
+    fn only_allowed_addresses_are_accepted() {
+        let finder = DeviceFinder {
+            addresses: vec![
+                "aa:bb:cc:dd:ee:01".to_string(),
+                "AA:BB:CC:DD:EE:02".to_string(),
+            ],
+            ..DeviceFinder::default()
+        };
+        assert!(finder.is_acceptable(&thermopro("AA:BB:CC:DD:EE:01", None)));
+        assert!(finder.is_acceptable(&thermopro("AA:BB:CC:DD:EE:02", None)));
+        assert!(!finder.is_acceptable(&thermopro("AA:BB:CC:DD:EE:03", None)));
+    }
+
+    #[test]
+    fn name_pattern_is_used() {
+        let finder = DeviceFinder {
+            name_pattern: Regex::new("^(Thermopro|TP25)").unwrap(),
+            ..DeviceFinder::default()
+        };
+        assert!(finder.is_acceptable(&Candidate {
+            name: Some("TP25 kitchen".to_string()),
+            ..thermopro("AA:BB:CC:DD:EE:FF", None)
+        }));
+        assert!(!finder.is_acceptable(&Candidate {
+            name: Some("Kitchen TP25".to_string()),
+            ..thermopro("AA:BB:CC:DD:EE:FF", None)
+        }));
+    }
+
+    #[test]
+    fn weak_and_unknown_signals_are_rejected() {
+        let finder = DeviceFinder {
+            min_rssi: Some(-70),
+            ..DeviceFinder::default()
+        };
+        assert!(finder.is_acceptable(&thermopro("AA:BB:CC:DD:EE:FF", Some(-70))));
+        assert!(!finder.is_acceptable(&thermopro("AA:BB:CC:DD:EE:FF", Some(-71))));
+        assert!(!finder.is_acceptable(&thermopro("AA:BB:CC:DD:EE:FF", None)));
+    }
+
+    #[test]
+    fn backends_are_chosen_by_spec() {
+        assert_matches!(Backend::from_spec("btleplug"), Ok(Backend::Btleplug));
+        assert_matches!(Backend::from_spec("simulator"), Ok(Backend::Simulator(_)));
+        assert_matches!(
+            Backend::from_spec("simulator:/no/such/scenario.toml"),
+            Err(BackendError::Scenario(ScenarioError::Io(_)))
+        );
+        assert_matches!(
+            Backend::from_spec("replay:/no/such/capture.jsonl"),
+            Err(BackendError::Capture(_))
+        );
+        assert_matches!(
+            Backend::from_spec("remote:grill-pi:2525"),
+            Ok(Backend::Remote(RemoteOptions { address: RemoteAddress::Tcp(a), .. })) if a == "grill-pi:2525"
+        );
+        for spec in ["replay", "btleplug:hci0", "bluetooth", "remote:"] {
+            assert_matches!(Backend::from_spec(spec), Err(BackendError::Unknown(_)));
+        }
+    }
+
+    #[test]
+    fn replay_options_are_parsed() {
+        let capture = tempfile::NamedTempFile::new().unwrap();
+        let spec = |options: &str| format!("replay:{}{}", capture.path().display(), options);
+
+        assert_matches!(
+            Backend::from_spec(&spec("?speed=10&check")),
+            Ok(Backend::Replay(_, options, _)) if options.speed == ReplaySpeed::Accelerated(10.0)
+                && options.check_commands
+                && options.wait_for_commands
+        );
+        assert_matches!(
+            Backend::from_spec(&spec("?step&nowait")),
+            Ok(Backend::Replay(_, options, _)) if options.speed == ReplaySpeed::Step
+                && !options.check_commands
+                && !options.wait_for_commands
+        );
+        assert_matches!(
+            Backend::from_spec(&spec("")),
+            Ok(Backend::Replay(_, options, _)) if options.speed == ReplaySpeed::RealTime
+        );
+        for speed in ["0", "-2", "NaN"] {
+            assert_matches!(
+                Backend::from_spec(&spec(&format!("?speed={speed}"))),
+                Err(BackendError::Speed(_))
+            );
+        }
+        for options in ["?speed=fast", "?loop", "?check=yes", "?"] {
+            assert_matches!(
+                Backend::from_spec(&spec(options)),
+                Err(BackendError::ReplayOption(_))
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stepped_replays_are_released_by_the_backend() {
+        let mut capture = tempfile::NamedTempFile::new().unwrap();
+        for t_ms in [0, 60_000] {
+            let record = CaptureRecord::new(t_ms, Direction::Notification, &[0x27, 0x00, 0x27]);
+            writeln!(capture, "{}", serde_json::to_string(&record).unwrap()).unwrap();
+        }
+        let spec = |options: &str| format!("replay:{}{}", capture.path().display(), options);
+        assert!(Backend::from_spec(&spec("")).unwrap().stepper().is_none());
+
+        let finder = DeviceFinder::for_backend(Backend::from_spec(&spec("?step")).unwrap());
+        let (mut rx, _tx) = finder.get_device(&CancellationToken::new()).await.unwrap();
+        let stepper = finder.backend.stepper().unwrap();
+        stepper.step();
+        rx.get_notification().await.unwrap();
+        assert!(timeout(Duration::from_secs(600), rx.get_notification())
+            .await
+            .is_err());
+        stepper.step();
+        rx.get_notification().await.unwrap();
+    }
+
+    #[test]
+    fn finders_are_made_from_args() {
+        let args = |args: &[&str]| DeviceFinder::from_args(args.iter().map(|a| a.to_string()));
+
+        let (finder, others) = args(&[
+            "--address",
+            "AA:BB:CC:DD:EE:01",
+            "--record",
+            "captures",
+            "--backend",
+            "simulator",
+        ])
+        .unwrap();
+        assert_matches!(finder.backend, Backend::Simulator(_));
+        assert_eq!(finder.addresses, vec!["AA:BB:CC:DD:EE:01"]);
+        assert_eq!(
+            others,
+            vec![("--record".to_string(), "captures".to_string())]
+        );
+
+        assert_matches!(args(&[]), Ok((DeviceFinder { backend: Backend::Btleplug, .. }, others)) if others.is_empty());
+        assert_matches!(
+            args(&["--backend"]),
+            Err(ArgsError::MissingValue(option)) if option == "--backend"
+        );
+        assert_matches!(
+            args(&["--backend", "bluetooth"]),
+            Err(ArgsError::Backend(BackendError::Unknown(_)))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn simulated_backend_connects() {
+        let finder = DeviceFinder::for_backend(Backend::from_spec("simulator").unwrap());
+        let cancel = CancellationToken::new();
+        let (mut rx, tx) = finder.get_device(&cancel).await.unwrap();
+        tx.send_cmd(build_startup_command()).await.unwrap();
+        assert_matches!(
+            rx.get_notification().await.unwrap().decoded,
+            Ok(Decoded::Startup)
+        );
+        assert_eq!(finder.scan(Duration::from_secs(1)).await.unwrap().len(), 1);
+
+        cancel.cancel();
+        assert!(matches!(
+            finder.get_device(&cancel).await,
+            Err(Error::Shutdown)
+        ));
     }
 }
@@ -1,4 +1,7 @@
+use crate::dev_finder::{Candidate, DeviceFinder};
+use crate::peripheral;
 use crate::peripheral::btleplug::{BtleplugReceiver, BtleplugWriter};
+use crate::Error;
 use btleplug::api::{
     Central, CharPropFlags, Characteristic, Manager as _, Peripheral as _, ScanFilter,
     ValueNotification,
@@ -6,72 +9,176 @@ use btleplug::api::{
 use btleplug::platform::{Adapter, Manager, Peripheral};
 use futures::Stream;
 use log::{debug, error, info, trace, warn};
-use std::error::Error;
 use std::pin::Pin;
 use std::time::Duration;
 use tokio::task::JoinSet;
 use tokio::time;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
-fn log_err_and_ret(s: &'static str) -> &'static str {
+fn log_err_and_ret(s: &'static str) -> Error {
     error!("{}", s);
-    s
+    Error::transport(s)
 }
 
-pub async fn get_device() -> Result<(BtleplugReceiver, BtleplugWriter), Box<dyn Error>> {
+pub async fn get_device(
+    finder: &DeviceFinder,
+    cancel: &CancellationToken,
+) -> Result<(BtleplugReceiver, BtleplugWriter), Error> {
     let Ok(manager) = Manager::new().await else {
-        return Err(log_err_and_ret("No adapters found").into());
+        return Err(log_err_and_ret("No adapters found"));
     };
+    let adapter_list = get_adapters(&manager, finder).await?;
+
+    let device = find_device(adapter_list, finder, cancel).await;
+    if cancel.is_cancelled() {
+        return Err(Error::Shutdown);
+    }
+    let device = device.ok_or(Error::transport("Device find failed"))?;
+    let (notify_characteristic, notifications) = subscribe_to_notifications(&device).await?;
+    let device_writer = get_write_characteristic(&device).await?;
+
+    let reader = BtleplugReceiver::new(notifications?);
+    let writer = BtleplugWriter::new(device, device_writer, notify_characteristic);
+
+    Ok((reader, writer))
+}
+
+pub async fn scan(finder: &DeviceFinder, duration: Duration) -> Result<Vec<Candidate>, Error> {
+    let Ok(manager) = Manager::new().await else {
+        return Err(log_err_and_ret("No adapters found"));
+    };
+    let adapter_list = get_adapters(&manager, finder).await?;
+
+    info!("Scanning for {:?}...", duration);
+    for (i, (adapter, _)) in adapter_list.iter().enumerate() {
+        if let Err(e) = adapter.start_scan(ScanFilter::default()).await {
+            stop_scans(&adapter_list[..i]).await;
+            return Err(e.into());
+        }
+    }
+    time::sleep(duration).await;
+
+    let candidates = collect_candidates(finder, &adapter_list).await;
+    stop_scans(&adapter_list).await;
+    candidates
+}
+
+async fn collect_candidates(
+    finder: &DeviceFinder,
+    adapter_list: &[(Adapter, String)],
+) -> Result<Vec<Candidate>, Error> {
+    let mut candidates = Vec::new();
+    for (adapter, adapter_name) in adapter_list {
+        for peripheral in adapter.peripherals().await? {
+            if let Some(candidate) = get_candidate(&peripheral, adapter_name).await {
+                if finder.is_acceptable(&candidate) {
+                    candidates.push(candidate);
+                }
+            }
+        }
+    }
+    Ok(candidates)
+}
+
+/// Stop scanning on every adapter given, so an error part way through a scan doesn't leave any of them running.
+async fn stop_scans(adapter_list: &[(Adapter, String)]) {
+    for (adapter, adapter_name) in adapter_list {
+        if adapter.stop_scan().await.is_err() {
+            warn!("Couldn't stop scanning on adapter {:?}", adapter_name);
+        }
+    }
+}
+
+/// Get the adapters to scan, along with their descriptions. This is just the preferred adapter if there is one,
+/// otherwise it's every adapter.
+async fn get_adapters(
+    manager: &Manager,
+    finder: &DeviceFinder,
+) -> Result<Vec<(Adapter, String)>, Error> {
     let Ok(adapter_list) = manager.adapters().await else {
-        return Err(log_err_and_ret("No adapters found").into());
+        return Err(log_err_and_ret("No adapters found"));
     };
     if adapter_list.is_empty() {
-        return Err(log_err_and_ret("No Bluetooth adapters found").into());
+        return Err(log_err_and_ret("No Bluetooth adapters found"));
     }
 
-    let device = find_device(adapter_list)
-        .await
-        .ok_or("Device find failed")?;
-    let notifications = subscribe_to_notifications(&device).await?;
-    let device_writer = get_write_characteristic(&device).await?;
+    let mut described = Vec::new();
+    for adapter in adapter_list {
+        let adapter_name = adapter
+            .adapter_info()
+            .await
+            .unwrap_or("<Unknown>".to_string());
+        described.push((adapter, adapter_name));
+    }
 
-    let reader = BtleplugReceiver::new(notifications?);
-    let writer = BtleplugWriter::new(device, device_writer);
+    if described
+        .iter()
+        .any(|(_, adapter_name)| finder.is_preferred_adapter(adapter_name))
+    {
+        described.retain(|(_, adapter_name)| finder.is_preferred_adapter(adapter_name));
+    } else if let Some(preferred) = &finder.preferred_adapter {
+        warn!(
+            "Preferred adapter {:?} not found, using all adapters",
+            preferred
+        );
+    }
 
-    Ok((reader, writer))
+    Ok(described)
 }
 
 // Returning None here implies an actual error has occurred, as we will wait forever to find a device.
-async fn find_device(adapter_list: Vec<Adapter>) -> Option<Peripheral> {
+async fn find_device(
+    adapter_list: Vec<(Adapter, String)>,
+    finder: &DeviceFinder,
+    cancel: &CancellationToken,
+) -> Option<Peripheral> {
     let mut tasks = JoinSet::new();
+    let stop_scanning = cancel.child_token();
 
     // TODO: There's a bug here... we start scanning on all adapters, but if a scan *fails* (as opposed to just not
     // finding a device) then it will return - which will trigger `tasks.join_next` to return a None result, and we'll
     // assume the whole device scan has failed.
 
     info!("Starting scan...");
-    for adapter in adapter_list.iter() {
-        let _ = tasks.spawn(find_device_from_adapter(adapter.clone()));
+    for (adapter, adapter_name) in adapter_list {
+        let _ = tasks.spawn(find_device_from_adapter(
+            adapter,
+            adapter_name,
+            finder.clone(),
+            stop_scanning.clone(),
+        ));
     }
     debug!("All adapter scan tasks spawned");
 
-    match tasks.join_next().await {
+    let found = match tasks.join_next().await {
         Some(Ok(Some(p))) => {
             info!("Found device");
             Some(p)
         }
+        _ if cancel.is_cancelled() => {
+            info!("Scan cancelled");
+            None
+        }
         _ => {
             warn!("Failed to find device");
             None
         }
-    }
+    };
+
+    // Give the other adapters a chance to stop scanning too.
+    stop_scanning.cancel();
+    while tasks.join_next().await.is_some() {}
+
+    found
 }
 
-async fn find_device_from_adapter(adapter: Adapter) -> Option<Peripheral> {
-    let adapter_name = adapter
-        .adapter_info()
-        .await
-        .unwrap_or("<Unknown>".to_string());
+async fn find_device_from_adapter(
+    adapter: Adapter,
+    adapter_name: String,
+    finder: DeviceFinder,
+    stop_scanning: CancellationToken,
+) -> Option<Peripheral> {
     debug!("Scanning on adapter {:?}", adapter_name);
 
     if adapter.start_scan(ScanFilter::default()).await.is_err() {
@@ -82,6 +189,21 @@ async fn find_device_from_adapter(adapter: Adapter) -> Option<Peripheral> {
         return None;
     }
 
+    let found = tokio::select! {
+        found = scan_adapter(&adapter, &adapter_name, &finder) => found,
+        _ = stop_scanning.cancelled() => None,
+    };
+    if adapter.stop_scan().await.is_err() {
+        warn!("Couldn't stop scanning on adapter {:?}", adapter_name);
+    }
+    found
+}
+
+async fn scan_adapter(
+    adapter: &Adapter,
+    adapter_name: &str,
+    finder: &DeviceFinder,
+) -> Option<Peripheral> {
     loop {
         // TODO: Is it necessary to sleep at the beginning here?
         // I can't remember if there was a stability issue from not sleeping.
@@ -96,7 +218,7 @@ async fn find_device_from_adapter(adapter: Adapter) -> Option<Peripheral> {
 
         // `peripherals` is all peripheral devices in range at this time.
         for peripheral in peripherals.iter() {
-            if check_peripheral(peripheral).await {
+            if check_peripheral(peripheral, adapter_name, finder).await {
                 debug!("Found acceptable device on adapter {:?}", adapter_name);
                 return Some(peripheral.clone());
             }
@@ -104,12 +226,34 @@ async fn find_device_from_adapter(adapter: Adapter) -> Option<Peripheral> {
     }
 }
 
-async fn check_peripheral(peripheral: &Peripheral) -> bool {
+/// Read what `peripheral` has advertised. Returns `None` if that's not possible.
+async fn get_candidate(peripheral: &Peripheral, adapter_name: &str) -> Option<Candidate> {
     let Ok(Some(properties)) = peripheral.properties().await else {
         warn!(
             "Could not retrieve properties from BLE device \"{:?}\"",
             peripheral
         );
+        return None;
+    };
+
+    Some(Candidate {
+        adapter: adapter_name.to_string(),
+        address: properties.address.to_string(),
+        name: properties.local_name,
+        rssi: properties.rssi,
+        tx_power_level: properties.tx_power_level,
+        manufacturer_data: properties.manufacturer_data,
+        service_data: properties.service_data,
+        services: properties.services,
+    })
+}
+
+async fn check_peripheral(
+    peripheral: &Peripheral,
+    adapter_name: &str,
+    finder: &DeviceFinder,
+) -> bool {
+    let Some(candidate) = get_candidate(peripheral, adapter_name).await else {
         return false;
     };
 
@@ -117,16 +261,13 @@ async fn check_peripheral(peripheral: &Peripheral) -> bool {
     // more time, lower down.
     let is_connected = peripheral.is_connected().await.unwrap_or(false);
 
-    let local_name = properties
-        .local_name
-        .unwrap_or(String::from("(peripheral name unknown)"));
     debug!(
-        "Checking peripheral {:?}. Connected? {:?}",
-        &local_name, is_connected
+        "Checking peripheral {:?} ({}). Connected? {:?}",
+        &candidate.name, &candidate.address, is_connected
     );
     // Check if it's the peripheral we want.
-    if is_relevant_name(local_name.as_str()) {
-        debug!("Peripheral name {:?} matches...", &local_name);
+    if finder.is_acceptable(&candidate) {
+        debug!("Peripheral {:?} matches...", &candidate.name);
         if !is_connected {
             trace!("Not connected, attempting to connect");
             // Connect if we aren't already connected.
@@ -151,15 +292,8 @@ async fn check_peripheral(peripheral: &Peripheral) -> bool {
     }
 }
 
-const THERMOPRO_NAME: &str = "Thermopro";
-
-/// UUID of the characteristic for which we should subscribe to notifications.
-const WRITE_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x1086fff1_3343_4817_8bb2_b32206336ce8);
-const NOTIFY_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x1086fff2_3343_4817_8bb2_b32206336ce8);
-
-pub fn is_relevant_name(name: &str) -> bool {
-    name == THERMOPRO_NAME
-}
+const WRITE_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(peripheral::WRITE_CHARACTERISTIC_UUID);
+const NOTIFY_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(peripheral::NOTIFY_CHARACTERISTIC_UUID);
 
 pub async fn has_required_characteristics(device: &Peripheral) -> bool {
     trace!("Discover peripheral services...");
@@ -208,7 +342,7 @@ type BtleNotificationStream =
     btleplug::Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>>;
 pub async fn subscribe_to_notifications(
     device: &Peripheral,
-) -> Result<BtleNotificationStream, Box<dyn Error>> {
+) -> Result<(Characteristic, BtleNotificationStream), Error> {
     // TODO: `discover_services` should already have been done, is it necessary to repeat it?
 
     device.discover_services().await?;
@@ -216,7 +350,7 @@ pub async fn subscribe_to_notifications(
     let notify_characteristic = characteristics
         .iter()
         .find(|c| c.uuid == NOTIFY_CHARACTERISTIC_UUID)
-        .ok_or("Notify characteristic not found")?;
+        .ok_or(Error::transport("Notify characteristic not found"))?;
 
     // TODO: We should already have checked the characteristic, is it necessary to repeat it?
     trace!("Checking characteristic {:?}", notify_characteristic);
@@ -224,7 +358,7 @@ pub async fn subscribe_to_notifications(
     // UUID.
     if !is_notify_characteristic(notify_characteristic) {
         warn!("Notify characteristic is not valid");
-        return Err("Bad characteristics".into());
+        return Err(Error::transport("Bad characteristics"));
     }
 
     info!(
@@ -233,21 +367,19 @@ pub async fn subscribe_to_notifications(
     );
     device.subscribe(notify_characteristic).await?;
 
-    Ok(device.notifications().await)
+    Ok((notify_characteristic.clone(), device.notifications().await))
 }
 
-pub async fn get_write_characteristic(
-    device: &Peripheral,
-) -> Result<Characteristic, Box<dyn Error>> {
+pub async fn get_write_characteristic(device: &Peripheral) -> Result<Characteristic, Error> {
     device.discover_services().await?;
     let characteristics = device.characteristics();
     let write_characteristic = characteristics
         .iter()
         .find(|c| c.uuid == WRITE_CHARACTERISTIC_UUID)
-        .ok_or("Write characteristic not found")?;
+        .ok_or(Error::transport("Write characteristic not found"))?;
 
     if !is_write_characteristic(write_characteristic) {
-        return Err("Bad characteristics".into());
+        return Err(Error::transport("Bad characteristics"));
     }
 
     Ok(write_characteristic.clone())
@@ -0,0 +1,31 @@
+use crate::peripheral::interface::{BoxedReceiver, BoxedWriter};
+use crate::peripheral::remote::{connect, RemoteError, RemoteOptions};
+use crate::Error;
+use log::{info, warn};
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+/// Connect to the thermometer through a bridge, trying again for as long as the bridge can't be reached. Only being
+/// rejected by the bridge, or `cancel` being cancelled, gives up.
+pub async fn get_device(
+    options: &RemoteOptions,
+    cancel: &CancellationToken,
+) -> Result<(BoxedReceiver, BoxedWriter), Error> {
+    loop {
+        info!("Connecting to bridge at {}", options.address);
+        let connected = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return Err(Error::Shutdown),
+            connected = connect(options) => connected,
+        };
+        match connected {
+            Ok((rx, tx)) => return Ok((BoxedReceiver::new(rx), BoxedWriter::new(tx))),
+            Err(e @ RemoteError::Rejected(_)) => return Err(Error::transport(e)),
+            Err(e) => warn!("{}, trying again in {:?}", e, options.retry_interval),
+        }
+        tokio::select! {
+            _ = cancel.cancelled() => return Err(Error::Shutdown),
+            _ = sleep(options.retry_interval) => {},
+        }
+    }
+}
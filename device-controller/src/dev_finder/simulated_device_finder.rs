@@ -0,0 +1,45 @@
+use crate::dev_finder::{Candidate, DeviceFinder};
+use crate::peripheral::capture::CaptureRecord;
+use crate::peripheral::interface::{BoxedReceiver, BoxedWriter};
+use crate::peripheral::replay::{replay_stepped, ReplayOptions, ReplayStepper};
+use crate::peripheral::simulator::Simulator;
+use crate::Error;
+use tokio_util::sync::CancellationToken;
+
+/// Connect to `simulator`, waiting for its link to come back if the scenario has dropped it.
+pub async fn get_simulated_device(
+    simulator: &Simulator,
+    cancel: &CancellationToken,
+) -> Result<(BoxedReceiver, BoxedWriter), Error> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => Err(Error::Shutdown),
+        (rx, tx) = simulator.connect() => Ok((BoxedReceiver::new(rx), BoxedWriter::new(tx))),
+    }
+}
+
+/// Start replaying `records` from the beginning.
+pub fn get_replayed_device(
+    records: &[CaptureRecord],
+    options: ReplayOptions,
+    stepper: &ReplayStepper,
+    cancel: &CancellationToken,
+) -> Result<(BoxedReceiver, BoxedWriter), Error> {
+    if cancel.is_cancelled() {
+        return Err(Error::Shutdown);
+    }
+    let (rx, tx) =
+        replay_stepped(records.to_vec(), options, stepper.clone()).map_err(Error::transport)?;
+    Ok((BoxedReceiver::new(rx), BoxedWriter::new(tx)))
+}
+
+/// There's only one simulated device, and it's always in range.
+pub fn scan(finder: &DeviceFinder) -> Vec<Candidate> {
+    let candidate = Candidate {
+        adapter: "Simulated adapter".to_string(),
+        address: "00:00:00:00:00:00".to_string(),
+        name: Some("Thermopro".to_string()),
+        ..Candidate::default()
+    };
+    Vec::from_iter(Some(candidate).filter(|c| finder.is_acceptable(c)))
+}
@@ -0,0 +1,65 @@
+use crate::peripheral::notification::DecodeError;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+/// Everything that can go wrong while finding or talking to a TP25.
+///
+/// This is `Clone` so that the same error can be reported to everyone who was waiting on the thing that failed.
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// The transport carrying data to and from the thermometer failed - for example, there's no Bluetooth adapter, or a
+    /// write failed.
+    Transport(Arc<dyn std::error::Error + Send + Sync>),
+    /// The thermometer responded, but the response couldn't be decoded.
+    Decode(DecodeError),
+    /// The thermometer replied with an 0xe0 error response.
+    DeviceError,
+    /// The thermometer did not respond in time, even after retrying.
+    Timeout,
+    /// The connection to the thermometer dropped before the request could be completed, or there isn't one.
+    Disconnected,
+    /// The controller has been shut down.
+    Shutdown,
+}
+
+impl Error {
+    /// Make a transport error from anything that can describe it, including a string.
+    pub fn transport(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Error::Transport(Arc::from(e.into()))
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "transport error: {e}"),
+            Error::Decode(e) => write!(f, "unable to decode response: {e}"),
+            Error::DeviceError => write!(f, "thermometer reported an error"),
+            Error::Timeout => write!(f, "timed out waiting for thermometer"),
+            Error::Disconnected => write!(f, "thermometer disconnected"),
+            Error::Shutdown => write!(f, "controller shut down"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e.as_ref()),
+            Error::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<btleplug::Error> for Error {
+    fn from(e: btleplug::Error) -> Self {
+        Error::transport(e)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Error::Decode(e)
+    }
+}
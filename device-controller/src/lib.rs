@@ -1,4 +1,7 @@
 pub mod controller;
 pub mod dev_finder;
+mod error;
 pub mod model;
 pub mod peripheral;
+
+pub use error::Error;
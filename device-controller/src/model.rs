@@ -1,3 +1,6 @@
+pub mod cook_estimate;
 pub mod device;
+pub mod device_info;
 pub mod device_temperature;
+pub mod link_health;
 pub mod probe;
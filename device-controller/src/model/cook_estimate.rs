@@ -0,0 +1,225 @@
+use crate::model::device_temperature::{DeviceTemperature, InRangeDeviceTemperature};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The fewest readings an estimate will be made from.
+const MIN_READINGS: usize = 5;
+
+/// When a probe is expected to reach its target, worked out from how quickly it has been heating up recently.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CookEstimate {
+    pub target: InRangeDeviceTemperature,
+    /// How fast the probe has been heating up, in degrees Celsius per minute.
+    pub degrees_per_minute: f32,
+    /// How long until the target is reached at that rate. `None` if the probe isn't heating up.
+    pub remaining: Option<Duration>,
+    /// The time remaining is likely to be at least this long. `None` if the probe isn't heating up.
+    pub earliest: Option<Duration>,
+    /// The time remaining is likely to be at most this long. `None` if the probe may not be heating up at all.
+    pub latest: Option<Duration>,
+    /// The probe has levelled off short of its target. Large cuts of meat often "stall" like this for hours while
+    /// moisture evaporates from them, so the estimate says little about when they'll be done.
+    pub stalled: bool,
+}
+
+/// How cook estimates are worked out.
+#[derive(Clone, Copy, Debug)]
+pub struct EstimatorSettings {
+    /// How much history the heating rate is worked out from. Longer smooths out noise, but is slower to notice
+    /// changes such as the pit temperature being turned up.
+    pub window: Duration,
+    /// Heating slower than this, in degrees Celsius per minute, counts as a stall.
+    pub stall_rate: f32,
+    /// Meat only stalls once it's hot, so below this temperature slow heating is just a slow start.
+    pub stall_min_temp: f32,
+}
+
+impl Default for EstimatorSettings {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(10 * 60),
+            stall_rate: 0.1,
+            stall_min_temp: 60.0,
+        }
+    }
+}
+
+/// Keeps a probe's recent readings, and estimates when it will reach a target by fitting a straight line to them.
+#[derive(Clone, Debug, Default)]
+pub struct CookEstimator {
+    /// Readings within the window, oldest first, in degrees Celsius.
+    readings: VecDeque<(Instant, f32)>,
+}
+
+impl CookEstimator {
+    /// Record a reading from the probe. An unplugged probe loses its history, as it's probably in something else
+    /// when it comes back.
+    pub fn add_reading(
+        &mut self,
+        at: Instant,
+        temp: DeviceTemperature,
+        settings: &EstimatorSettings,
+    ) {
+        let DeviceTemperature::InRange(t) = temp else {
+            self.readings.clear();
+            return;
+        };
+        self.readings.push_back((at, f32::from(t)));
+        while self
+            .readings
+            .front()
+            .is_some_and(|(first, _)| at.duration_since(*first) > settings.window)
+        {
+            self.readings.pop_front();
+        }
+    }
+
+    /// Estimate when the probe will reach `target`. `None` until there's a quarter of a window of history to go on.
+    pub fn estimate(
+        &self,
+        target: InRangeDeviceTemperature,
+        settings: &EstimatorSettings,
+    ) -> Option<CookEstimate> {
+        let (first_at, _) = *self.readings.front()?;
+        let (last_at, current) = *self.readings.back()?;
+        if self.readings.len() < MIN_READINGS
+            || last_at.duration_since(first_at) < settings.window / 4
+        {
+            return None;
+        }
+
+        // Least squares fit of temperature against time.
+        let points: Vec<(f32, f32)> = self
+            .readings
+            .iter()
+            .map(|(at, t)| (at.duration_since(first_at).as_secs_f32(), *t))
+            .collect();
+        let n = points.len() as f32;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f32>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f32>() / n;
+        let sxx: f32 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        let sxy: f32 = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let slope = sxy / sxx;
+        let intercept = mean_y - slope * mean_x;
+        let residuals: f32 = points
+            .iter()
+            .map(|(x, y)| (y - intercept - slope * x).powi(2))
+            .sum();
+        let slope_error = (residuals / (n - 2.0) / sxx).sqrt();
+
+        let target_temp = f32::from(target);
+        let remaining_at = |rate: f32| time_to_reach(current, target_temp, rate);
+        Some(CookEstimate {
+            target,
+            degrees_per_minute: slope * 60.0,
+            remaining: remaining_at(slope),
+            // Roughly a 95% confidence interval for the rate.
+            earliest: remaining_at(slope + 2.0 * slope_error),
+            latest: remaining_at(slope - 2.0 * slope_error),
+            stalled: current >= settings.stall_min_temp
+                && current < target_temp
+                && slope * 60.0 < settings.stall_rate,
+        })
+    }
+}
+
+/// How long it takes to heat from `current` to `target` at `rate` degrees per second.
+fn time_to_reach(current: f32, target: f32, rate: f32) -> Option<Duration> {
+    if current >= target {
+        Some(Duration::ZERO)
+    } else if rate <= 0.0 {
+        None
+    } else {
+        Duration::try_from_secs_f32((target - current) / rate).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::device_temperature::DeviceTemperature::{InRange, OutOfRange};
+    use assert_matches::assert_matches;
+
+    /// Feed `estimator` a reading every 10 seconds for `minutes`, starting at `start` degrees and changing by
+    /// `per_minute`, with a little noise.
+    fn heat(
+        estimator: &mut CookEstimator,
+        at: &mut Instant,
+        start: f32,
+        per_minute: f32,
+        minutes: u32,
+    ) {
+        let settings = EstimatorSettings::default();
+        for i in 0..minutes * 6 {
+            let noise = [0.0, 0.1, -0.1][i as usize % 3];
+            let t = start + per_minute * i as f32 / 6.0 + noise;
+            estimator.add_reading(*at, InRange(InRangeDeviceTemperature::from(t)), &settings);
+            *at += Duration::from_secs(10);
+        }
+    }
+
+    fn target(t: f32) -> InRangeDeviceTemperature {
+        InRangeDeviceTemperature::from(t)
+    }
+
+    #[test]
+    fn needs_some_history() {
+        let settings = EstimatorSettings::default();
+        let mut estimator = CookEstimator::default();
+        let mut at = Instant::now();
+        heat(&mut estimator, &mut at, 20.0, 1.0, 2);
+        assert_eq!(estimator.estimate(target(60.0), &settings), None);
+
+        heat(&mut estimator, &mut at, 22.0, 1.0, 1);
+        assert!(estimator.estimate(target(60.0), &settings).is_some());
+
+        estimator.add_reading(at, OutOfRange, &settings);
+        assert_eq!(estimator.estimate(target(60.0), &settings), None);
+    }
+
+    #[test]
+    fn estimates_steady_heating() {
+        let settings = EstimatorSettings::default();
+        let mut estimator = CookEstimator::default();
+        heat(&mut estimator, &mut Instant::now(), 20.0, 1.0, 10);
+
+        // About 30 degrees to go, at a degree a minute.
+        let estimate = estimator.estimate(target(60.0), &settings).unwrap();
+        assert!((estimate.degrees_per_minute - 1.0).abs() < 0.05);
+        let remaining = estimate.remaining.unwrap().as_secs_f32() / 60.0;
+        assert!((29.0..32.0).contains(&remaining), "{remaining}");
+        assert!(estimate.earliest.unwrap() <= estimate.remaining.unwrap());
+        assert!(estimate.latest.unwrap() >= estimate.remaining.unwrap());
+        assert!(!estimate.stalled);
+
+        assert_matches!(
+            estimator.estimate(target(25.0), &settings),
+            Some(CookEstimate {
+                remaining: Some(Duration::ZERO),
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn detects_stall() {
+        let settings = EstimatorSettings::default();
+        let mut estimator = CookEstimator::default();
+        let mut at = Instant::now();
+        heat(&mut estimator, &mut at, 40.0, 1.0, 25);
+        heat(&mut estimator, &mut at, 65.0, 0.0, 10);
+
+        let estimate = estimator.estimate(target(95.0), &settings).unwrap();
+        assert!(estimate.stalled);
+        assert_eq!(estimate.latest, None);
+
+        // The same plateau early in the cook is just slow going.
+        let mut estimator = CookEstimator::default();
+        heat(&mut estimator, &mut Instant::now(), 30.0, 0.0, 10);
+        assert!(!estimator.estimate(target(95.0), &settings).unwrap().stalled);
+    }
+}
@@ -1,6 +1,8 @@
+use crate::model::device_info::DeviceInfo;
+use crate::model::link_health::LinkHealth;
 use crate::model::probe::Probe;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum TemperatureMode {
     Celsius,
     Fahrenheit,
@@ -11,4 +13,11 @@ pub struct TP25State {
     pub probes: [Probe; 4],
     pub temperature_mode: Option<TemperatureMode>,
     pub connected: bool,
+    pub device_info: DeviceInfo,
+    /// How many times the thermometer has completed the startup handshake since the controller started.
+    pub sessions: u32,
+    /// How many times the thermometer has announced that it started up on a link that was already set up, which it
+    /// only does after restarting.
+    pub reboots: u32,
+    pub link: LinkHealth,
 }
@@ -0,0 +1,27 @@
+use crate::model::device_temperature::DeviceTemperature;
+use crate::model::probe::AlarmThreshold;
+
+/// A response received during the initial sync: the bytes as they arrived, and what they were decoded to.
+#[derive(Clone, Debug)]
+pub struct SyncResponse<T> {
+    pub raw: Vec<u8>,
+    pub decoded: T,
+}
+
+/// What the thermometer reported during the initial sync, which mirrors the queries the official app makes after
+/// connecting.
+///
+/// Any query that went unanswered is left as `None`.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceInfo {
+    /// Set once every query in the initial sync has been answered.
+    pub sync_complete: bool,
+    /// The response to 0x26, which has an unknown purpose.
+    pub unknown_26: Option<SyncResponse<[u8; 5]>>,
+    /// The response to 0x41, which has an unknown purpose.
+    pub unknown_41: Option<SyncResponse<[u8; 2]>>,
+    /// The response to 0x25.
+    pub alt_temperatures: Option<SyncResponse<[DeviceTemperature; 4]>>,
+    /// The responses to 0x24 for probes 1-6. The TP25 only has four probes, but the app asks about six.
+    pub probe_profiles: [Option<SyncResponse<AlarmThreshold>>; 6],
+}
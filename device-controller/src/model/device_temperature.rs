@@ -70,9 +70,11 @@ impl From<InRangeDeviceTemperature> for f32 {
 
 impl From<f32> for InRangeDeviceTemperature {
     fn from(value: f32) -> InRangeDeviceTemperature {
+        // Round before splitting, so that rounding up can carry into the degrees.
+        let tenths = (value * 10.0).round() as u32;
         InRangeDeviceTemperature {
-            degrees: value as u16,
-            tenths: ((value * 10.0).round() % 10.0) as u8,
+            degrees: (tenths / 10) as u16,
+            tenths: (tenths % 10) as u8,
         }
     }
 }
@@ -101,8 +103,7 @@ mod tests {
 
         let t = t.unwrap();
         let InRange(t2) = t else {
-            assert!(false);
-            return;
+            panic!("Expected an in-range temperature");
         };
 
         assert_eq!(t2.degrees, 34);
@@ -116,6 +117,20 @@ mod tests {
         assert_eq!(t.tenths, 6);
     }
 
+    #[test]
+    fn from_f32_rounds_up_into_the_next_degree() {
+        for value in [57.95_f32, 57.96, 57.97, 57.98, 57.99] {
+            assert_eq!(
+                InRangeDeviceTemperature::from(value),
+                InRangeDeviceTemperature::new(58, 0)
+            );
+        }
+        assert_eq!(
+            InRangeDeviceTemperature::from(57.94_f32),
+            InRangeDeviceTemperature::new(57, 9)
+        );
+    }
+
     #[test]
     fn f32_from_bytes() {
         let t = DeviceTemperature::try_from([0x03u8, 0x46u8]);
@@ -123,8 +138,7 @@ mod tests {
         let t = t.unwrap();
 
         let InRange(t2) = t else {
-            assert!(false);
-            return;
+            panic!("Expected an in-range temperature");
         };
 
         let f: f32 = f32::from(t2);
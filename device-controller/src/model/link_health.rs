@@ -0,0 +1,30 @@
+use crate::Error;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How the connection to the thermometer has been going, for spotting a flaky link.
+#[derive(Clone, Debug, Default)]
+pub struct LinkHealth {
+    /// How many times the controller has tried to find and connect to the thermometer since it started.
+    pub connect_attempts: u32,
+    /// Why the most recent connection ended. `None` until a connection has ended.
+    pub last_disconnect_reason: Option<Error>,
+    /// When the current connection was made. `None` while not connected.
+    pub connected_at: Option<Instant>,
+    /// When a notification was last received, on this or any earlier connection.
+    pub last_notification_at: Option<Instant>,
+    /// Set if the reconnect policy ran out of attempts, so the controller has stopped trying to connect.
+    pub gave_up: bool,
+}
+
+impl LinkHealth {
+    /// How long the current connection has been up, if there is one.
+    pub fn session_uptime(&self) -> Option<Duration> {
+        self.connected_at.map(|t| t.elapsed())
+    }
+
+    /// How long it's been since a notification was received, if one ever has.
+    pub fn time_since_last_notification(&self) -> Option<Duration> {
+        self.last_notification_at.map(|t| t.elapsed())
+    }
+}
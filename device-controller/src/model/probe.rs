@@ -1,6 +1,7 @@
+use crate::model::cook_estimate::CookEstimate;
 use crate::model::device_temperature::{DeviceTemperature, InRangeDeviceTemperature};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct UpperLimitThreshold {
     pub max: InRangeDeviceTemperature,
 }
@@ -13,27 +14,63 @@ pub enum AlarmState {
     Alarm,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct RangeLimitThreshold {
     pub min: InRangeDeviceTemperature,
     pub max: InRangeDeviceTemperature,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum AlarmThreshold {
     NoneSet,
     UpperLimit(UpperLimitThreshold),
     RangeLimit(RangeLimitThreshold),
 }
 
+/// The outcome of setting a probe's alarm profile again after connecting to the thermometer.
+///
+/// The thermometer keeps its profiles while switched off, but doesn't alarm until they have been set again. So the
+/// controller remembers the profiles it has set, and re-sends them each time it connects.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ProfileRearm {
+    /// No profile has been set on this probe, so there is nothing to re-arm.
+    #[default]
+    NotNeeded,
+    /// The profile will be re-armed once the connection is set up.
+    Pending,
+    /// The thermometer still had the profile, and it has been set again so that it alarms.
+    Rearmed,
+    /// The thermometer had lost the profile (or had a different one), so it has been restored.
+    Restored,
+    /// The profile could not be set again, so the thermometer may not alarm.
+    Failed,
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Probe {
     pub temperature: DeviceTemperature,
     pub alarm: AlarmState,
     pub alarm_threshold: Option<AlarmThreshold>,
+    pub profile_rearm: ProfileRearm,
+    /// The temperature the user wants this probe to reach, if different from the alarm threshold's maximum.
+    pub cook_target: Option<InRangeDeviceTemperature>,
+    /// When the probe is expected to reach its target, once there's enough history to tell.
+    pub cook_estimate: Option<CookEstimate>,
+}
+
+impl Probe {
+    /// The temperature the cook estimate is for: the user's target if there is one, otherwise the top of the alarm
+    /// threshold.
+    pub fn estimate_target(&self) -> Option<InRangeDeviceTemperature> {
+        self.cook_target.or(match self.alarm_threshold {
+            Some(AlarmThreshold::UpperLimit(u)) => Some(u.max),
+            Some(AlarmThreshold::RangeLimit(r)) => Some(r.max),
+            _ => None,
+        })
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum ProbeIdx {
     Probe1 = 1,
     Probe2 = 2,
@@ -63,6 +100,7 @@ impl ProbeIdx {
         Self::internal_as_one_based(*self)
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn try_from_zero_based(idx: u8) -> Result<Self, ()> {
         match idx {
             0 => Ok(ProbeIdx::Probe1),
@@ -77,6 +115,7 @@ impl ProbeIdx {
         Self::try_from_zero_based(idx).unwrap()
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn try_from_one_based(idx: u8) -> Result<Self, ()> {
         match idx {
             1 => Ok(ProbeIdx::Probe1),
@@ -1,9 +1,16 @@
-#[cfg(not(feature = "dummy_device"))]
 pub mod btleplug;
+pub mod btsnoop;
+pub mod capture;
 pub mod command;
-
-#[cfg(feature = "dummy_device")]
 pub mod dummy;
 pub mod interface;
 pub mod notification;
+pub mod remote;
+pub mod replay;
+pub mod simulator;
 pub mod transfer;
+
+/// UUID of the characteristic commands are written to.
+pub const WRITE_CHARACTERISTIC_UUID: u128 = 0x1086fff1_3343_4817_8bb2_b32206336ce8;
+/// UUID of the characteristic for which we should subscribe to notifications.
+pub const NOTIFY_CHARACTERISTIC_UUID: u128 = 0x1086fff2_3343_4817_8bb2_b32206336ce8;
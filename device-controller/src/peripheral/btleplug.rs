@@ -1,14 +1,13 @@
 use crate::peripheral::command::Command;
 use crate::peripheral::interface::{TP25Receiver, TP25Writer};
 use crate::peripheral::notification::Notification;
+use crate::Error;
 use btleplug::api::{Characteristic, Peripheral as _, ValueNotification, WriteType};
 use btleplug::platform::Peripheral;
 use bytes::Bytes;
 use futures::Stream;
 use futures::StreamExt;
 use std::pin::Pin;
-use std::time::Duration;
-use tokio::time::timeout;
 
 type BtleNotificationStream = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
 
@@ -23,38 +22,51 @@ impl BtleplugReceiver {
 }
 
 impl TP25Receiver for BtleplugReceiver {
-    async fn get_notification(&mut self) -> Option<Notification> {
-        let Ok(Some(vn)) = timeout(Duration::from_secs(4), self.receiver.next()).await else {
-            return None;
-        };
+    async fn get_notification(&mut self) -> Result<Notification, Error> {
+        // Noticing when the thermometer goes quiet is left to the connection handler's watchdog.
+        let vn = self.receiver.next().await.ok_or(Error::Disconnected)?;
 
         let d: Bytes = vn.value.into();
-        Some(Notification::from(d))
+        Ok(Notification::from(d))
     }
 }
 
 pub struct BtleplugWriter {
     device: Peripheral,
     write_characteristic: Characteristic,
+    notify_characteristic: Characteristic,
 }
 
 impl BtleplugWriter {
-    pub fn new(device: Peripheral, write_characteristic: Characteristic) -> BtleplugWriter {
+    pub fn new(
+        device: Peripheral,
+        write_characteristic: Characteristic,
+        notify_characteristic: Characteristic,
+    ) -> BtleplugWriter {
         BtleplugWriter {
             device,
             write_characteristic,
+            notify_characteristic,
         }
     }
 }
 
 impl TP25Writer for BtleplugWriter {
-    async fn send_cmd(&self, command: Command) -> Result<(), btleplug::Error> {
-        self.device
+    async fn send_cmd(&self, command: Command) -> Result<(), Error> {
+        Ok(self
+            .device
             .write(
                 &self.write_characteristic,
                 command.raw.iter().as_slice(),
                 WriteType::WithoutResponse,
             )
-            .await
+            .await?)
+    }
+
+    async fn disconnect(&self) -> Result<(), Error> {
+        // Try to disconnect even if unsubscribing fails, as that's what frees up the thermometer.
+        let unsubscribed = self.device.unsubscribe(&self.notify_characteristic).await;
+        self.device.disconnect().await?;
+        Ok(unsubscribed?)
     }
 }
@@ -0,0 +1,380 @@
+//! Import of btsnoop HCI logs, such as the `btsnoop_hci.log` Android writes when "Enable Bluetooth HCI snoop log" is
+//! turned on in the developer options, to pick out the conversation between an app and a TP25 as a
+//! [capture](crate::peripheral::capture).
+//!
+//! Only what's needed for that is decoded: ACL data packets (reassembled from fragments), the ATT protocol carried in
+//! them, and within that, writes to and notifications from the TP25's characteristics. The characteristics' handles
+//! are learnt from the GATT discovery the app does after connecting. Logs that start after discovery need the handles
+//! given explicitly.
+
+use crate::peripheral::capture::CaptureRecord;
+use crate::peripheral::command::Command;
+use crate::peripheral::notification::Notification;
+use crate::peripheral::transfer::Transfer;
+use crate::peripheral::{NOTIFY_CHARACTERISTIC_UUID, WRITE_CHARACTERISTIC_UUID};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+const MAGIC: &[u8] = b"btsnoop\0";
+const HEADER_LEN: usize = 16;
+const RECORD_HEADER_LEN: usize = 24;
+
+/// Packets with no framing, the direction and type being given by the record flags.
+const DATALINK_HCI_UNENCAPSULATED: u32 = 1001;
+/// Packets with a leading UART (H4) packet type byte. This is what Android writes.
+const DATALINK_HCI_UART: u32 = 1002;
+const H4_ACL_DATA: u8 = 0x02;
+
+/// Record flags.
+const FLAG_RECEIVED: u32 = 0x01;
+const FLAG_COMMAND_OR_EVENT: u32 = 0x02;
+
+/// btsnoop timestamps are microseconds since midnight at the start of the year 0. This is the Unix epoch.
+const UNIX_EPOCH_MICROS: i64 = 0x00dc_ddb3_0f2f_8000;
+
+/// ACL packet boundary flag for a fragment continuing an L2CAP PDU.
+const ACL_CONTINUATION: u16 = 0b01;
+const L2CAP_ATT_CHANNEL: u16 = 0x0004;
+
+const ATT_READ_BY_TYPE_RESPONSE: u8 = 0x09;
+const ATT_WRITE_REQUEST: u8 = 0x12;
+const ATT_WRITE_COMMAND: u8 = 0x52;
+const ATT_NOTIFICATION: u8 = 0x1b;
+const ATT_INDICATION: u8 = 0x1d;
+/// The length of each entry in a Read By Type response listing characteristics with 128-bit UUIDs: the declaration's
+/// handle, the properties, the value handle, then the UUID.
+const CHARACTERISTIC_128_ENTRY_LEN: usize = 2 + 1 + 2 + 16;
+
+/// The ATT handles of the TP25's characteristics on a connection.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AttHandles {
+    pub write: Option<u16>,
+    pub notify: Option<u16>,
+}
+
+impl AttHandles {
+    /// These handles, falling back to `other` for those not set.
+    fn or(self, other: AttHandles) -> AttHandles {
+        AttHandles {
+            write: self.write.or(other.write),
+            notify: self.notify.or(other.notify),
+        }
+    }
+}
+
+/// Pick the commands sent to a TP25, and the notifications received from it, out of the btsnoop log in `log`. Any
+/// handles in `handles` are used in preference to those found in the log.
+///
+/// `t_ms` counts from the first transfer found. Android logs local time, so `wall_ms` is likely to be out by the time
+/// zone offset.
+pub fn import(log: &[u8], handles: AttHandles) -> std::io::Result<Vec<CaptureRecord>> {
+    let mut discovered: HashMap<u16, AttHandles> = HashMap::new();
+    let mut partial: HashMap<(u16, bool), Vec<u8>> = HashMap::new();
+    let mut first_timestamp = None;
+    let mut records = Vec::new();
+
+    for packet in acl_packets(log)? {
+        let Some(pdu) = reassemble(&mut partial, &packet) else {
+            continue;
+        };
+        let [_, _, cid_low, cid_high, att @ ..] = pdu.as_slice() else {
+            continue;
+        };
+        if u16::from_le_bytes([*cid_low, *cid_high]) != L2CAP_ATT_CHANNEL {
+            continue;
+        }
+
+        let connection_handles = discovered.entry(packet.connection).or_default();
+        let transfer = match att {
+            [ATT_READ_BY_TYPE_RESPONSE, len, entries @ ..]
+                if *len as usize == CHARACTERISTIC_128_ENTRY_LEN =>
+            {
+                for entry in entries.chunks_exact(CHARACTERISTIC_128_ENTRY_LEN) {
+                    let value_handle = u16::from_le_bytes([entry[3], entry[4]]);
+                    match u128::from_le_bytes(entry[5..].try_into().unwrap()) {
+                        WRITE_CHARACTERISTIC_UUID => connection_handles.write = Some(value_handle),
+                        NOTIFY_CHARACTERISTIC_UUID => {
+                            connection_handles.notify = Some(value_handle)
+                        }
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            [ATT_WRITE_REQUEST | ATT_WRITE_COMMAND, low, high, value @ ..]
+                if handles.or(*connection_handles).write
+                    == Some(u16::from_le_bytes([*low, *high])) =>
+            {
+                Transfer::Command(Command::from(Bytes::copy_from_slice(value)))
+            }
+            [ATT_NOTIFICATION | ATT_INDICATION, low, high, value @ ..]
+                if handles.or(*connection_handles).notify
+                    == Some(u16::from_le_bytes([*low, *high])) =>
+            {
+                Transfer::Notification(Notification::from(Bytes::copy_from_slice(value)))
+            }
+            _ => continue,
+        };
+
+        let first = *first_timestamp.get_or_insert(packet.timestamp);
+        // The timestamps come straight from the file, so they could be anything.
+        let t_ms = packet.timestamp.saturating_sub(first).max(0) as u64 / 1000;
+        let wall_ms = packet
+            .timestamp
+            .checked_sub(UNIX_EPOCH_MICROS)
+            .filter(|t| *t >= 0)
+            .map(|t| t as u64 / 1000);
+        records.push(CaptureRecord {
+            wall_ms,
+            ..CaptureRecord::for_transfer(t_ms, &transfer)
+        });
+    }
+
+    Ok(records)
+}
+
+struct AclPacket<'a> {
+    /// Microseconds, as in the log.
+    timestamp: i64,
+    received: bool,
+    connection: u16,
+    boundary: u16,
+    data: &'a [u8],
+}
+
+/// The ACL data packets in a btsnoop log. A truncated final record, as left when the log is copied while it's being
+/// written, is ignored.
+fn acl_packets(log: &[u8]) -> std::io::Result<Vec<AclPacket<'_>>> {
+    let invalid = |e: &str| Error::new(ErrorKind::InvalidData, e.to_string());
+    if log.len() < HEADER_LEN || !log.starts_with(MAGIC) {
+        return Err(invalid("not a btsnoop log"));
+    }
+    let datalink = u32::from_be_bytes(log[12..16].try_into().unwrap());
+    if datalink != DATALINK_HCI_UART && datalink != DATALINK_HCI_UNENCAPSULATED {
+        return Err(invalid(&format!(
+            "unsupported btsnoop datalink type {datalink}"
+        )));
+    }
+
+    let mut packets = Vec::new();
+    let mut rest = &log[HEADER_LEN..];
+    while rest.len() >= RECORD_HEADER_LEN {
+        let field = |i: usize| u32::from_be_bytes(rest[i..i + 4].try_into().unwrap());
+        let included_len = field(4) as usize;
+        let flags = field(8);
+        let timestamp = i64::from_be_bytes(rest[16..24].try_into().unwrap());
+        let Some(data) = rest.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + included_len) else {
+            break;
+        };
+        rest = &rest[RECORD_HEADER_LEN + included_len..];
+
+        let acl = match (datalink, data) {
+            (DATALINK_HCI_UART, [H4_ACL_DATA, acl @ ..]) => acl,
+            (DATALINK_HCI_UNENCAPSULATED, acl) if flags & FLAG_COMMAND_OR_EVENT == 0 => acl,
+            _ => continue,
+        };
+        let [handle_low, handle_high, len_low, len_high, payload @ ..] = acl else {
+            continue;
+        };
+        let handle = u16::from_le_bytes([*handle_low, *handle_high]);
+        let len = u16::from_le_bytes([*len_low, *len_high]) as usize;
+        packets.push(AclPacket {
+            timestamp,
+            received: flags & FLAG_RECEIVED != 0,
+            connection: handle & 0x0fff,
+            boundary: (handle >> 12) & 0b11,
+            data: &payload[..len.min(payload.len())],
+        });
+    }
+    Ok(packets)
+}
+
+/// Add `packet` to the L2CAP PDU being built up for its connection and direction. Returns the PDU once it's complete.
+fn reassemble(partial: &mut HashMap<(u16, bool), Vec<u8>>, packet: &AclPacket) -> Option<Vec<u8>> {
+    let key = (packet.connection, packet.received);
+    let pdu = if packet.boundary == ACL_CONTINUATION {
+        let pdu = partial.get_mut(&key)?;
+        pdu.extend_from_slice(packet.data);
+        pdu
+    } else {
+        partial.insert(key, packet.data.to_vec());
+        partial.get_mut(&key).unwrap()
+    };
+
+    let [len_low, len_high, ..] = pdu.as_slice() else {
+        return None;
+    };
+    let len = u16::from_le_bytes([*len_low, *len_high]) as usize + 4;
+    if pdu.len() < len {
+        return None;
+    }
+    let mut pdu = partial.remove(&key).unwrap();
+    pdu.truncate(len);
+    Some(pdu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripheral::capture::Direction;
+    use assert_matches::assert_matches;
+
+    const CONNECTION: u16 = 0x0040;
+    /// 2024-01-01 00:00:00 UTC.
+    const START: i64 = UNIX_EPOCH_MICROS + 1_704_067_200_000_000;
+
+    fn log(packets: &[(i64, bool, Vec<u8>)]) -> Vec<u8> {
+        let mut log = MAGIC.to_vec();
+        log.extend(1u32.to_be_bytes());
+        log.extend(DATALINK_HCI_UART.to_be_bytes());
+        for (timestamp, received, data) in packets {
+            let len = data.len() as u32;
+            log.extend(len.to_be_bytes());
+            log.extend(len.to_be_bytes());
+            log.extend((*received as u32).to_be_bytes());
+            log.extend(0u32.to_be_bytes());
+            log.extend(timestamp.to_be_bytes());
+            log.extend(data);
+        }
+        log
+    }
+
+    /// H4 framed ACL packets carrying `att`, split into fragments of at most `mtu` bytes.
+    fn acl(att: &[u8], mtu: usize) -> Vec<Vec<u8>> {
+        let mut l2cap = (att.len() as u16).to_le_bytes().to_vec();
+        l2cap.extend(L2CAP_ATT_CHANNEL.to_le_bytes());
+        l2cap.extend(att);
+        l2cap
+            .chunks(mtu)
+            .enumerate()
+            .map(|(i, fragment)| {
+                let boundary = if i == 0 { 0b10 } else { ACL_CONTINUATION };
+                let mut packet = vec![H4_ACL_DATA];
+                packet.extend((CONNECTION | boundary << 12).to_le_bytes());
+                packet.extend((fragment.len() as u16).to_le_bytes());
+                packet.extend(fragment);
+                packet
+            })
+            .collect()
+    }
+
+    fn characteristic(value_handle: u16, uuid: u128) -> Vec<u8> {
+        let mut entry = (value_handle - 1).to_le_bytes().to_vec();
+        entry.push(0x10);
+        entry.extend(value_handle.to_le_bytes());
+        entry.extend(uuid.to_le_bytes());
+        entry
+    }
+
+    fn att(opcode: u8, handle: u16, value: &[u8]) -> Vec<u8> {
+        let mut att = vec![opcode];
+        att.extend(handle.to_le_bytes());
+        att.extend(value);
+        att
+    }
+
+    const STARTUP: [u8; 12] = [
+        0x01, 0x09, 0x70, 0x32, 0xe2, 0xc1, 0x79, 0x9d, 0xb4, 0xd1, 0xc7, 0xb1,
+    ];
+    /// An alarm acknowledgement, padded with junk to 20 bytes.
+    const ALARM_ACK: [u8; 20] = [
+        0x27, 0x00, 0x27, 0x3a, 0x91, 0x00, 0x5c, 0x12, 0x08, 0xee, 0x41, 0x00, 0x7d, 0x02, 0x00,
+        0x19, 0xc4, 0x00, 0x63, 0x8b,
+    ];
+
+    #[test]
+    fn finds_discovered_characteristics() {
+        let mut discovery = vec![
+            ATT_READ_BY_TYPE_RESPONSE,
+            CHARACTERISTIC_128_ENTRY_LEN as u8,
+        ];
+        discovery.extend(characteristic(0x002a, WRITE_CHARACTERISTIC_UUID));
+        discovery.extend(characteristic(0x002d, NOTIFY_CHARACTERISTIC_UUID));
+
+        let mut packets: Vec<_> = acl(&discovery, 27)
+            .into_iter()
+            .map(|p| (START, true, p))
+            .collect();
+        for (at, received, att) in [
+            (
+                START + 1_000,
+                false,
+                att(ATT_WRITE_COMMAND, 0x002a, &STARTUP),
+            ),
+            // Something else on another characteristic.
+            (
+                START + 2_000,
+                false,
+                att(ATT_WRITE_REQUEST, 0x0030, &[0x01, 0x00]),
+            ),
+            (
+                START + 85_000,
+                true,
+                att(ATT_NOTIFICATION, 0x002d, &ALARM_ACK),
+            ),
+        ] {
+            packets.extend(acl(&att, 27).into_iter().map(|p| (at, received, p)));
+        }
+
+        let records = import(&log(&packets), AttHandles::default()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].dir, Direction::Command);
+        assert_eq!(records[0].bytes(), Some(STARTUP.to_vec()));
+        assert_eq!(records[0].summary.as_deref(), Some("Startup"));
+        assert_eq!(records[0].wall_ms, Some(1_704_067_200_001));
+        assert_eq!(records[1].dir, Direction::Notification);
+        assert_eq!(records[1].t_ms, 84);
+        assert_eq!(records[1].bytes(), Some(ALARM_ACK.to_vec()));
+        assert_eq!(records[1].summary.as_deref(), Some("AlarmAck"));
+    }
+
+    #[test]
+    fn uses_given_handles_without_discovery() {
+        let packets: Vec<_> = acl(&att(ATT_NOTIFICATION, 0x002d, &ALARM_ACK), 10)
+            .into_iter()
+            .map(|p| (START, true, p))
+            .collect();
+        let log = log(&packets);
+        assert_eq!(import(&log, AttHandles::default()).unwrap(), vec![]);
+
+        let handles = AttHandles {
+            write: None,
+            notify: Some(0x002d),
+        };
+        let records = import(&log, handles).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].bytes(), Some(ALARM_ACK.to_vec()));
+    }
+
+    #[test]
+    fn copes_with_nonsensical_timestamps() {
+        let handles = AttHandles {
+            write: None,
+            notify: Some(0x002d),
+        };
+        let packets: Vec<_> = [i64::MAX, i64::MIN]
+            .into_iter()
+            .flat_map(|at| {
+                acl(&att(ATT_NOTIFICATION, 0x002d, &ALARM_ACK), 27)
+                    .into_iter()
+                    .map(move |p| (at, true, p))
+            })
+            .collect();
+
+        let records = import(&log(&packets), handles).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].t_ms, 0);
+        assert!(records[0].wall_ms.is_some());
+        assert_eq!(records[1].t_ms, 0);
+        assert_eq!(records[1].wall_ms, None);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_matches!(
+            import(b"{\"t_ms\":0}", AttHandles::default()),
+            Err(e) if e.kind() == ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! The capture file format, used to record and replay conversations with a thermometer.
+//!
+//! A capture is a JSON lines file: one JSON object per line, in the order the transfers happened. For example:
+//!
+//! ```text
+//! {"t_ms":0,"wall_ms":1700000000000,"dir":"command","hex":"01097032e2c1799db4d1c7b1","summary":"Startup"}
+//! {"t_ms":84,"wall_ms":1700000000084,"dir":"notification","hex":"01010a0c00...","summary":"Startup"}
+//! ```
+//!
+//! * `t_ms` - milliseconds since the capture started, measured with a monotonic clock.
+//! * `wall_ms` - optional. The wall clock time, in milliseconds since the Unix epoch.
+//! * `dir` - `"command"` for data sent to the thermometer, `"notification"` for data received from it.
+//! * `hex` - the raw bytes, hex encoded.
+//! * `summary` - optional. A human-readable description, ignored when replaying.
+//!
+//! Blank lines, and lines starting with `#`, are ignored.
+
+use crate::peripheral::transfer::Transfer;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Sent to the thermometer.
+    Command,
+    /// Received from the thermometer.
+    Notification,
+}
+
+/// One transfer in a capture file.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    pub t_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wall_ms: Option<u64>,
+    pub dir: Direction,
+    pub hex: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+impl CaptureRecord {
+    pub fn new(t_ms: u64, dir: Direction, bytes: &[u8]) -> Self {
+        Self {
+            t_ms,
+            wall_ms: None,
+            dir,
+            hex: bytes_to_hex(bytes),
+            summary: None,
+        }
+    }
+
+    /// Record `transfer`, with a summary of what it decodes as.
+    pub fn for_transfer(t_ms: u64, transfer: &Transfer) -> Self {
+        let (dir, raw, summary) = match transfer {
+            Transfer::Command(c) => (Direction::Command, &c.raw, format!("{:?}", c.decoded)),
+            Transfer::Notification(n) => (
+                Direction::Notification,
+                &n.raw,
+                match &n.decoded {
+                    Ok(d) => format!("{d:?}"),
+                    Err(e) => format!("Undecodable: {e}"),
+                },
+            ),
+        };
+        Self {
+            summary: Some(summary),
+            ..Self::new(t_ms, dir, raw)
+        }
+    }
+
+    /// The raw bytes of the transfer, or `None` if `hex` isn't valid hex.
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        hex_to_bytes(&self.hex)
+    }
+}
+
+/// Read a capture. Fails on the first line that isn't a valid record, giving its line number.
+pub fn read_capture(reader: impl BufRead) -> std::io::Result<Vec<CaptureRecord>> {
+    let mut records = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |e: String| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line {}: {}", i + 1, e),
+            )
+        };
+        let record: CaptureRecord =
+            serde_json::from_str(line).map_err(|e| invalid(e.to_string()))?;
+        if record.bytes().is_none() {
+            return Err(invalid(format!("{:?} is not valid hex", record.hex)));
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+pub fn read_capture_file(path: impl AsRef<Path>) -> std::io::Result<Vec<CaptureRecord>> {
+    read_capture(BufReader::new(std::fs::File::open(path)?))
+}
+
+/// Write a capture, one record per line.
+pub fn write_capture(records: &[CaptureRecord], mut writer: impl Write) -> std::io::Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut writer, record)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Format bytes as lower case hex, with no separators.
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse hex with no separators, in either case.
+pub fn hex_to_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 == 1 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn reads_records_skipping_comments() {
+        let capture = "# A short capture\n\
+            {\"t_ms\":0,\"dir\":\"command\",\"hex\":\"270027\"}\n\
+            \n\
+            {\"t_ms\":50,\"wall_ms\":1700000000050,\"dir\":\"notification\",\"hex\":\"270027\",\"summary\":\"Alarm ack\"}\n";
+        let records = read_capture(capture.as_bytes()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0],
+            CaptureRecord::new(0, Direction::Command, &[0x27, 0x00, 0x27])
+        );
+        assert_eq!(records[1].dir, Direction::Notification);
+        assert_eq!(records[1].bytes(), Some(vec![0x27, 0x00, 0x27]));
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let capture = "{\"t_ms\":0,\"dir\":\"command\",\"hex\":\"270027\"}\n\
+            {\"t_ms\":0,\"dir\":\"command\",\"hex\":\"27002\"}\n";
+        assert_matches!(
+            read_capture(capture.as_bytes()),
+            Err(e) if e.to_string().starts_with("line 2:")
+        );
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let record = CaptureRecord {
+            wall_ms: Some(1_700_000_000_000),
+            summary: Some("Temperatures".to_string()),
+            ..CaptureRecord::new(1234, Direction::Notification, &[0x30, 0x0f, 0xab])
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            json,
+            r#"{"t_ms":1234,"wall_ms":1700000000000,"dir":"notification","hex":"300fab","summary":"Temperatures"}"#
+        );
+        assert_eq!(read_capture(json.as_bytes()).unwrap(), vec![record]);
+    }
+}
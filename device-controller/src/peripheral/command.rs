@@ -1,9 +1,9 @@
 use crate::model::device::TemperatureMode;
-use crate::model::probe::{AlarmThreshold, ProbeIdx};
-use crate::peripheral::notification::calc_checksum;
+use crate::model::device_temperature::DeviceTemperature;
+use crate::model::probe::{AlarmThreshold, ProbeIdx, RangeLimitThreshold, UpperLimitThreshold};
 use bytes::Bytes;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum Decoded {
     Startup,
 
@@ -15,49 +15,144 @@ pub enum Decoded {
     #[allow(dead_code)]
     ReportProfile(ProbeIdx),
 
+    /// Report the profile of a probe the TP25 doesn't have (5 or 6), as the official app does.
+    ReportExtraProfile(u8),
+
     #[allow(dead_code)]
     SetProbeProfile(ProbeIdx, AlarmThreshold),
 
     #[allow(dead_code)]
     AlarmAck,
 
+    /// Ask for the same 0x30 temperature report the thermometer pushes by itself.
+    TempReport,
+
+    AltTempReport,
+
+    Unknown26,
+
+    Unknown41,
+
     #[allow(dead_code)]
     Custom(Vec<u8>),
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Command {
     pub raw: Bytes,
     pub decoded: Decoded,
 }
 
+/// Work out what a command seen on the wire is, for example in a packet capture. Anything that isn't exactly what one
+/// of the builders below would produce is `Decoded::Custom`.
+impl From<Bytes> for Command {
+    fn from(raw: Bytes) -> Command {
+        match recognise(&raw) {
+            Some(command) if command.raw == raw => command,
+            _ => build_custom_cmd(raw.to_vec()),
+        }
+    }
+}
+
+/// The command `raw` looks like it was built as, which may not match it exactly.
+fn recognise(raw: &[u8]) -> Option<Command> {
+    let frame = tlvc::decode(raw).ok()?;
+    let command = match (frame.kind, frame.value.as_slice()) {
+        (0x01, _) => build_startup_command(),
+        (0x20, [0x0c]) => build_set_temp_mode_command(TemperatureMode::Celsius),
+        (0x20, [0x0f]) => build_set_temp_mode_command(TemperatureMode::Fahrenheit),
+        (0x23, [index, 0xcc, max_high, max_low, min_high, min_low]) => {
+            let max = DeviceTemperature::try_from([*max_high, *max_low]).ok()?;
+            let min = DeviceTemperature::try_from([*min_high, *min_low]).ok()?;
+            let threshold = match (min, max) {
+                (DeviceTemperature::OutOfRange, DeviceTemperature::OutOfRange) => {
+                    AlarmThreshold::NoneSet
+                }
+                (DeviceTemperature::OutOfRange, DeviceTemperature::InRange(max)) => {
+                    AlarmThreshold::UpperLimit(UpperLimitThreshold { max })
+                }
+                (DeviceTemperature::InRange(min), DeviceTemperature::InRange(max)) => {
+                    AlarmThreshold::RangeLimit(RangeLimitThreshold { min, max })
+                }
+                (DeviceTemperature::InRange(_), DeviceTemperature::OutOfRange) => return None,
+            };
+            build_set_profile_cmd(ProbeIdx::try_from_one_based(*index).ok()?, threshold)
+        }
+        (0x24, [index @ (5 | 6)]) => build_report_extra_profile_cmd(*index),
+        (0x24, [index]) => build_report_profile_cmd(ProbeIdx::try_from_one_based(*index).ok()?),
+        (0x25, []) => build_alt_temp_report_cmd(),
+        (0x26, []) => build_unknown_26_cmd(),
+        (0x27, []) => build_alarm_ack_cmd(),
+        (0x30, []) => build_temp_report_cmd(),
+        (0x41, []) => build_unknown_41_cmd(),
+        _ => return None,
+    };
+    Some(command)
+}
+
+fn encode(kind: u8, value: &[u8]) -> Bytes {
+    tlvc::encode(kind, value).into()
+}
+
 pub fn build_startup_command() -> Command {
     Command {
-        raw: Bytes::from_static(&[
-            0x01, 0x09, 0x70, 0x32, 0xe2, 0xc1, 0x79, 0x9d, 0xb4, 0xd1, 0xc7, 0xb1,
-        ]),
+        raw: encode(
+            0x01,
+            &[0x70, 0x32, 0xe2, 0xc1, 0x79, 0x9d, 0xb4, 0xd1, 0xc7],
+        ),
         decoded: Decoded::Startup,
     }
 }
 
 pub fn build_set_temp_mode_command(mode: TemperatureMode) -> Command {
     Command {
-        raw: Bytes::from_static(if matches!(mode, TemperatureMode::Celsius) {
-            &[0x20, 0x01, 0x0c, 0x2d]
-        } else {
-            &[0x20, 0x01, 0x0f, 0x30]
-        }),
+        raw: encode(
+            0x20,
+            if matches!(mode, TemperatureMode::Celsius) {
+                &[0x0c]
+            } else {
+                &[0x0f]
+            },
+        ),
         decoded: Decoded::SetTempMode(mode),
     }
 }
 
 pub fn build_alarm_ack_cmd() -> Command {
     Command {
-        raw: Bytes::from_static(&[0x27, 0x00, 0x27]),
+        raw: encode(0x27, &[]),
         decoded: Decoded::AlarmAck,
     }
 }
 
+pub fn build_temp_report_cmd() -> Command {
+    Command {
+        raw: encode(0x30, &[]),
+        decoded: Decoded::TempReport,
+    }
+}
+
+pub fn build_alt_temp_report_cmd() -> Command {
+    Command {
+        raw: encode(0x25, &[]),
+        decoded: Decoded::AltTempReport,
+    }
+}
+
+pub fn build_unknown_26_cmd() -> Command {
+    Command {
+        raw: encode(0x26, &[]),
+        decoded: Decoded::Unknown26,
+    }
+}
+
+pub fn build_unknown_41_cmd() -> Command {
+    Command {
+        raw: encode(0x41, &[]),
+        decoded: Decoded::Unknown41,
+    }
+}
+
 pub fn build_custom_cmd(raw: Vec<u8>) -> Command {
     Command {
         raw: raw.clone().into(),
@@ -66,48 +161,121 @@ pub fn build_custom_cmd(raw: Vec<u8>) -> Command {
 }
 
 pub fn build_report_profile_cmd(probe_idx: ProbeIdx) -> Command {
-    let mut raw = vec![0x24, 0x01, probe_idx.as_one_based()];
-    let checksum = calc_checksum(raw.as_slice());
-    raw.push(checksum);
-
     Command {
-        raw: raw.into(),
+        raw: encode(0x24, &[probe_idx.as_one_based()]),
         decoded: Decoded::ReportProfile(probe_idx),
     }
 }
 
+/// `index` is one-based, and should be 5 or 6. For probes the TP25 actually has, use `build_report_profile_cmd`.
+pub fn build_report_extra_profile_cmd(index: u8) -> Command {
+    Command {
+        raw: encode(0x24, &[index]),
+        decoded: Decoded::ReportExtraProfile(index),
+    }
+}
+
 pub fn build_set_profile_cmd(probe_idx: ProbeIdx, threshold: AlarmThreshold) -> Command {
-    let mut raw = vec![0x23, 0x06, probe_idx.as_one_based(), 0xcc];
+    let mut value = vec![probe_idx.as_one_based(), 0xcc];
 
     match threshold {
         AlarmThreshold::NoneSet => {
-            raw.push(0xff);
-            raw.push(0xff);
-            raw.push(0xff);
-            raw.push(0xff);
+            value.push(0xff);
+            value.push(0xff);
+            value.push(0xff);
+            value.push(0xff);
         }
         AlarmThreshold::UpperLimit(ult) => {
             let u: [u8; 2] = ult.max.into();
-            raw.push(u[0]);
-            raw.push(u[1]);
-            raw.push(0xff);
-            raw.push(0xff);
+            value.push(u[0]);
+            value.push(u[1]);
+            value.push(0xff);
+            value.push(0xff);
         }
         AlarmThreshold::RangeLimit(rlt) => {
             let min: [u8; 2] = rlt.min.into();
             let max: [u8; 2] = rlt.max.into();
-            raw.push(max[0]);
-            raw.push(max[1]);
-            raw.push(min[0]);
-            raw.push(min[1]);
+            value.push(max[0]);
+            value.push(max[1]);
+            value.push(min[0]);
+            value.push(min[1]);
         }
     }
 
-    let checksum = calc_checksum(raw.as_slice());
-    raw.push(checksum);
-
     Command {
-        raw: raw.into(),
+        raw: encode(0x23, &value),
         decoded: Decoded::SetProbeProfile(probe_idx, threshold),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::device_temperature::InRangeDeviceTemperature;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn builds_startup_command() {
+        assert_eq!(
+            build_startup_command().raw.as_ref(),
+            &[0x01, 0x09, 0x70, 0x32, 0xe2, 0xc1, 0x79, 0x9d, 0xb4, 0xd1, 0xc7, 0xb1]
+        );
+    }
+
+    #[test]
+    fn builds_set_temp_mode_commands() {
+        assert_eq!(
+            build_set_temp_mode_command(TemperatureMode::Celsius)
+                .raw
+                .as_ref(),
+            &[0x20, 0x01, 0x0c, 0x2d]
+        );
+        assert_eq!(
+            build_set_temp_mode_command(TemperatureMode::Fahrenheit)
+                .raw
+                .as_ref(),
+            &[0x20, 0x01, 0x0f, 0x30]
+        );
+    }
+
+    #[test]
+    fn builds_set_profile_command() {
+        let threshold = AlarmThreshold::RangeLimit(RangeLimitThreshold {
+            min: InRangeDeviceTemperature::new(25, 0),
+            max: InRangeDeviceTemperature::new(29, 0),
+        });
+        assert_eq!(
+            build_set_profile_cmd(ProbeIdx::Probe4, threshold)
+                .raw
+                .as_ref(),
+            &[0x23, 0x06, 0x04, 0xcc, 0x02, 0x90, 0x02, 0x50, 0xdd]
+        );
+    }
+
+    #[test]
+    fn recognises_commands_from_raw_bytes() {
+        let raw = Bytes::from_static(&[0x23, 0x06, 0x04, 0xcc, 0x02, 0x90, 0x02, 0x50, 0xdd]);
+        assert_matches!(
+            Command::from(raw).decoded,
+            Decoded::SetProbeProfile(ProbeIdx::Probe4, AlarmThreshold::RangeLimit(_))
+        );
+        assert_matches!(
+            Command::from(build_report_extra_profile_cmd(6).raw).decoded,
+            Decoded::ReportExtraProfile(6)
+        );
+        assert_matches!(
+            Command::from(build_startup_command().raw).decoded,
+            Decoded::Startup
+        );
+
+        // A bad checksum, and a command nobody has seen.
+        assert_matches!(
+            Command::from(Bytes::from_static(&[0x27, 0x00, 0x28])).decoded,
+            Decoded::Custom(_)
+        );
+        assert_matches!(
+            Command::from(Bytes::from(tlvc::encode(0x42, &[]))).decoded,
+            Decoded::Custom(_)
+        );
+    }
+}
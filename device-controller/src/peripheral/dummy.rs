@@ -1,25 +1,40 @@
+//! A pretend thermometer, for trying out the controller without one. Its temperatures simply climb a degree a second;
+//! the [simulator](crate::peripheral::simulator) builds on it to play back whole cooks.
+//!
+//! It behaves as the docs describe a real TP25 behaving: responses are real TLVC frames padded out with junk, which go
+//! through the same decoding as notifications from a thermometer; alarms go off when the simulated temperatures cross
+//! the probes' thresholds; and commands it doesn't understand get an 0xe0 error response.
+
 use crate::model::device::TemperatureMode;
-use crate::model::device_temperature::DeviceTemperature::InRange;
-use crate::model::device_temperature::InRangeDeviceTemperature;
+use crate::model::device_temperature::DeviceTemperature::{InRange, OutOfRange};
+use crate::model::device_temperature::{DeviceTemperature, InRangeDeviceTemperature};
 use crate::model::probe::AlarmThreshold;
 use crate::peripheral::command::{Command, Decoded};
 use crate::peripheral::interface::{TP25Receiver, TP25Writer};
-use crate::peripheral::notification::Decoded::{
-    ReportProbeProfile, SetProbeProfile, SetTempMode, Startup, Temperatures,
-};
-use crate::peripheral::notification::{
-    Notification, ProbeProfileData, ProbeTemperature, TemperatureData,
-};
+use crate::peripheral::notification::Notification;
+use crate::Error;
 use bytes::Bytes;
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::time::sleep;
 
+/// Notifications are padded out to this length.
+pub(crate) const NOTIFICATION_LEN: usize = 20;
+
+/// The padding after the frame in a notification, taken from real responses. It looks like whatever was left in the
+/// thermometer's buffer, so it's the same bytes whatever the frame's length.
+const JUNK: [u8; NOTIFICATION_LEN] = [
+    0x00, 0x00, 0x00, 0x2d, 0x00, 0x00, 0x91, 0x7d, 0x00, 0x00, 0xe9, 0x19, 0x00, 0x20, 0x48, 0x00,
+    0x00, 0x20, 0x02, 0x00,
+];
+
+/// The simulated temperature climbs a degree a second, then starts again from zero.
+const MAX_TEMP: u16 = 150;
+
 struct InternalState {
     temp: u16,
-    thresholds: [AlarmThreshold; 4],
-    mode: TemperatureMode,
+    thermometer: Thermometer,
     queued_notifications: VecDeque<Notification>,
 }
 
@@ -33,88 +48,291 @@ impl Peripheral {
         Self {
             internal: Arc::new(Mutex::new(InternalState {
                 temp: 0,
-                mode: TemperatureMode::Celsius,
+                thermometer: Thermometer::default(),
                 queued_notifications: VecDeque::new(),
-                thresholds: [AlarmThreshold::NoneSet; 4],
             })),
         }
     }
 }
 
+impl Default for Peripheral {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TP25Receiver for Peripheral {
-    async fn get_notification(&mut self) -> Option<Notification> {
-        let n = get_queued_notification(&self.internal);
-
-        match n {
-            Some(n) => Some(n),
-            None => {
-                sleep(Duration::from_secs(1)).await;
-                let mut state = self.internal.lock().unwrap();
-                state.temp += 1;
-                let t = state.temp;
-                Some(build_temp_notification(t, state.mode))
+    async fn get_notification(&mut self) -> Result<Notification, Error> {
+        loop {
+            if let Some(n) = get_queued_notification(&self.internal) {
+                return Ok(n);
+            }
+
+            sleep(Duration::from_secs(1)).await;
+            let mut state = self.internal.lock().unwrap();
+            if state.thermometer.started {
+                state.temp = (state.temp + 1) % MAX_TEMP;
+                let temps = state.temps();
+                return Ok(state.thermometer.temperature_report(temps));
             }
         }
     }
 }
 
 impl TP25Writer for Peripheral {
-    async fn send_cmd(&self, command: Command) -> Result<(), btleplug::Error> {
+    async fn disconnect(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn send_cmd(&self, command: Command) -> Result<(), Error> {
         let mut state = self.internal.lock().unwrap();
+        let temps = state.temps();
+        let notification = state.thermometer.respond(command, temps);
+        state.queued_notifications.push_back(notification);
+        Ok(())
+    }
+}
+
+impl InternalState {
+    fn temps(&self) -> [DeviceTemperature; 4] {
+        [0, 1, 2, 3].map(|i| {
+            InRange(InRangeDeviceTemperature::new(
+                self.temp + i,
+                i as u8 * 2 + 1,
+            ))
+        })
+    }
+}
+
+/// The thermometer's side of the protocol, given the probes' temperatures. Both the dummy device and the
+/// [simulator](crate::peripheral::simulator) use it.
+pub(crate) struct Thermometer {
+    /// Stored alarm profiles, which are still reported after a reboot.
+    thresholds: [AlarmThreshold; 4],
+    /// Which stored profiles actually raise alarms. After a reboot, none do until they're set again.
+    active: [bool; 4],
+    /// Probes whose alarms have been acknowledged. They stay quiet until the temperature comes back within the
+    /// threshold.
+    acknowledged: [bool; 4],
+    mode: TemperatureMode,
+    /// Like the real thing, no temperatures are sent until the 0x01 startup command has been.
+    pub(crate) started: bool,
+}
+
+impl Default for Thermometer {
+    fn default() -> Self {
+        Self {
+            thresholds: [AlarmThreshold::NoneSet; 4],
+            active: [false; 4],
+            acknowledged: [false; 4],
+            mode: TemperatureMode::Celsius,
+            started: false,
+        }
+    }
+}
+
+impl Thermometer {
+    pub(crate) fn reboot(&mut self) {
+        self.active = [false; 4];
+        self.acknowledged = [false; 4];
+        self.started = false;
+    }
+
+    pub(crate) fn respond(
+        &mut self,
+        command: Command,
+        temps: [DeviceTemperature; 4],
+    ) -> Notification {
+        // Custom commands may still be ones the thermometer knows, if they're well formed.
+        let command = match command.decoded {
+            Decoded::Custom(raw) => Command::from(Bytes::from(raw)),
+            _ => command,
+        };
+
         match command.decoded {
-            Decoded::Startup => state.queued_notifications.push_back(Notification {
-                raw: mock_raw_bytes(),
-                decoded: Startup,
-            }),
-            Decoded::SetTempMode(mode) => {
-                state.queued_notifications.push_back(Notification {
-                    raw: mock_raw_bytes(),
-                    decoded: SetTempMode,
-                });
-                state.mode = mode;
+            Decoded::Startup => {
+                self.started = true;
+                notification(0x01, &[0x0a])
             }
-            Decoded::ReportProfile(idx) => {
-                let t = state.thresholds[idx.as_zero_based() as usize];
-                state.queued_notifications.push_back(Notification {
-                    raw: mock_raw_bytes(),
-                    decoded: ReportProbeProfile(ProbeProfileData { idx, threshold: t }),
-                });
+            Decoded::SetTempMode(mode) => {
+                self.mode = mode;
+                notification(0x20, &[])
             }
+            Decoded::ReportProfile(idx) => profile_report(
+                idx.as_one_based(),
+                self.thresholds[idx.as_zero_based() as usize],
+            ),
+            Decoded::ReportExtraProfile(index) => profile_report(index, AlarmThreshold::NoneSet),
             Decoded::SetProbeProfile(idx, profile) => {
-                state.thresholds[idx.as_zero_based() as usize] = profile;
-                state.queued_notifications.push_back(Notification {
-                    raw: mock_raw_bytes(),
-                    decoded: SetProbeProfile,
-                });
+                let i = idx.as_zero_based() as usize;
+                self.thresholds[i] = profile;
+                self.active[i] = true;
+                self.acknowledged[i] = false;
+                // The second byte echoes the one after the probe index in the command.
+                notification(0x23, &[idx.as_one_based(), command.raw[3]])
+            }
+            Decoded::AlarmAck => {
+                let alarms = self.alarms(temps);
+                for (acknowledged, alarm) in self.acknowledged.iter_mut().zip(alarms) {
+                    *acknowledged |= alarm;
+                }
+                notification(0x27, &[])
             }
-            Decoded::AlarmAck => {} // Dummy device currently doesn't issue alarms
-            Decoded::Custom(_) => {} // Do nothing for custom commands
+            Decoded::TempReport => self.temperature_report(temps),
+            Decoded::AltTempReport => {
+                let mut value = vec![0x06, 0x00];
+                value.extend(temps.iter().flat_map(|t| <[u8; 2]>::from(*t)));
+                value.extend([0xff; 4]);
+                notification(0x25, &value)
+            }
+            // These responses are copied from a real thermometer.
+            Decoded::Unknown26 => notification(0x26, &[0x0c, 0x0c, 0x5a, 0x03, 0x0f]),
+            Decoded::Unknown41 => notification(0x41, &[0x31, 0x11]),
+            // Malformed, or not a command the thermometer has. The value is copied from a real error response.
+            Decoded::Custom(_) => notification(0xe0, &[0x30, 0x04]),
+        }
+    }
+
+    /// Which probes are alarming. An alarm that's been acknowledged is forgotten once its probe is back within its
+    /// threshold.
+    fn alarms(&mut self, temps: [DeviceTemperature; 4]) -> [bool; 4] {
+        let mut alarms = [false; 4];
+        for i in 0..4 {
+            let outside = self.active[i] && is_outside(temps[i], self.thresholds[i]);
+            if !outside {
+                self.acknowledged[i] = false;
+            }
+            alarms[i] = outside && !self.acknowledged[i];
+        }
+        alarms
+    }
+
+    pub(crate) fn temperature_report(&mut self, temps: [DeviceTemperature; 4]) -> Notification {
+        let alarm_bits = self
+            .alarms(temps)
+            .iter()
+            .enumerate()
+            .fold(0u8, |bits, (i, alarm)| bits | ((*alarm as u8) << i));
+        let mode = match self.mode {
+            TemperatureMode::Celsius => 0x0c,
+            TemperatureMode::Fahrenheit => 0x0f,
         };
-        Ok(())
+        // The first byte changes between traces, but nobody knows what it means.
+        let mut value = vec![0x5a, mode, alarm_bits];
+        value.extend(temps.iter().flat_map(|t| <[u8; 2]>::from(*t)));
+        value.extend([0xff; 4]);
+        notification(0x30, &value)
     }
 }
 
+fn is_outside(temp: DeviceTemperature, threshold: AlarmThreshold) -> bool {
+    let InRange(t) = temp else {
+        return false;
+    };
+    let t = f32::from(t);
+    match threshold {
+        AlarmThreshold::NoneSet => false,
+        AlarmThreshold::UpperLimit(u) => t >= f32::from(u.max),
+        AlarmThreshold::RangeLimit(r) => t < f32::from(r.min) || t > f32::from(r.max),
+    }
+}
+
+fn profile_report(index: u8, threshold: AlarmThreshold) -> Notification {
+    let (min, max) = match threshold {
+        AlarmThreshold::NoneSet => (OutOfRange, OutOfRange),
+        AlarmThreshold::UpperLimit(u) => (OutOfRange, InRange(u.max)),
+        AlarmThreshold::RangeLimit(r) => (InRange(r.min), InRange(r.max)),
+    };
+    // The second byte isn't understood. This is the value seen from a real thermometer.
+    let mut value = vec![index, 0xfa];
+    value.extend(<[u8; 2]>::from(max));
+    value.extend(<[u8; 2]>::from(min));
+    notification(0x24, &value)
+}
+
+/// A notification holding a single frame, padded out with junk as the thermometer does.
+fn notification(kind: u8, value: &[u8]) -> Notification {
+    let frame = tlvc::Frame::new(kind, value);
+    let used = (tlvc::OVERHEAD + value.len()).min(NOTIFICATION_LEN);
+    Notification::from(Bytes::from(frame.with_trailing(&JUNK[used..]).encode()))
+}
+
 fn get_queued_notification(internal: &Arc<Mutex<InternalState>>) -> Option<Notification> {
     let mut state = internal.lock().unwrap();
     state.queued_notifications.pop_front()
 }
 
-fn build_temp_notification(t: u16, mode: TemperatureMode) -> Notification {
-    let temps = (0..4)
-        .map(|i| ProbeTemperature {
-            temp: InRange(InRangeDeviceTemperature::new(t + i, i as u8 * 2 + 1)),
-            alarm: false,
-        })
-        .collect::<Vec<_>>();
-    Notification {
-        raw: mock_raw_bytes(),
-        decoded: Temperatures(TemperatureData {
-            temps: temps.try_into().unwrap(),
-            temp_mode: mode,
-        }),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::probe::{ProbeIdx, UpperLimitThreshold};
+    use crate::peripheral::command::{
+        build_alarm_ack_cmd, build_custom_cmd, build_report_profile_cmd, build_set_profile_cmd,
+        build_startup_command,
+    };
+    use crate::peripheral::notification::Decoded as N;
+    use assert_matches::assert_matches;
+
+    async fn respond(p: &mut Peripheral, command: Command) -> Notification {
+        p.send_cmd(command).await.unwrap();
+        p.get_notification().await.unwrap()
     }
-}
 
-fn mock_raw_bytes() -> Bytes {
-    Bytes::from([1u8, 2u8].as_slice())
+    #[tokio::test(start_paused = true)]
+    async fn sends_padded_frames() {
+        let mut p = Peripheral::new();
+        let n = respond(&mut p, build_startup_command()).await;
+        assert_eq!(n.raw.len(), NOTIFICATION_LEN);
+        assert_eq!(&n.raw[..4], &[0x01, 0x01, 0x0a, 0x0c]);
+        assert_matches!(n.decoded, Ok(N::Startup));
+
+        let threshold = AlarmThreshold::UpperLimit(UpperLimitThreshold {
+            max: InRangeDeviceTemperature::new(75, 0),
+        });
+        respond(&mut p, build_set_profile_cmd(ProbeIdx::Probe2, threshold)).await;
+        let n = respond(&mut p, build_report_profile_cmd(ProbeIdx::Probe2)).await;
+        assert_matches!(n.decoded, Ok(N::ReportProbeProfile(data)) if data.threshold == threshold);
+
+        let n = p.get_notification().await.unwrap();
+        assert_eq!(n.raw.len(), NOTIFICATION_LEN);
+        assert_matches!(n.decoded, Ok(N::Temperatures(_)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn raises_and_acknowledges_alarms() {
+        let mut p = Peripheral::new();
+        respond(&mut p, build_startup_command()).await;
+        let threshold = AlarmThreshold::UpperLimit(UpperLimitThreshold {
+            max: InRangeDeviceTemperature::new(5, 0),
+        });
+        respond(&mut p, build_set_profile_cmd(ProbeIdx::Probe1, threshold)).await;
+
+        let alarm = |n: Notification| match n.decoded {
+            Ok(N::Temperatures(data)) => data.temps[0].alarm,
+            other => panic!("unexpected notification {other:?}"),
+        };
+        for _ in 0..4 {
+            assert!(!alarm(p.get_notification().await.unwrap()));
+        }
+        assert!(alarm(p.get_notification().await.unwrap()));
+
+        assert_matches!(
+            respond(&mut p, build_alarm_ack_cmd()).await.decoded,
+            Ok(N::AlarmAck)
+        );
+        assert!(!alarm(p.get_notification().await.unwrap()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejects_malformed_commands() {
+        let mut p = Peripheral::new();
+        for raw in [vec![0x27, 0x00, 0x28], vec![0x42, 0x00, 0x42], vec![0x24]] {
+            let n = respond(&mut p, build_custom_cmd(raw)).await;
+            assert_matches!(n.decoded, Ok(N::Error));
+        }
+
+        // A well formed custom command is answered as normal.
+        let n = respond(&mut p, build_custom_cmd(vec![0x41, 0x00, 0x41])).await;
+        assert_matches!(n.decoded, Ok(N::Unknown41(_)));
+    }
 }
@@ -1,14 +1,85 @@
 use crate::peripheral::command::Command;
 use crate::peripheral::notification::Notification;
+use crate::Error;
+use futures::future::BoxFuture;
 
 #[trait_variant::make(TP25Receiver: Send)]
 pub trait LocalTP25Receiver {
     #[allow(unused)] // Needed because we always used the variant constructed above
-    async fn get_notification(&mut self) -> Option<Notification>;
+    /// Wait for the next notification from the thermometer. An error means the connection is no longer usable.
+    ///
+    /// This must be cancel safe: if the future is dropped before it completes, no notification may be lost.
+    async fn get_notification(&mut self) -> Result<Notification, Error>;
 }
 
 #[trait_variant::make(TP25Writer: Send)]
 pub trait LocalTP25Writer {
     #[allow(unused)] // Needed because we always used the variant constructed above
-    async fn send_cmd(&self, command: Command) -> Result<(), btleplug::Error>;
+    async fn send_cmd(&self, command: Command) -> Result<(), Error>;
+
+    /// Cleanly end the connection, so the thermometer is free for something else to connect to.
+    #[allow(unused)]
+    async fn disconnect(&self) -> Result<(), Error>;
+}
+
+/// The object safe form of [TP25Receiver], so that receivers for different backends can be used interchangeably.
+trait DynTP25Receiver: Send {
+    fn get_notification(&mut self) -> BoxFuture<'_, Result<Notification, Error>>;
+}
+
+impl<T: TP25Receiver> DynTP25Receiver for T {
+    fn get_notification(&mut self) -> BoxFuture<'_, Result<Notification, Error>> {
+        Box::pin(TP25Receiver::get_notification(self))
+    }
+}
+
+/// The object safe form of [TP25Writer].
+trait DynTP25Writer: Send + Sync {
+    fn send_cmd(&self, command: Command) -> BoxFuture<'_, Result<(), Error>>;
+
+    fn disconnect(&self) -> BoxFuture<'_, Result<(), Error>>;
+}
+
+impl<T: TP25Writer + Sync> DynTP25Writer for T {
+    fn send_cmd(&self, command: Command) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(TP25Writer::send_cmd(self, command))
+    }
+
+    fn disconnect(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(TP25Writer::disconnect(self))
+    }
+}
+
+/// A receiver from any backend.
+pub struct BoxedReceiver(Box<dyn DynTP25Receiver>);
+
+impl BoxedReceiver {
+    pub fn new(receiver: impl TP25Receiver + 'static) -> Self {
+        Self(Box::new(receiver))
+    }
+}
+
+impl TP25Receiver for BoxedReceiver {
+    async fn get_notification(&mut self) -> Result<Notification, Error> {
+        self.0.get_notification().await
+    }
+}
+
+/// A writer from any backend.
+pub struct BoxedWriter(Box<dyn DynTP25Writer>);
+
+impl BoxedWriter {
+    pub fn new(writer: impl TP25Writer + Sync + 'static) -> Self {
+        Self(Box::new(writer))
+    }
+}
+
+impl TP25Writer for BoxedWriter {
+    async fn send_cmd(&self, command: Command) -> Result<(), Error> {
+        self.0.send_cmd(command).await
+    }
+
+    async fn disconnect(&self) -> Result<(), Error> {
+        self.0.disconnect().await
+    }
 }
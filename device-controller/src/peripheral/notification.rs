@@ -3,53 +3,114 @@ use crate::model::device_temperature::DeviceTemperature;
 use crate::model::device_temperature::DeviceTemperature::{InRange, OutOfRange};
 use crate::model::probe::{AlarmThreshold, ProbeIdx, RangeLimitThreshold, UpperLimitThreshold};
 use bytes::Bytes;
+use std::fmt::{Display, Formatter};
 
 #[derive(Clone, Debug)]
 pub struct Notification {
     pub raw: Bytes,
-    pub decoded: Decoded,
+    pub decoded: Result<Decoded, DecodeError>,
 }
 
 impl From<Bytes> for Notification {
     fn from(raw: Bytes) -> Notification {
-        if raw.len() < 3 {
-            return Notification {
-                raw,
-                decoded: Decoded::Unknown,
-            };
-        }
+        let decoded = decode(&raw);
+        Notification { raw, decoded }
+    }
+}
 
-        let decoded = match raw[0] {
-            0x01 => make_notification(&raw, 1, startup),
-            0x20 => make_notification(&raw, 0, set_temp_mode),
-            0x23 => make_notification(&raw, 2, set_probe_profile),
-            0x24 => make_notification(&raw, 6, report_probe_profile),
-            0x30 => make_notification(&raw, 0x0f, temperature_report),
-            0xe0 => Decoded::Error,
-            _ => Decoded::Unknown,
-        };
+fn decode(raw: &Bytes) -> Result<Decoded, DecodeError> {
+    if raw.len() < 3 {
+        return Err(DecodeError::TooShort);
+    }
 
-        Notification { raw, decoded }
+    match raw[0] {
+        0x01 => make_notification(raw, 1, startup),
+        0x20 => make_notification(raw, 0, set_temp_mode),
+        0x23 => make_notification(raw, 2, set_probe_profile),
+        0x24 => make_notification(raw, 6, report_probe_profile),
+        0x25 => make_notification(raw, 0x0e, alt_temperature_report),
+        0x26 => make_notification(raw, 5, unknown_26),
+        0x27 => make_notification(raw, 0, alarm_ack),
+        0x30 => make_notification(raw, 0x0f, temperature_report),
+        0x41 => make_notification(raw, 2, unknown_41),
+        0xe0 => make_notification(raw, 2, error),
+        t => Err(DecodeError::UnknownType(t)),
     }
 }
 
 #[derive(Clone, Debug)]
 pub enum Decoded {
-    Unknown,
-    Startup,                              // 0x01
-    SetTempMode,                          // 0x20
-    SetProbeProfile,                      // 0x23
-    ReportProbeProfile(ProbeProfileData), // 0x24
-    Temperatures(TemperatureData),        // 0x30
-    Error,                                // 0xe0
+    Startup,                                        // 0x01
+    SetTempMode,                                    // 0x20
+    SetProbeProfile(ProbeIdx),                      // 0x23
+    ReportProbeProfile(ProbeProfileData),           // 0x24
+    ReportExtraProbeProfile(ExtraProbeProfileData), // 0x24, for probes 5 and 6
+    AltTemperatures(AltTemperatureData),            // 0x25
+    Unknown26([u8; 5]),                             // 0x26
+    AlarmAck,                                       // 0x27
+    Temperatures(TemperatureData),                  // 0x30
+    Unknown41([u8; 2]),                             // 0x41
+    Error,                                          // 0xe0
+}
+
+/// Reasons a notification could not be decoded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DecodeError {
+    /// There are not enough bytes for the TLVC header, or for the length it claims.
+    TooShort,
+    /// The length byte is not the one expected for this notification type.
+    LengthMismatch { expected: u8, actual: u8 },
+    /// The checksum byte does not match the preceding bytes.
+    BadChecksum { expected: u8, actual: u8 },
+    /// A temperature field is not valid BCD.
+    BadBcd,
+    /// A probe index outside of the range the TP25 supports.
+    BadProbeIndex(u8),
+    /// A probe profile with a low temperature alarm but no high temperature alarm.
+    BadThreshold,
+    /// The first byte is not a notification type we know about.
+    UnknownType(u8),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::TooShort => write!(f, "notification too short"),
+            DecodeError::LengthMismatch { expected, actual } => write!(
+                f,
+                "length mismatch (expected {:#04x}, got {:#04x})",
+                expected, actual
+            ),
+            DecodeError::BadChecksum { expected, actual } => write!(
+                f,
+                "bad checksum (expected {:#04x}, got {:#04x})",
+                expected, actual
+            ),
+            DecodeError::BadBcd => write!(f, "temperature not valid BCD"),
+            DecodeError::BadProbeIndex(idx) => write!(f, "bad probe index {}", idx),
+            DecodeError::BadThreshold => write!(f, "low alarm threshold without high threshold"),
+            DecodeError::UnknownType(t) => write!(f, "unknown notification type {:#04x}", t),
+        }
+    }
 }
 
+impl std::error::Error for DecodeError {}
+
 #[derive(Clone, Debug)]
 pub struct ProbeProfileData {
     pub idx: ProbeIdx,
     pub threshold: AlarmThreshold,
 }
 
+/// A profile reported for a probe the TP25 doesn't have. The official app asks about probes 5 and 6, and the thermometer
+/// answers as if they exist.
+#[derive(Clone, Debug)]
+pub struct ExtraProbeProfileData {
+    /// One-based, like `ProbeIdx::as_one_based`.
+    pub index: u8,
+    pub threshold: AlarmThreshold,
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct ProbeTemperature {
     pub temp: DeviceTemperature,
@@ -62,47 +123,61 @@ pub struct TemperatureData {
     pub temp_mode: TemperatureMode,
 }
 
-type InnerConversion = fn(raw: &Bytes) -> Decoded;
+/// The temperatures carried by an 0x25 response. The response has space for 6 temperatures, but only the first 4
+/// correspond to probes on the TP25 - the others always seem to be 0xffff.
+///
+/// Unlike 0x30, there is no alarm or temperature mode information in this response.
+#[derive(Clone, Copy, Debug)]
+pub struct AltTemperatureData {
+    pub temps: [DeviceTemperature; 4],
+}
+
+type InnerConversion = fn(value: &[u8]) -> Result<Decoded, DecodeError>;
 
-fn make_notification(raw: &Bytes, length: usize, inner_conversion: InnerConversion) -> Decoded {
+fn make_notification(
+    raw: &Bytes,
+    length: usize,
+    inner_conversion: InnerConversion,
+) -> Result<Decoded, DecodeError> {
     if raw[1] != length as u8 {
-        return Decoded::Unknown;
+        return Err(DecodeError::LengthMismatch {
+            expected: length as u8,
+            actual: raw[1],
+        });
     }
 
-    let checksum_byte = raw[2 + length];
-    let calc_checksum = calc_checksum(raw.slice(0..2 + length).as_ref());
+    // Anything after the frame is junk, so is ignored.
+    let frame = tlvc::decode(raw).map_err(|_| DecodeError::TooShort)?;
 
-    if checksum_byte != calc_checksum {
-        return Decoded::Unknown;
+    if !frame.is_checksum_valid() {
+        return Err(DecodeError::BadChecksum {
+            expected: frame.expected_checksum(),
+            actual: frame.checksum,
+        });
     }
 
-    // At this point, we know the notification has the correct format, so these inner conversion do not need to do any
-    // checking.
-    inner_conversion(raw)
+    // At this point, we know the value has the correct length, so these inner conversions only need to check the
+    // values they find.
+    inner_conversion(&frame.value)
 }
 
-pub fn calc_checksum(bytes: &[u8]) -> u8 {
-    #[allow(arithmetic_overflow)]
-    let sum: u64 = bytes[0..bytes.len()].iter().map(|x| *x as u64).sum();
-    (sum % 256) as u8
+fn startup(_: &[u8]) -> Result<Decoded, DecodeError> {
+    // There is a byte of value, but its purpose is currently unknown.
+    Ok(Decoded::Startup)
 }
 
-fn startup(_: &Bytes) -> Decoded {
-    // There is a byte of value, but its purpose is currently unknown.
-    Decoded::Startup
+fn probe_idx(value: u8) -> Result<ProbeIdx, DecodeError> {
+    ProbeIdx::try_from_one_based(value).map_err(|_| DecodeError::BadProbeIndex(value))
 }
 
-fn report_probe_profile(raw: &Bytes) -> Decoded {
-    let idx = ProbeIdx::from_one_based(raw[2]);
-    let high_threshold = DeviceTemperature::try_from([raw[4], raw[5]]);
-    let low_threshold = DeviceTemperature::try_from([raw[6], raw[7]]);
+fn temp_from_bytes(high: u8, low: u8) -> Result<DeviceTemperature, DecodeError> {
+    DeviceTemperature::try_from([high, low]).map_err(|_| DecodeError::BadBcd)
+}
 
-    let Ok(high_threshold) = high_threshold else {
-        return Decoded::Unknown;
-    };
-    let Ok(low_threshold) = low_threshold else {
-        return Decoded::Unknown;
-    };
+fn report_probe_profile(value: &[u8]) -> Result<Decoded, DecodeError> {
+    let index = value[0];
+    let high_threshold = temp_from_bytes(value[2], value[3])?;
+    let low_threshold = temp_from_bytes(value[4], value[5])?;
 
     let threshold: AlarmThreshold = match (low_threshold, high_threshold) {
         (InRange(low), InRange(high)) => AlarmThreshold::RangeLimit(RangeLimitThreshold {
@@ -110,7 +185,7 @@ fn report_probe_profile(raw: &Bytes) -> Decoded {
             max: high,
         }),
         (InRange(_), OutOfRange) => {
-            return Decoded::Unknown;
+            return Err(DecodeError::BadThreshold);
         }
         (OutOfRange, InRange(high)) => {
             AlarmThreshold::UpperLimit(UpperLimitThreshold { max: high })
@@ -118,48 +193,86 @@ fn report_probe_profile(raw: &Bytes) -> Decoded {
         (OutOfRange, OutOfRange) => AlarmThreshold::NoneSet,
     };
 
-    Decoded::ReportProbeProfile(ProbeProfileData { idx, threshold })
+    match index {
+        5 | 6 => Ok(Decoded::ReportExtraProbeProfile(ExtraProbeProfileData {
+            index,
+            threshold,
+        })),
+        _ => Ok(Decoded::ReportProbeProfile(ProbeProfileData {
+            idx: probe_idx(index)?,
+            threshold,
+        })),
+    }
 }
 
-fn temperature_report(raw: &Bytes) -> Decoded {
-    let temp_mode = if raw[3] == 0x0f {
+fn temperature_report(value: &[u8]) -> Result<Decoded, DecodeError> {
+    let temp_mode = if value[1] == 0x0f {
         TemperatureMode::Fahrenheit
     } else {
         TemperatureMode::Celsius
     };
-    let alarms = raw[4];
+    let alarms = value[2];
 
     let mut temps: [ProbeTemperature; 4] = [ProbeTemperature::default(); 4];
-    for i in 0..4 {
-        let Ok(t) = DeviceTemperature::try_from([raw[5 + (i * 2)], raw[6 + (i * 2)]]) else {
-            return Decoded::Unknown;
-        };
-        temps[i].temp = t;
-        temps[i].alarm = (alarms & (1 << i)) != 0;
+    for (i, temp) in temps.iter_mut().enumerate() {
+        temp.temp = temp_from_bytes(value[3 + (i * 2)], value[4 + (i * 2)])?;
+        temp.alarm = (alarms & (1 << i)) != 0;
+    }
+
+    Ok(Decoded::Temperatures(TemperatureData { temps, temp_mode }))
+}
+
+fn alt_temperature_report(value: &[u8]) -> Result<Decoded, DecodeError> {
+    // value[0] and value[1] are two bytes of unknown purpose, then the temperatures follow.
+    let mut temps: [DeviceTemperature; 4] = [DeviceTemperature::default(); 4];
+    for (i, temp) in temps.iter_mut().enumerate() {
+        *temp = temp_from_bytes(value[2 + (i * 2)], value[3 + (i * 2)])?;
     }
 
-    Decoded::Temperatures(TemperatureData { temps, temp_mode })
+    Ok(Decoded::AltTemperatures(AltTemperatureData { temps }))
+}
+
+fn set_temp_mode(_: &[u8]) -> Result<Decoded, DecodeError> {
+    Ok(Decoded::SetTempMode)
+}
+
+fn set_probe_profile(value: &[u8]) -> Result<Decoded, DecodeError> {
+    // The second byte seems to match the unknown byte sent in the command, so is discarded.
+    Ok(Decoded::SetProbeProfile(probe_idx(value[0])?))
+}
+
+// The purpose of the 0x26 and 0x41 responses is unknown, so their values are passed on as they are.
+fn unknown_26(value: &[u8]) -> Result<Decoded, DecodeError> {
+    Ok(Decoded::Unknown26([
+        value[0], value[1], value[2], value[3], value[4],
+    ]))
+}
+
+fn unknown_41(value: &[u8]) -> Result<Decoded, DecodeError> {
+    Ok(Decoded::Unknown41([value[0], value[1]]))
 }
 
-fn set_temp_mode(_: &Bytes) -> Decoded {
-    Decoded::SetTempMode
+fn alarm_ack(_: &[u8]) -> Result<Decoded, DecodeError> {
+    Ok(Decoded::AlarmAck)
 }
 
-fn set_probe_profile(_: &Bytes) -> Decoded {
-    // There are two bytes sent that we're discarding.
-    Decoded::SetProbeProfile
+fn error(_: &[u8]) -> Result<Decoded, DecodeError> {
+    // The two bytes of value are not understood.
+    Ok(Decoded::Error)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use assert_matches::assert_matches;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
 
     #[test]
     fn parses_simple_startup_response() {
         let b = Bytes::from_static(&[0x01u8, 0x01u8, 0x0au8, 0x0cu8]);
         let n = Notification::from(b);
-        assert_matches!(n.decoded, Decoded::Startup);
+        assert_matches!(n.decoded, Ok(Decoded::Startup));
     }
 
     #[test]
@@ -171,9 +284,8 @@ mod tests {
             0xff, 0xff, 0xff, 0xc3,
         ]);
         let n = Notification::from(b);
-        let Decoded::Temperatures(d) = n.decoded else {
-            assert!(false);
-            return;
+        let Ok(Decoded::Temperatures(d)) = n.decoded else {
+            panic!("Expected a temperature report");
         };
 
         assert_matches!(d.temp_mode, TemperatureMode::Celsius);
@@ -190,4 +302,159 @@ mod tests {
         assert!(!d.temps[2].alarm);
         assert!(!d.temps[3].alarm);
     }
+
+    #[test]
+    fn parses_alt_temp_report() {
+        use crate::model::device_temperature::InRangeDeviceTemperature;
+
+        let b = Bytes::from_static(&[
+            0x25, 0x0e, 0x06, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02, 0x05, 0xff, 0xff,
+            0xff, 0xff, 0x36, 0x20, 0x02, 0x00,
+        ]);
+        let n = Notification::from(b);
+        let Ok(Decoded::AltTemperatures(d)) = n.decoded else {
+            panic!("Expected an alternative temperature report");
+        };
+
+        assert_matches!(d.temps[0], OutOfRange);
+        assert_matches!(d.temps[1], OutOfRange);
+        assert_matches!(d.temps[2], OutOfRange);
+        assert_matches!(
+            d.temps[3],
+            InRange(t) if t == InRangeDeviceTemperature::new(20, 5)
+        );
+    }
+
+    #[test]
+    fn rejects_short_notifications() {
+        let n = Notification::from(Bytes::from_static(&[0x30, 0x0f]));
+        assert_matches!(n.decoded, Err(DecodeError::TooShort));
+
+        // The length byte is correct, but the frame has been truncated.
+        let n = Notification::from(Bytes::from_static(&[0x30, 0x0f, 0x5a, 0x0c, 0x00]));
+        assert_matches!(n.decoded, Err(DecodeError::TooShort));
+    }
+
+    #[test]
+    fn rejects_bad_length() {
+        let n = Notification::from(Bytes::from_static(&[0x01, 0x02, 0x0a, 0x0c, 0x0c]));
+        assert_matches!(
+            n.decoded,
+            Err(DecodeError::LengthMismatch {
+                expected: 1,
+                actual: 2
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let n = Notification::from(Bytes::from_static(&[0x01, 0x01, 0x0a, 0x0d]));
+        assert_matches!(
+            n.decoded,
+            Err(DecodeError::BadChecksum {
+                expected: 0x0c,
+                actual: 0x0d
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_bcd() {
+        let b = Bytes::from_static(&[
+            0x30, 0x0f, 0x5a, 0x0c, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x03, 0x2a, 0xff,
+            0xff, 0xff, 0xff, 0xc8,
+        ]);
+        assert_matches!(Notification::from(b).decoded, Err(DecodeError::BadBcd));
+    }
+
+    #[test]
+    fn parses_extra_probe_profile() {
+        // The app queries profiles for probes 5 and 6, which the TP25 doesn't have.
+        let b = Bytes::from_static(&[0x24, 0x06, 0x05, 0x00, 0xff, 0xff, 0xff, 0xff, 0x2b]);
+        let Ok(Decoded::ReportExtraProbeProfile(d)) = Notification::from(b).decoded else {
+            panic!("Expected an extra probe profile");
+        };
+        assert_eq!(d.index, 5);
+        assert_matches!(d.threshold, AlarmThreshold::NoneSet);
+    }
+
+    #[test]
+    fn parses_unknown_responses() {
+        let n = Notification::from(Bytes::from_static(&[
+            0x26, 0x05, 0x0c, 0x0c, 0x5a, 0x03, 0x0f, 0xaf, 0x00, 0x00,
+        ]));
+        assert_matches!(
+            n.decoded,
+            Ok(Decoded::Unknown26([0x0c, 0x0c, 0x5a, 0x03, 0x0f]))
+        );
+
+        let n = Notification::from(Bytes::from_static(&[0x41, 0x02, 0x31, 0x11, 0x85]));
+        assert_matches!(n.decoded, Ok(Decoded::Unknown41([0x31, 0x11])));
+    }
+
+    #[test]
+    fn rejects_unknown_probe_index() {
+        let b = Bytes::from_static(&[0x24, 0x06, 0x07, 0x00, 0xff, 0xff, 0xff, 0xff, 0x2d]);
+        assert_matches!(
+            Notification::from(b).decoded,
+            Err(DecodeError::BadProbeIndex(7))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_type() {
+        let n = Notification::from(Bytes::from_static(&[0x42, 0x00, 0x42]));
+        assert_matches!(n.decoded, Err(DecodeError::UnknownType(0x42)));
+    }
+
+    // Builds a notification of a known type with the correct length and checksum, so that decoding gets as far as
+    // checking the values.
+    fn well_formed_notification() -> impl Strategy<Value = Vec<u8>> {
+        let known = prop_oneof![
+            Just((0x01u8, 1usize)),
+            Just((0x20, 0)),
+            Just((0x23, 2)),
+            Just((0x24, 6)),
+            Just((0x25, 0x0e)),
+            Just((0x26, 5)),
+            Just((0x27, 0)),
+            Just((0x30, 0x0f)),
+            Just((0x41, 2)),
+            Just((0xe0, 2)),
+        ];
+        (known, vec(any::<u8>(), 0x0f), vec(any::<u8>(), 0..20)).prop_map(
+            |((t, length), value, junk)| {
+                tlvc::Frame::new(t, &value[..length])
+                    .with_trailing(&junk)
+                    .encode()
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics_on_arbitrary_bytes(raw in vec(any::<u8>(), 0..40)) {
+            let _ = Notification::from(Bytes::from(raw));
+        }
+
+        #[test]
+        fn never_panics_on_truncated_bytes(raw in well_formed_notification(), cut in any::<usize>()) {
+            let cut = cut % (raw.len() + 1);
+            let _ = Notification::from(Bytes::copy_from_slice(&raw[..cut]));
+        }
+
+        #[test]
+        fn well_formed_notifications_pass_framing_checks(raw in well_formed_notification()) {
+            let decoded = Notification::from(Bytes::from(raw)).decoded;
+            let framing_failed = matches!(
+                decoded,
+                Err(DecodeError::TooShort
+                    | DecodeError::LengthMismatch { .. }
+                    | DecodeError::BadChecksum { .. }
+                    | DecodeError::UnknownType(_))
+            );
+            prop_assert!(!framing_failed);
+        }
+    }
 }
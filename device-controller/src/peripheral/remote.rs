@@ -0,0 +1,533 @@
+//! A thermometer attached to another machine, reached over TCP or a Unix socket through the `tp25-bridge` binary
+//! running there. This lets the controller run somewhere other than next to the Bluetooth adapter.
+//!
+//! The bridge relays the thermometer's raw notifications and commands, so everything else (decoding, the watchdog,
+//! retries) happens at this end as usual. Everything sent either way is a frame: a kind byte, a big-endian `u16`
+//! length, then that many bytes.
+//!
+//! When a client connects:
+//!
+//! 1. The bridge sends `HELLO`: the protocol version, and a random nonce.
+//! 2. The client sends `AUTH`: its own nonce, and an HMAC-SHA256 of both nonces keyed by the shared secret (empty if it
+//!    has no secret).
+//! 3. The bridge sends `WELCOME`, with its own HMAC of the nonces so the client knows it's talking to the right bridge,
+//!    or `REJECTED` with a reason. A bridge without a secret accepts anyone.
+//! 4. The bridge finds and connects to the thermometer, then sends `READY`.
+//! 5. `COMMAND`s and `NOTIFICATION`s are relayed until either end goes away, or the client sends `DISCONNECT`.
+
+pub mod bridge;
+
+use crate::peripheral::command::Command;
+use crate::peripheral::interface::{TP25Receiver, TP25Writer};
+use crate::peripheral::notification::Notification;
+use crate::Error;
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use sha2::Sha256;
+use std::fmt::{Display, Formatter};
+use std::io;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::timeout;
+
+const VERSION: u8 = 1;
+
+const HELLO: u8 = 0x00;
+const AUTH: u8 = 0x01;
+const WELCOME: u8 = 0x02;
+const REJECTED: u8 = 0x03;
+const READY: u8 = 0x04;
+const COMMAND: u8 = 0x10;
+const NOTIFICATION: u8 = 0x11;
+const DISCONNECT: u8 = 0x12;
+
+const NONCE_LEN: usize = 16;
+
+/// How long each end waits for the other during the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where a bridge listens: `<host>:<port>`, or `unix:<path>` for a Unix socket.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RemoteAddress {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl RemoteAddress {
+    /// Parse an address, which is a Unix socket if it starts `unix:`. `None` if it's empty, or a Unix socket where
+    /// there's no such thing.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) if !path.is_empty() => Some(RemoteAddress::Unix(PathBuf::from(path))),
+            Some(_) => None,
+            None if !s.is_empty() => Some(RemoteAddress::Tcp(s.to_string())),
+            None => None,
+        }
+    }
+
+    pub async fn connect(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(match self {
+            RemoteAddress::Tcp(address) => {
+                let stream = TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            #[cfg(unix)]
+            RemoteAddress::Unix(path) => Box::new(UnixStream::connect(path).await?),
+        })
+    }
+
+    /// Listen on this address. A Unix socket left behind by a bridge that didn't exit cleanly is replaced.
+    pub async fn bind(&self) -> io::Result<Listener> {
+        Ok(match self {
+            RemoteAddress::Tcp(address) => Listener::Tcp(TcpListener::bind(address).await?),
+            #[cfg(unix)]
+            RemoteAddress::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                Listener::Unix(UnixListener::bind(path)?)
+            }
+        })
+    }
+}
+
+impl Display for RemoteAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteAddress::Tcp(address) => write!(f, "{address}"),
+            #[cfg(unix)]
+            RemoteAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A stream to or from a bridge.
+pub trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Wait for a client, and describe where it's from.
+    pub async fn accept(&self) -> io::Result<(Box<dyn Connection>, String)> {
+        Ok(match self {
+            Listener::Tcp(listener) => {
+                let (stream, address) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                (Box::new(stream), address.to_string())
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => (
+                Box::new(listener.accept().await?.0),
+                "unix socket".to_string(),
+            ),
+        })
+    }
+}
+
+/// How to reach a thermometer through a bridge.
+#[derive(Clone)]
+pub struct RemoteOptions {
+    pub address: RemoteAddress,
+    /// The secret the bridge was started with, if any.
+    pub secret: Option<String>,
+    /// How long to wait before trying again when the bridge can't be reached.
+    pub retry_interval: Duration,
+}
+
+impl RemoteOptions {
+    pub fn new(address: RemoteAddress) -> Self {
+        Self {
+            address,
+            secret: None,
+            retry_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RemoteError {
+    /// The bridge couldn't be reached, or went away. It may be worth trying again later.
+    Unavailable(io::Error),
+    /// The bridge refused the connection, or doesn't speak the same protocol. Trying again won't help.
+    Rejected(String),
+}
+
+impl Display for RemoteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteError::Unavailable(e) => write!(f, "bridge unavailable: {e}"),
+            RemoteError::Rejected(reason) => write!(f, "bridge rejected connection: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RemoteError::Unavailable(e) => Some(e),
+            RemoteError::Rejected(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for RemoteError {
+    fn from(e: io::Error) -> Self {
+        RemoteError::Unavailable(e)
+    }
+}
+
+/// Connect to the bridge at `options.address`, and wait until it's connected to the thermometer.
+pub async fn connect(
+    options: &RemoteOptions,
+) -> Result<(RemoteReceiver, RemoteWriter), RemoteError> {
+    let connection = options.address.connect().await?;
+    handshake(connection, options.secret.as_deref()).await
+}
+
+/// As [connect], over a connection that's already been made.
+pub async fn handshake(
+    connection: Box<dyn Connection>,
+    secret: Option<&str>,
+) -> Result<(RemoteReceiver, RemoteWriter), RemoteError> {
+    let (read, mut write) = split(connection);
+    let mut frames = spawn_reader(read);
+
+    let hello = expect(&mut frames, HELLO).await?;
+    let [version, server_nonce @ ..] = &hello[..] else {
+        return Err(RemoteError::Rejected("malformed hello".to_string()));
+    };
+    if *version != VERSION || server_nonce.len() != NONCE_LEN {
+        return Err(RemoteError::Rejected(format!(
+            "bridge speaks protocol version {version}, not {VERSION}"
+        )));
+    }
+
+    let client_nonce = nonce();
+    let mut auth = client_nonce.to_vec();
+    if let Some(secret) = secret {
+        auth.extend(
+            mac(secret, b"client", server_nonce, &client_nonce)
+                .finalize()
+                .into_bytes(),
+        );
+    }
+    write_frame(&mut write, AUTH, &auth).await?;
+
+    let welcome = expect(&mut frames, WELCOME).await?;
+    if let Some(secret) = secret {
+        if mac(secret, b"bridge", server_nonce, &client_nonce)
+            .verify_slice(&welcome)
+            .is_err()
+        {
+            return Err(RemoteError::Rejected(
+                "bridge doesn't know the secret".to_string(),
+            ));
+        }
+    }
+
+    // Finding the thermometer can take as long as it takes.
+    match frames.recv().await {
+        Some(Ok(Frame { kind: READY, .. })) => {}
+        Some(Ok(frame)) => return Err(unexpected(frame)),
+        Some(Err(e)) => return Err(e.into()),
+        None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+    }
+
+    let receiver = RemoteReceiver { frames };
+    let writer = RemoteWriter {
+        write: Mutex::new(write),
+    };
+    Ok((receiver, writer))
+}
+
+/// Wait for the next frame during the handshake, which should be of type `kind`.
+async fn expect(
+    frames: &mut mpsc::Receiver<io::Result<Frame>>,
+    kind: u8,
+) -> Result<Bytes, RemoteError> {
+    match timeout(HANDSHAKE_TIMEOUT, frames.recv()).await {
+        Ok(Some(Ok(frame))) if frame.kind == kind => Ok(frame.payload),
+        Ok(Some(Ok(frame))) => Err(unexpected(frame)),
+        Ok(Some(Err(e))) => Err(e.into()),
+        Ok(None) => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        Err(_) => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+    }
+}
+
+fn unexpected(frame: Frame) -> RemoteError {
+    match frame.kind {
+        REJECTED => RemoteError::Rejected(String::from_utf8_lossy(&frame.payload).into_owned()),
+        kind => RemoteError::Rejected(format!("unexpected frame {kind:#04x}")),
+    }
+}
+
+pub struct RemoteReceiver {
+    frames: mpsc::Receiver<io::Result<Frame>>,
+}
+
+impl TP25Receiver for RemoteReceiver {
+    async fn get_notification(&mut self) -> Result<Notification, Error> {
+        // Frames are read by a separate task, so receiving from the channel is all that needs to be cancel safe.
+        loop {
+            match self.frames.recv().await {
+                Some(Ok(Frame {
+                    kind: NOTIFICATION,
+                    payload,
+                })) => return Ok(Notification::from(payload)),
+                Some(Ok(frame)) => {
+                    warn!("Ignoring unexpected frame {:#04x} from bridge", frame.kind)
+                }
+                Some(Err(e)) => {
+                    debug!("Lost connection to bridge: {}", e);
+                    return Err(Error::Disconnected);
+                }
+                None => return Err(Error::Disconnected),
+            }
+        }
+    }
+}
+
+pub struct RemoteWriter {
+    write: Mutex<WriteHalf<Box<dyn Connection>>>,
+}
+
+impl TP25Writer for RemoteWriter {
+    async fn send_cmd(&self, command: Command) -> Result<(), Error> {
+        let mut write = self.write.lock().await;
+        write_frame(&mut *write, COMMAND, &command.raw)
+            .await
+            .map_err(Error::transport)
+    }
+
+    async fn disconnect(&self) -> Result<(), Error> {
+        let mut write = self.write.lock().await;
+        write_frame(&mut *write, DISCONNECT, &[])
+            .await
+            .map_err(Error::transport)?;
+        write.shutdown().await.map_err(Error::transport)
+    }
+}
+
+struct Frame {
+    kind: u8,
+    payload: Bytes,
+}
+
+/// Read the next frame, or `None` if the other end closed the connection between frames.
+async fn read_frame(read: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<Frame>> {
+    let kind = match read.read_u8().await {
+        Ok(kind) => kind,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut payload = vec![0; read.read_u16().await? as usize];
+    read.read_exact(&mut payload).await?;
+    Ok(Some(Frame {
+        kind,
+        payload: Bytes::from(payload),
+    }))
+}
+
+async fn write_frame(
+    write: &mut (impl AsyncWrite + Unpin),
+    kind: u8,
+    payload: &[u8],
+) -> io::Result<()> {
+    let len = u16::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too long"))?;
+    let mut frame = Vec::with_capacity(3 + payload.len());
+    frame.push(kind);
+    frame.extend(len.to_be_bytes());
+    frame.extend(payload);
+    write.write_all(&frame).await?;
+    write.flush().await
+}
+
+/// Read frames in a task of their own, as reading a frame isn't cancel safe. The task stops at the end of the stream,
+/// after an error, or once the receiver is dropped.
+fn spawn_reader(mut read: ReadHalf<Box<dyn Connection>>) -> mpsc::Receiver<io::Result<Frame>> {
+    let (tx, rx) = mpsc::channel(32);
+    tokio::spawn(async move {
+        loop {
+            let frame = tokio::select! {
+                frame = read_frame(&mut read) => frame,
+                _ = tx.closed() => break,
+            };
+            let last = !matches!(frame, Ok(Some(_)));
+            if let Some(frame) = frame.transpose() {
+                if tx.send(frame).await.is_err() {
+                    break;
+                }
+            }
+            if last {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn nonce() -> [u8; NONCE_LEN] {
+    uuid::Uuid::new_v4().into_bytes()
+}
+
+/// Prove knowledge of `secret`, for the side of the handshake named by `label`: finalize it to send, or verify what
+/// the other side sent against it.
+fn mac(secret: &str, label: &[u8], server_nonce: &[u8], client_nonce: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(label);
+    mac.update(server_nonce);
+    mac.update(client_nonce);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bridge::Bridge;
+    use super::*;
+    use crate::dev_finder::{Backend, DeviceFinder};
+    use crate::peripheral::command::build_startup_command;
+    use crate::peripheral::notification::Decoded;
+    use assert_matches::assert_matches;
+    use tokio::io::duplex;
+    use tokio::task::JoinHandle;
+    use tokio_util::sync::CancellationToken;
+
+    fn simulator_bridge(secret: Option<&str>) -> Bridge {
+        let finder = DeviceFinder::for_backend(Backend::from_spec("simulator").unwrap());
+        Bridge::new(finder, secret.map(str::to_string))
+    }
+
+    /// Start `bridge` serving one end of a connection, and give the other end.
+    fn serve(bridge: Bridge) -> (Box<dyn Connection>, JoinHandle<Result<(), Error>>) {
+        let (client, server) = duplex(1024);
+        let served = tokio::spawn(async move {
+            bridge
+                .serve(Box::new(server), &CancellationToken::new())
+                .await
+        });
+        (Box::new(client), served)
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        let hex = |mac: Hmac<Sha256>| {
+            crate::peripheral::capture::bytes_to_hex(&mac.finalize().into_bytes())
+        };
+        assert_eq!(
+            hex(mac("\x0b".repeat(20).as_str(), b"Hi ", b"The", b"re")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hex(mac("Jefe", b"what do ya want ", b"for nothing?", b"")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn relays_a_thermometer() {
+        let (connection, served) = serve(simulator_bridge(Some("s3cret")));
+        let (mut rx, tx) = handshake(connection, Some("s3cret")).await.unwrap();
+
+        tx.send_cmd(build_startup_command()).await.unwrap();
+        let n = rx.get_notification().await.unwrap();
+        assert_eq!(&n.raw[..4], &[0x01, 0x01, 0x0a, 0x0c]);
+        assert_matches!(n.decoded, Ok(Decoded::Startup));
+        assert_matches!(
+            rx.get_notification().await.unwrap().decoded,
+            Ok(Decoded::Temperatures(_))
+        );
+
+        tx.disconnect().await.unwrap();
+        assert_matches!(served.await.unwrap(), Ok(()));
+        assert_matches!(rx.get_notification().await, Err(Error::Disconnected));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn both_ends_must_know_the_secret() {
+        for (bridge_secret, client_secret, reason) in [
+            (Some("s3cret"), Some("guess"), "wrong secret"),
+            (Some("s3cret"), None, "wrong secret"),
+            (None, Some("s3cret"), "bridge doesn't know the secret"),
+        ] {
+            let (connection, served) = serve(simulator_bridge(bridge_secret));
+            assert_matches!(
+                handshake(connection, client_secret).await.err(),
+                Some(RemoteError::Rejected(r)) if r == reason
+            );
+            if bridge_secret.is_some() {
+                assert_matches!(served.await.unwrap(), Err(Error::Transport(_)));
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reconnects_when_the_bridge_comes_back() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("bridge.sock");
+        let address = RemoteAddress::parse(&format!("unix:{}", path.display())).unwrap();
+        let finder = DeviceFinder::for_backend(Backend::Remote(RemoteOptions {
+            secret: Some("s3cret".to_string()),
+            retry_interval: Duration::from_millis(20),
+            ..RemoteOptions::new(address.clone())
+        }));
+        let start_bridge = || {
+            let address = address.clone();
+            let cancel = CancellationToken::new();
+            let stop = cancel.clone();
+            tokio::spawn(async move {
+                let listener = address.bind().await.unwrap();
+                simulator_bridge(Some("s3cret"))
+                    .run(listener, &cancel)
+                    .await;
+            });
+            stop
+        };
+
+        // The bridge isn't there to begin with.
+        let _ = std::fs::remove_file(&path);
+        let finding = tokio::spawn(async move {
+            let found = finder.get_device(&CancellationToken::new()).await;
+            (finder, found)
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!finding.is_finished());
+        let stop = start_bridge();
+        let (finder, found) = finding.await.unwrap();
+        let Ok((mut rx, tx)) = found else {
+            panic!("unable to connect through bridge");
+        };
+        tx.send_cmd(build_startup_command()).await.unwrap();
+        assert_matches!(
+            rx.get_notification().await.unwrap().decoded,
+            Ok(Decoded::Startup)
+        );
+
+        // When the bridge goes away, so does the connection, until the bridge is back.
+        stop.cancel();
+        assert_matches!(rx.get_notification().await, Err(Error::Disconnected));
+        let stop = start_bridge();
+        let found = finder.get_device(&CancellationToken::new()).await;
+        assert!(found.is_ok());
+        stop.cancel();
+    }
+}
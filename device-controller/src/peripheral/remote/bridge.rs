@@ -0,0 +1,140 @@
+//! The bridge's end of the [remote](super) protocol: it owns the connection to the thermometer, and relays it to one
+//! client at a time.
+
+use super::{
+    mac, nonce, spawn_reader, write_frame, Connection, Frame, Listener, AUTH, COMMAND, DISCONNECT,
+    HANDSHAKE_TIMEOUT, HELLO, NONCE_LEN, NOTIFICATION, READY, REJECTED, VERSION, WELCOME,
+};
+use crate::dev_finder::DeviceFinder;
+use crate::peripheral::command::Command;
+use crate::peripheral::interface::{TP25Receiver, TP25Writer};
+use crate::Error;
+use hmac::Mac;
+use log::{info, warn};
+use tokio::io::{split, AsyncWriteExt};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+pub struct Bridge {
+    finder: DeviceFinder,
+    secret: Option<String>,
+}
+
+impl Bridge {
+    /// Relay the thermometer found by `finder`. If there's a `secret`, clients must know it too.
+    pub fn new(finder: DeviceFinder, secret: Option<String>) -> Self {
+        Self { finder, secret }
+    }
+
+    /// Serve clients from `listener`, one at a time, until `cancel` is cancelled. Each client gets a fresh connection
+    /// to the thermometer, which is dropped when the client goes away.
+    pub async fn run(&self, listener: Listener, cancel: &CancellationToken) {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = cancel.cancelled() => break,
+            };
+            match accepted {
+                Ok((connection, from)) => {
+                    info!("Client connected from {}", from);
+                    match self.serve(connection, cancel).await {
+                        Ok(()) => info!("Client from {} disconnected", from),
+                        Err(e) => warn!("Client from {} disconnected: {}", from, e),
+                    }
+                }
+                Err(e) => warn!("Unable to accept client: {}", e),
+            }
+        }
+    }
+
+    /// Handle one client, until it or the thermometer goes away.
+    pub async fn serve(
+        &self,
+        connection: Box<dyn Connection>,
+        cancel: &CancellationToken,
+    ) -> Result<(), Error> {
+        let (read, mut write) = split(connection);
+        let mut frames = spawn_reader(read);
+
+        let server_nonce = nonce();
+        let mut hello = vec![VERSION];
+        hello.extend(server_nonce);
+        write_frame(&mut write, HELLO, &hello)
+            .await
+            .map_err(Error::transport)?;
+
+        let auth = match timeout(HANDSHAKE_TIMEOUT, frames.recv()).await {
+            Ok(Some(Ok(Frame {
+                kind: AUTH,
+                payload,
+            }))) if payload.len() >= NONCE_LEN => payload,
+            Ok(Some(Err(e))) => return Err(Error::transport(e)),
+            Ok(None) => return Ok(()),
+            _ => {
+                let _ = write_frame(&mut write, REJECTED, b"expected authentication").await;
+                return Err(Error::transport("client didn't authenticate"));
+            }
+        };
+        let (client_nonce, client_mac) = auth.split_at(NONCE_LEN);
+        let welcome = match &self.secret {
+            Some(secret) => {
+                if mac(secret, b"client", &server_nonce, client_nonce)
+                    .verify_slice(client_mac)
+                    .is_err()
+                {
+                    let _ = write_frame(&mut write, REJECTED, b"wrong secret").await;
+                    return Err(Error::transport("client gave the wrong secret"));
+                }
+                mac(secret, b"bridge", &server_nonce, client_nonce)
+                    .finalize()
+                    .into_bytes()
+                    .to_vec()
+            }
+            None => Vec::new(),
+        };
+        write_frame(&mut write, WELCOME, &welcome)
+            .await
+            .map_err(Error::transport)?;
+
+        // The client shouldn't send anything until it's told the thermometer is ready, so anything from it means it's
+        // gone.
+        let (mut device_rx, device_tx) = tokio::select! {
+            found = self.finder.get_device(cancel) => found?,
+            _ = frames.recv() => return Ok(()),
+        };
+        write_frame(&mut write, READY, &[])
+            .await
+            .map_err(Error::transport)?;
+
+        let result = loop {
+            tokio::select! {
+                notification = device_rx.get_notification() => {
+                    let sent = match notification {
+                        Ok(n) => write_frame(&mut write, NOTIFICATION, &n.raw).await,
+                        Err(e) => break Err(e),
+                    };
+                    if let Err(e) = sent {
+                        break Err(Error::transport(e));
+                    }
+                }
+                frame = frames.recv() => match frame {
+                    Some(Ok(Frame { kind: COMMAND, payload })) => {
+                        if let Err(e) = device_tx.send_cmd(Command::from(payload)).await {
+                            break Err(e);
+                        }
+                    }
+                    Some(Ok(Frame { kind: DISCONNECT, .. })) | None => break Ok(()),
+                    Some(Ok(frame)) => warn!("Ignoring unexpected frame {:#04x} from client", frame.kind),
+                    Some(Err(e)) => break Err(Error::transport(e)),
+                },
+                _ = cancel.cancelled() => break Ok(()),
+            }
+        };
+
+        if let Err(e) = device_tx.disconnect().await {
+            warn!("Unable to disconnect from thermometer: {}", e);
+        }
+        let _ = write.shutdown().await;
+        result
+    }
+}
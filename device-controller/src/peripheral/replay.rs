@@ -0,0 +1,361 @@
+//! A peripheral that plays back a [capture](crate::peripheral::capture) instead of talking to a thermometer, so the
+//! UIs can be demonstrated, and regression tested, with real cooks.
+//!
+//! Notifications are replayed with their recorded timing. Commands are accepted and, optionally, checked against
+//! the ones in the capture. By default each notification is also held back until as many commands have been sent as
+//! had been when it was recorded, so replies don't arrive before the commands they answer.
+
+use crate::peripheral::capture::{CaptureRecord, Direction};
+use crate::peripheral::command::Command;
+use crate::peripheral::interface::{TP25Receiver, TP25Writer};
+use crate::peripheral::notification::Notification;
+use crate::Error;
+use bytes::Bytes;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{watch, Semaphore};
+use tokio::time::{sleep_until, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    /// Notifications arrive with the same spacing as when they were recorded.
+    RealTime,
+    /// Notifications arrive this many times faster than when they were recorded.
+    Accelerated(f64),
+    /// Each notification waits for a call to [ReplayStepper::step], ignoring the recorded timing. The controller polls
+    /// the thermometer when it goes quiet, so stepping slower than [Liveness](crate::controller::connection_handler::Liveness)
+    /// allows will need `check_commands` off.
+    Step,
+}
+
+impl ReplaySpeed {
+    /// Play back `factor` times faster than recorded, as long as `factor` is a positive number.
+    pub fn accelerated(factor: f64) -> Result<Self, InvalidSpeed> {
+        // This also rules out NaN.
+        if factor > 0.0 && factor.is_finite() {
+            Ok(ReplaySpeed::Accelerated(factor))
+        } else {
+            Err(InvalidSpeed(factor))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReplayOptions {
+    pub speed: ReplaySpeed,
+    /// Fail any command that doesn't match the next one in the capture.
+    pub check_commands: bool,
+    /// Hold each notification back until as many commands have been sent as had been when it was recorded.
+    pub wait_for_commands: bool,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            speed: ReplaySpeed::RealTime,
+            check_commands: false,
+            wait_for_commands: true,
+        }
+    }
+}
+
+/// An [ReplaySpeed::Accelerated] factor that isn't a positive number.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidSpeed(pub f64);
+
+impl Display for InvalidSpeed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "replay speed must be a positive number, not {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSpeed {}
+
+/// Replay `records`, which should be in the order they were captured.
+pub fn replay(
+    records: Vec<CaptureRecord>,
+    options: ReplayOptions,
+) -> Result<(ReplayReceiver, ReplayWriter), InvalidSpeed> {
+    replay_stepped(records, options, ReplayStepper::default())
+}
+
+/// As [replay], but in [ReplaySpeed::Step] mode the notifications are released by `stepper`, which can be made before
+/// the replay starts (and shared between replays).
+pub fn replay_stepped(
+    records: Vec<CaptureRecord>,
+    options: ReplayOptions,
+    stepper: ReplayStepper,
+) -> Result<(ReplayReceiver, ReplayWriter), InvalidSpeed> {
+    if let ReplaySpeed::Accelerated(factor) = options.speed {
+        ReplaySpeed::accelerated(factor)?;
+    }
+
+    let mut notifications = Vec::new();
+    let mut commands = Vec::new();
+    for record in records {
+        let Some(bytes) = record.bytes() else {
+            continue;
+        };
+        match record.dir {
+            Direction::Command => commands.push(Bytes::from(bytes)),
+            Direction::Notification => notifications.push(RecordedNotification {
+                t: Duration::from_millis(record.t_ms),
+                commands_before: commands.len(),
+                raw: Bytes::from(bytes),
+            }),
+        }
+    }
+
+    let (sent_tx, sent_rx) = watch::channel(0);
+    let receiver = ReplayReceiver {
+        notifications,
+        next: 0,
+        base: Instant::now(),
+        options,
+        commands_sent: sent_rx,
+        steps: stepper.steps,
+    };
+    let writer = ReplayWriter {
+        commands: Arc::new(commands),
+        check_commands: options.check_commands,
+        commands_sent: Arc::new(Mutex::new(sent_tx)),
+    };
+    Ok((receiver, writer))
+}
+
+struct RecordedNotification {
+    /// When it was received, relative to the start of the capture.
+    t: Duration,
+    /// How many commands had been sent when it was received.
+    commands_before: usize,
+    raw: Bytes,
+}
+
+pub struct ReplayReceiver {
+    notifications: Vec<RecordedNotification>,
+    next: usize,
+    /// When the start of the capture is replayed from.
+    base: Instant,
+    options: ReplayOptions,
+    commands_sent: watch::Receiver<usize>,
+    steps: Arc<Semaphore>,
+}
+
+impl ReplayReceiver {
+    /// Something to release notifications with, in [ReplaySpeed::Step] mode.
+    pub fn stepper(&self) -> ReplayStepper {
+        ReplayStepper {
+            steps: self.steps.clone(),
+        }
+    }
+
+    fn due(&self, t: Duration) -> Instant {
+        match self.options.speed {
+            ReplaySpeed::RealTime => self.base + t,
+            ReplaySpeed::Accelerated(factor) => self.base + t.div_f64(factor),
+            ReplaySpeed::Step => self.base,
+        }
+    }
+}
+
+impl TP25Receiver for ReplayReceiver {
+    async fn get_notification(&mut self) -> Result<Notification, Error> {
+        // Nothing below changes any state until the last await, so this is cancel safe.
+        let Some(recorded) = self.notifications.get(self.next) else {
+            return Err(Error::Disconnected);
+        };
+
+        if self.options.wait_for_commands {
+            let needed = recorded.commands_before;
+            self.commands_sent
+                .wait_for(|sent| *sent >= needed)
+                .await
+                .map_err(|_| Error::Disconnected)?;
+        }
+
+        let due = self.due(recorded.t);
+        sleep_until(due).await;
+
+        if self.options.speed == ReplaySpeed::Step {
+            self.steps
+                .acquire()
+                .await
+                .map_err(|_| Error::Disconnected)?
+                .forget();
+        }
+
+        // If waiting for a command held this notification back, keep the rest of the capture spaced as it was.
+        let now = Instant::now();
+        if now > due {
+            self.base += now - due;
+        }
+        let raw = recorded.raw.clone();
+        self.next += 1;
+        Ok(Notification::from(raw))
+    }
+}
+
+/// Releases notifications one at a time, in [ReplaySpeed::Step] mode.
+#[derive(Clone)]
+pub struct ReplayStepper {
+    steps: Arc<Semaphore>,
+}
+
+impl Default for ReplayStepper {
+    fn default() -> Self {
+        Self {
+            steps: Arc::new(Semaphore::new(0)),
+        }
+    }
+}
+
+impl ReplayStepper {
+    /// Let the next notification through. Steps taken before it's asked for aren't lost.
+    pub fn step(&self) {
+        self.steps.add_permits(1);
+    }
+}
+
+#[derive(Clone)]
+pub struct ReplayWriter {
+    /// The commands in the capture, in order.
+    commands: Arc<Vec<Bytes>>,
+    check_commands: bool,
+    commands_sent: Arc<Mutex<watch::Sender<usize>>>,
+}
+
+impl TP25Writer for ReplayWriter {
+    async fn send_cmd(&self, command: Command) -> Result<(), Error> {
+        let sent = self.commands_sent.lock().unwrap();
+        let index = *sent.borrow();
+        if self.check_commands {
+            match self.commands.get(index) {
+                Some(expected) if *expected == command.raw => {}
+                Some(expected) => {
+                    return Err(Error::transport(format!(
+                        "command {} was {:02x?}, but the capture has {:02x?}",
+                        index + 1,
+                        command.raw.as_ref(),
+                        expected.as_ref()
+                    )))
+                }
+                None => {
+                    return Err(Error::transport(format!(
+                        "command {} was {:02x?}, but the capture only has {} commands",
+                        index + 1,
+                        command.raw.as_ref(),
+                        self.commands.len()
+                    )))
+                }
+            }
+        }
+        sent.send_replace(index + 1);
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripheral::command::{build_alarm_ack_cmd, build_temp_report_cmd};
+    use crate::peripheral::notification::Decoded;
+    use assert_matches::assert_matches;
+    use tokio::time::timeout;
+
+    const ALARM_ACK: [u8; 3] = [0x27, 0x00, 0x27];
+
+    fn capture() -> Vec<CaptureRecord> {
+        vec![
+            CaptureRecord::new(0, Direction::Command, &ALARM_ACK),
+            CaptureRecord::new(100, Direction::Notification, &ALARM_ACK),
+            CaptureRecord::new(1100, Direction::Notification, &ALARM_ACK),
+        ]
+    }
+
+    fn options(speed: ReplaySpeed) -> ReplayOptions {
+        ReplayOptions {
+            speed,
+            ..ReplayOptions::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replays_with_recorded_timing() {
+        let start = Instant::now();
+        let (mut receiver, writer) = replay(capture(), options(ReplaySpeed::RealTime)).unwrap();
+
+        // The first notification answers a command, so it waits for one.
+        assert_matches!(
+            timeout(Duration::from_secs(5), receiver.get_notification()).await,
+            Err(_)
+        );
+        let sent_at = Instant::now();
+        writer.send_cmd(build_alarm_ack_cmd()).await.unwrap();
+
+        let n = receiver.get_notification().await.unwrap();
+        assert_matches!(n.decoded, Ok(Decoded::AlarmAck));
+        assert_eq!(Instant::now(), sent_at);
+
+        receiver.get_notification().await.unwrap();
+        assert_eq!(Instant::now(), sent_at + Duration::from_secs(1));
+
+        assert_matches!(receiver.get_notification().await, Err(Error::Disconnected));
+        assert!(start.elapsed() < Duration::from_secs(7));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accelerates_and_steps() {
+        let start = Instant::now();
+        let (mut receiver, writer) =
+            replay(capture(), options(ReplaySpeed::Accelerated(10.0))).unwrap();
+        writer.send_cmd(build_alarm_ack_cmd()).await.unwrap();
+        receiver.get_notification().await.unwrap();
+        receiver.get_notification().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(110));
+
+        let (mut receiver, writer) = replay(capture(), options(ReplaySpeed::Step)).unwrap();
+        let stepper = receiver.stepper();
+        writer.send_cmd(build_alarm_ack_cmd()).await.unwrap();
+        assert_matches!(
+            timeout(Duration::from_secs(60), receiver.get_notification()).await,
+            Err(_)
+        );
+        stepper.step();
+        stepper.step();
+        receiver.get_notification().await.unwrap();
+        receiver.get_notification().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn checks_commands() {
+        let (_, writer) = replay(
+            capture(),
+            ReplayOptions {
+                check_commands: true,
+                ..ReplayOptions::default()
+            },
+        )
+        .unwrap();
+        assert_matches!(
+            writer.send_cmd(build_temp_report_cmd()).await,
+            Err(Error::Transport(_))
+        );
+        assert_matches!(writer.send_cmd(build_alarm_ack_cmd()).await, Ok(()));
+        assert_matches!(
+            writer.send_cmd(build_alarm_ack_cmd()).await,
+            Err(Error::Transport(_))
+        );
+    }
+
+    #[test]
+    fn rejects_speeds_that_are_not_positive() {
+        for factor in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(replay(capture(), options(ReplaySpeed::Accelerated(factor))).is_err());
+        }
+    }
+}
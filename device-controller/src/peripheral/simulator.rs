@@ -0,0 +1,824 @@
+//! A simulated thermometer, for trying out the UIs and testing the controller without a real one.
+//!
+//! It plays back a [Scenario]: how each probe heats up, when probes are plugged in and pulled out, and when the link
+//! drops, responses are slow or the thermometer reboots. Everything runs on tokio time, so with the clock paused a
+//! whole cook takes milliseconds.
+//!
+//! The protocol is spoken by the same engine as the [dummy](crate::peripheral::dummy) device, so responses are real
+//! padded TLVC frames, alarms go off when the probes cross their thresholds, and unknown commands get an error
+//! response. On top of that, a reboot forgets which alarms are armed, as a real TP25 does.
+//!
+//! Scenarios can be written in TOML (or the equivalent JSON). Times are in seconds from the start of the scenario, and
+//! temperatures are in degrees Celsius:
+//!
+//! ```toml
+//! report_interval_secs = 1.0
+//!
+//! [[probes]]
+//! probe = 1
+//! start_temp = 5.0
+//! ambient_temp = 110.0
+//! time_constant_secs = 5400.0
+//! stall = { temp = 68.0, duration_secs = 3600.0 }
+//!
+//! [[probes]]
+//! probe = 4
+//! start_temp = 20.0
+//! ambient_temp = 110.0
+//! time_constant_secs = 300.0
+//! plugged_in_secs = 60.0
+//! unplugged_secs = 7200.0
+//!
+//! [[link_drops]]
+//! at_secs = 1800.0
+//! duration_secs = 30.0
+//!
+//! [[delays]]
+//! from_secs = 600.0
+//! to_secs = 900.0
+//! delay_secs = 1.5
+//!
+//! [[reboots]]
+//! at_secs = 3600.0
+//! ```
+
+use crate::model::device_temperature::DeviceTemperature::{InRange, OutOfRange};
+use crate::model::device_temperature::{DeviceTemperature, InRangeDeviceTemperature};
+use crate::peripheral::command::Command;
+use crate::peripheral::dummy::Thermometer;
+use crate::peripheral::interface::{TP25Receiver, TP25Writer};
+use crate::peripheral::notification::Notification;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{sleep_until, Instant};
+
+/// The highest temperature a probe can report.
+const MAX_TEMP: f32 = 999.9;
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    /// The scenario couldn't be parsed.
+    Parse(String),
+    /// The scenario parsed, but doesn't make sense.
+    Invalid(String),
+}
+
+impl Display for ScenarioError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "unable to read scenario: {e}"),
+            ScenarioError::Parse(e) => write!(f, "unable to parse scenario: {e}"),
+            ScenarioError::Invalid(e) => write!(f, "invalid scenario: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScenarioError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// What happens to a simulated thermometer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    /// How often the thermometer sends temperatures by itself.
+    pub report_interval_secs: f64,
+    /// The probes that are plugged in at some point. Probes not listed are never plugged in.
+    pub probes: Vec<ProbeScenario>,
+    pub link_drops: Vec<LinkDrop>,
+    pub delays: Vec<ResponseDelay>,
+    pub reboots: Vec<Reboot>,
+}
+
+impl Default for Scenario {
+    /// A brisket, a chicken and a probe measuring the smoker.
+    fn default() -> Self {
+        Self {
+            report_interval_secs: 1.0,
+            probes: vec![
+                ProbeScenario {
+                    stall: Some(Stall {
+                        temp: 68.0,
+                        duration_secs: 3600.0,
+                    }),
+                    ..ProbeScenario::new(1, 5.0, 110.0, 5400.0)
+                },
+                ProbeScenario::new(2, 5.0, 110.0, 3600.0),
+                ProbeScenario::new(4, 20.0, 110.0, 300.0),
+            ],
+            link_drops: Vec::new(),
+            delays: Vec::new(),
+            reboots: Vec::new(),
+        }
+    }
+}
+
+impl Scenario {
+    pub fn from_toml(s: &str) -> Result<Self, ScenarioError> {
+        toml::from_str::<Scenario>(s)
+            .map_err(|e| ScenarioError::Parse(e.to_string()))?
+            .validated()
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, ScenarioError> {
+        serde_json::from_str::<Scenario>(s)
+            .map_err(|e| ScenarioError::Parse(e.to_string()))?
+            .validated()
+    }
+
+    /// Load a scenario from a file, which is JSON if its name ends `.json` and TOML otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        if path.extension().is_some_and(|e| e == "json") {
+            Self::from_json(&s)
+        } else {
+            Self::from_toml(&s)
+        }
+    }
+
+    fn validated(self) -> Result<Self, ScenarioError> {
+        let invalid = |e: String| Err(ScenarioError::Invalid(e));
+        let mut seen = [false; 4];
+        for p in &self.probes {
+            let Some(seen) = seen.get_mut((p.probe as usize).wrapping_sub(1)) else {
+                return invalid(format!("there is no probe {}", p.probe));
+            };
+            if *seen {
+                return invalid(format!("probe {} is given more than once", p.probe));
+            }
+            *seen = true;
+            if p.time_constant_secs <= 0.0 {
+                return invalid(format!("probe {} has no time constant", p.probe));
+            }
+        }
+        if self.report_interval_secs <= 0.0 {
+            return invalid("the report interval must be positive".to_string());
+        }
+        Ok(self)
+    }
+
+    fn probe(&self, index: usize) -> Option<&ProbeScenario> {
+        self.probes.iter().find(|p| p.probe as usize == index + 1)
+    }
+
+    /// When the link drops, from link drops and reboots, soonest first.
+    fn outages(&self) -> Vec<(Duration, Outage)> {
+        let mut outages: Vec<_> = self
+            .link_drops
+            .iter()
+            .map(|d| (secs(d.at_secs), Outage::LinkDrop))
+            .chain(
+                self.reboots
+                    .iter()
+                    .map(|r| (secs(r.at_secs), Outage::Reboot)),
+            )
+            .collect();
+        outages.sort_by_key(|(at, _)| *at);
+        outages
+    }
+
+    /// If the link is down at `elapsed`, when it comes back.
+    fn link_down_until(&self, elapsed: Duration) -> Option<Duration> {
+        self.link_drops
+            .iter()
+            .map(|d| (secs(d.at_secs), secs(d.at_secs + d.duration_secs)))
+            .filter(|(from, to)| (*from..*to).contains(&elapsed))
+            .map(|(_, to)| to)
+            .max()
+    }
+
+    fn response_delay(&self, elapsed: Duration) -> Duration {
+        self.delays
+            .iter()
+            .filter(|d| (secs(d.from_secs)..secs(d.to_secs)).contains(&elapsed))
+            .map(|d| secs(d.delay_secs))
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// How a probe heats up. It follows Newton's law of cooling, heading towards the ambient temperature more slowly the
+/// closer it gets, and may stall on the way.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProbeScenario {
+    /// 1 to 4, as on the thermometer.
+    pub probe: u8,
+    /// The temperature when the probe is plugged in.
+    pub start_temp: f32,
+    /// The temperature of whatever the probe is heating up in, such as the smoker.
+    pub ambient_temp: f32,
+    /// How quickly the probe heats up: the time it takes to get about two thirds of the way to the ambient temperature.
+    pub time_constant_secs: f64,
+    #[serde(default)]
+    pub plugged_in_secs: f64,
+    #[serde(default)]
+    pub unplugged_secs: Option<f64>,
+    #[serde(default)]
+    pub stall: Option<Stall>,
+}
+
+impl ProbeScenario {
+    pub fn new(probe: u8, start_temp: f32, ambient_temp: f32, time_constant_secs: f64) -> Self {
+        Self {
+            probe,
+            start_temp,
+            ambient_temp,
+            time_constant_secs,
+            plugged_in_secs: 0.0,
+            unplugged_secs: None,
+            stall: None,
+        }
+    }
+
+    /// The probe's temperature `elapsed` seconds into the scenario, or `None` if it isn't plugged in.
+    pub fn temperature_at(&self, elapsed: f64) -> Option<f32> {
+        if elapsed < self.plugged_in_secs || self.unplugged_secs.is_some_and(|u| elapsed >= u) {
+            return None;
+        }
+        let t = elapsed - self.plugged_in_secs;
+        let start = self.start_temp as f64;
+        let ambient = self.ambient_temp as f64;
+        let tau = self.time_constant_secs;
+        let newton = |from: f64, t: f64| ambient + (from - ambient) * (-t / tau).exp();
+
+        let temp = match self.stall {
+            // Only a stall between the start and ambient temperatures is ever reached.
+            Some(stall) if (stall.temp as f64 - start) * (ambient - stall.temp as f64) > 0.0 => {
+                let stall_temp = stall.temp as f64;
+                let reached = tau * ((start - ambient) / (stall_temp - ambient)).ln();
+                if t < reached {
+                    newton(start, t)
+                } else if t < reached + stall.duration_secs {
+                    stall_temp
+                } else {
+                    newton(stall_temp, t - reached - stall.duration_secs)
+                }
+            }
+            _ => newton(start, t),
+        };
+        Some((temp as f32).clamp(0.0, MAX_TEMP))
+    }
+}
+
+/// The probe stops heating up for a while at a particular temperature, as large cuts of meat do while moisture
+/// evaporates from them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stall {
+    pub temp: f32,
+    pub duration_secs: f64,
+}
+
+/// The thermometer can't be reached for a while, for example because it's out of range. Any connection is lost.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkDrop {
+    pub at_secs: f64,
+    pub duration_secs: f64,
+}
+
+/// The thermometer is slow to respond to commands for a while.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResponseDelay {
+    pub from_secs: f64,
+    pub to_secs: f64,
+    pub delay_secs: f64,
+}
+
+/// The thermometer restarts, for example because its batteries were changed. Any connection is lost, and the alarm
+/// profiles are kept but no longer active until they're set again.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Reboot {
+    pub at_secs: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outage {
+    LinkDrop,
+    Reboot,
+}
+
+fn secs(s: f64) -> Duration {
+    Duration::try_from_secs_f64(s).unwrap_or_default()
+}
+
+/// A simulated thermometer. It can be connected to many times, though only the latest connection works.
+#[derive(Clone)]
+pub struct Simulator {
+    state: Arc<Mutex<SimulatorState>>,
+    /// Wakes the receiver when a response is queued.
+    queued: Arc<Notify>,
+}
+
+struct SimulatorState {
+    scenario: Scenario,
+    start: Instant,
+    outages: Vec<(Duration, Outage)>,
+    outages_passed: usize,
+    thermometer: Thermometer,
+    /// Incremented whenever the connection is lost, so that old receivers and writers know they're done with.
+    connection: u64,
+    /// Responses, and when they're due to be sent.
+    responses: VecDeque<(Instant, Notification)>,
+    next_report: Instant,
+}
+
+impl Simulator {
+    /// Start playing back `scenario`, from now.
+    pub fn new(scenario: Scenario) -> Self {
+        let start = Instant::now();
+        Self {
+            state: Arc::new(Mutex::new(SimulatorState {
+                outages: scenario.outages(),
+                scenario,
+                start,
+                outages_passed: 0,
+                thermometer: Thermometer::default(),
+                connection: 0,
+                responses: VecDeque::new(),
+                next_report: start,
+            })),
+            queued: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Another simulated thermometer, playing the same scenario from now but otherwise independent of this one.
+    pub fn another(&self) -> Self {
+        Self::new(self.state.lock().unwrap().scenario.clone())
+    }
+
+    /// Connect to the thermometer, waiting for the link to come back if it's down.
+    pub async fn connect(&self) -> (SimulatedReceiver, SimulatedWriter) {
+        loop {
+            let down_until = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                state.advance(now);
+                match state.scenario.link_down_until(now - state.start) {
+                    Some(until) => state.start + until,
+                    None => {
+                        state.disconnect();
+                        state.thermometer.started = false;
+                        let connection = state.connection;
+                        let receiver = SimulatedReceiver {
+                            simulator: self.clone(),
+                            connection,
+                        };
+                        let writer = SimulatedWriter {
+                            simulator: self.clone(),
+                            connection,
+                        };
+                        return (receiver, writer);
+                    }
+                }
+            };
+            sleep_until(down_until).await;
+        }
+    }
+}
+
+impl SimulatorState {
+    /// Catch up with the scenario's link drops and reboots.
+    fn advance(&mut self, now: Instant) {
+        while let Some((at, outage)) = self.outages.get(self.outages_passed).copied() {
+            if self.start + at > now {
+                break;
+            }
+            self.outages_passed += 1;
+            self.disconnect();
+            if let Outage::Reboot = outage {
+                self.thermometer.reboot();
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.connection += 1;
+        self.responses.clear();
+    }
+
+    fn next_outage(&self) -> Option<Instant> {
+        self.outages
+            .get(self.outages_passed)
+            .map(|(at, _)| self.start + *at)
+    }
+
+    fn temps(&self, now: Instant) -> [DeviceTemperature; 4] {
+        let elapsed = (now - self.start).as_secs_f64();
+        [0, 1, 2, 3].map(|i| {
+            self.scenario
+                .probe(i)
+                .and_then(|p| p.temperature_at(elapsed))
+                .map_or(OutOfRange, |t| InRange(InRangeDeviceTemperature::from(t)))
+        })
+    }
+
+    fn report_interval(&self) -> Duration {
+        secs(self.scenario.report_interval_secs)
+    }
+}
+
+pub struct SimulatedReceiver {
+    simulator: Simulator,
+    connection: u64,
+}
+
+impl TP25Receiver for SimulatedReceiver {
+    async fn get_notification(&mut self) -> Result<Notification, Error> {
+        // State only changes as the notification is returned, or as the scenario says it should, so this is cancel
+        // safe.
+        loop {
+            let wake_at = {
+                let mut state = self.simulator.state.lock().unwrap();
+                let now = Instant::now();
+                state.advance(now);
+                if state.connection != self.connection {
+                    return Err(Error::Disconnected);
+                }
+                if state.responses.front().is_some_and(|(due, _)| *due <= now) {
+                    return Ok(state.responses.pop_front().unwrap().1);
+                }
+                if state.thermometer.started && state.next_report <= now {
+                    state.next_report = now + state.report_interval();
+                    let temps = state.temps(now);
+                    return Ok(state.thermometer.temperature_report(temps));
+                }
+                [
+                    state.responses.front().map(|(due, _)| *due),
+                    state.thermometer.started.then_some(state.next_report),
+                    state.next_outage(),
+                ]
+                .into_iter()
+                .flatten()
+                .min()
+            };
+            match wake_at {
+                Some(wake_at) => tokio::select! {
+                    _ = sleep_until(wake_at) => {},
+                    _ = self.simulator.queued.notified() => {},
+                },
+                None => self.simulator.queued.notified().await,
+            }
+        }
+    }
+}
+
+pub struct SimulatedWriter {
+    simulator: Simulator,
+    connection: u64,
+}
+
+impl TP25Writer for SimulatedWriter {
+    async fn send_cmd(&self, command: Command) -> Result<(), Error> {
+        {
+            let mut state = self.simulator.state.lock().unwrap();
+            let now = Instant::now();
+            state.advance(now);
+            if state.connection != self.connection {
+                return Err(Error::Disconnected);
+            }
+            let was_started = state.thermometer.started;
+            let temps = state.temps(now);
+            let response = state.thermometer.respond(command, temps);
+            if state.thermometer.started && !was_started {
+                state.next_report = now + state.report_interval();
+            }
+            let due = now + state.scenario.response_delay(now - state.start);
+            state.responses.push_back((due, response));
+        }
+        self.simulator.queued.notify_one();
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<(), Error> {
+        let mut state = self.simulator.state.lock().unwrap();
+        if state.connection == self.connection {
+            state.disconnect();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::command_request::{command_channel, CommandRequest};
+    use crate::controller::connection_handler::ConnectionHandler;
+    use crate::controller::connection_mgr::ProtectedDeviceState;
+    use crate::controller::handle::Publisher;
+    use crate::model::device::TP25State;
+    use crate::model::probe::{AlarmState, AlarmThreshold, ProbeIdx, UpperLimitThreshold};
+    use crate::peripheral::command::{
+        build_alarm_ack_cmd, build_custom_cmd, build_report_profile_cmd, build_set_profile_cmd,
+        build_startup_command,
+    };
+    use crate::peripheral::dummy::NOTIFICATION_LEN;
+    use crate::peripheral::notification::Decoded as N;
+    use assert_matches::assert_matches;
+    use tokio_util::sync::CancellationToken;
+
+    async fn respond(
+        rx: &mut SimulatedReceiver,
+        tx: &SimulatedWriter,
+        command: Command,
+    ) -> Notification {
+        tx.send_cmd(command).await.unwrap();
+        rx.get_notification().await.unwrap()
+    }
+
+    fn upper_limit(max: u16) -> AlarmThreshold {
+        AlarmThreshold::UpperLimit(UpperLimitThreshold {
+            max: InRangeDeviceTemperature::new(max, 0),
+        })
+    }
+
+    fn temps(n: Notification) -> TemperatureReport {
+        match n.decoded {
+            Ok(N::Temperatures(data)) => TemperatureReport {
+                temps: data.temps.map(|t| t.temp),
+                alarms: data.temps.map(|t| t.alarm),
+            },
+            other => panic!("unexpected notification {other:?}"),
+        }
+    }
+
+    struct TemperatureReport {
+        temps: [DeviceTemperature; 4],
+        alarms: [bool; 4],
+    }
+
+    #[test]
+    fn probes_heat_up_and_stall() {
+        let probe = ProbeScenario {
+            plugged_in_secs: 100.0,
+            unplugged_secs: Some(10_000.0),
+            stall: Some(Stall {
+                temp: 60.0,
+                duration_secs: 1000.0,
+            }),
+            ..ProbeScenario::new(1, 10.0, 110.0, 500.0)
+        };
+        let temp = |t| probe.temperature_at(t);
+        let close = |a: Option<f32>, b: f32| a.is_some_and(|a| (a - b).abs() < 0.01);
+
+        assert_eq!(temp(99.0), None);
+        assert!(close(temp(100.0), 10.0));
+        // It takes τ·ln(100 / 50) to get half way.
+        let stall_start = 100.0 + 500.0 * 2f64.ln();
+        assert!(close(
+            temp(stall_start - 200.0),
+            110.0 - 100.0 * (0.4f32 - 2f32.ln()).exp()
+        ));
+        assert!(close(temp(stall_start + 1.0), 60.0));
+        assert!(close(temp(stall_start + 999.0), 60.0));
+        assert!(close(
+            temp(stall_start + 1500.0),
+            110.0 - 50.0 * (-1f32).exp()
+        ));
+        assert_eq!(temp(10_000.0), None);
+
+        // A stall the probe never gets to makes no difference.
+        let cooling = ProbeScenario {
+            stall: Some(Stall {
+                temp: 60.0,
+                duration_secs: 1000.0,
+            }),
+            ..ProbeScenario::new(1, 50.0, 20.0, 500.0)
+        };
+        assert!(close(
+            cooling.temperature_at(500.0),
+            20.0 + 30.0 * (-1f32).exp()
+        ));
+    }
+
+    #[test]
+    fn loads_toml_and_json() {
+        let toml = r#"
+            report_interval_secs = 2.0
+
+            [[probes]]
+            probe = 3
+            start_temp = 5.0
+            ambient_temp = 110.0
+            time_constant_secs = 5400.0
+            unplugged_secs = 7200.0
+            stall = { temp = 68.0, duration_secs = 3600.0 }
+
+            [[link_drops]]
+            at_secs = 1800.0
+            duration_secs = 30.0
+
+            [[delays]]
+            from_secs = 600.0
+            to_secs = 900.0
+            delay_secs = 1.5
+
+            [[reboots]]
+            at_secs = 3600.0
+        "#;
+        let scenario = Scenario::from_toml(toml).unwrap();
+        assert_eq!(scenario.report_interval_secs, 2.0);
+        assert_eq!(scenario.probes[0].probe, 3);
+        assert_eq!(scenario.probes[0].plugged_in_secs, 0.0);
+        assert_eq!(scenario.reboots, vec![Reboot { at_secs: 3600.0 }]);
+
+        let json = serde_json::to_string(&scenario).unwrap();
+        assert_eq!(Scenario::from_json(&json).unwrap(), scenario);
+
+        assert_matches!(
+            Scenario::from_toml("[[probes]]\nprobe = 1"),
+            Err(ScenarioError::Parse(_))
+        );
+        assert_matches!(
+            Scenario::from_json(
+                r#"{"probes": [{"probe": 5, "start_temp": 0, "ambient_temp": 0, "time_constant_secs": 1}]}"#
+            ),
+            Err(ScenarioError::Invalid(_))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn raises_and_acknowledges_alarms() {
+        let start = Instant::now();
+        let (mut rx, tx) = Simulator::new(Scenario::default()).connect().await;
+        let n = respond(&mut rx, &tx, build_startup_command()).await;
+        assert_eq!(n.raw.len(), NOTIFICATION_LEN);
+        assert_eq!(&n.raw[..4], &[0x01, 0x01, 0x0a, 0x0c]);
+        assert_matches!(n.decoded, Ok(N::Startup));
+
+        // Probe 3 isn't in the scenario, so it's never plugged in.
+        let report = temps(rx.get_notification().await.unwrap());
+        assert_matches!(report.temps[0], InRange(t) if f32::from(t) == 5.0);
+        assert_matches!(report.temps[2], OutOfRange);
+
+        // The smoker probe heats up from 20, getting past 30 after 35.3 seconds.
+        let threshold = upper_limit(30);
+        respond(
+            &mut rx,
+            &tx,
+            build_set_profile_cmd(ProbeIdx::Probe4, threshold),
+        )
+        .await;
+        let n = respond(&mut rx, &tx, build_report_profile_cmd(ProbeIdx::Probe4)).await;
+        assert_eq!(n.raw.len(), NOTIFICATION_LEN);
+        assert_matches!(n.decoded, Ok(N::ReportProbeProfile(data)) if data.threshold == threshold);
+
+        while !temps(rx.get_notification().await.unwrap()).alarms[3] {}
+        assert_eq!(start.elapsed(), Duration::from_secs(36));
+
+        assert_matches!(
+            respond(&mut rx, &tx, build_alarm_ack_cmd()).await.decoded,
+            Ok(N::AlarmAck)
+        );
+        assert!(!temps(rx.get_notification().await.unwrap()).alarms[3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejects_malformed_commands() {
+        let (mut rx, tx) = Simulator::new(Scenario::default()).connect().await;
+        for raw in [vec![0x27, 0x00, 0x28], vec![0x42, 0x00, 0x42], vec![0x24]] {
+            let n = respond(&mut rx, &tx, build_custom_cmd(raw)).await;
+            assert_matches!(n.decoded, Ok(N::Error));
+        }
+
+        // A well formed custom command is answered as normal.
+        let n = respond(&mut rx, &tx, build_custom_cmd(vec![0x41, 0x00, 0x41])).await;
+        assert_matches!(n.decoded, Ok(N::Unknown41(_)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delays_responses() {
+        let scenario = Scenario {
+            delays: vec![ResponseDelay {
+                from_secs: 10.0,
+                to_secs: 20.0,
+                delay_secs: 2.5,
+            }],
+            ..Scenario::default()
+        };
+        let (mut rx, tx) = Simulator::new(scenario).connect().await;
+        let start = Instant::now();
+        respond(&mut rx, &tx, build_alarm_ack_cmd()).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        let sent = Instant::now();
+        assert_matches!(
+            respond(&mut rx, &tx, build_alarm_ack_cmd()).await.decoded,
+            Ok(N::AlarmAck)
+        );
+        assert_eq!(sent.elapsed(), Duration::from_millis(2500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drops_the_link_and_forgets_profiles_on_reboot() {
+        let scenario = Scenario {
+            link_drops: vec![LinkDrop {
+                at_secs: 10.0,
+                duration_secs: 20.0,
+            }],
+            reboots: vec![Reboot { at_secs: 60.0 }],
+            ..Scenario::default()
+        };
+        let simulator = Simulator::new(scenario);
+        let start = Instant::now();
+        let (mut rx, tx) = simulator.connect().await;
+        respond(&mut rx, &tx, build_startup_command()).await;
+        // The smoker probe starts at 20, so it alarms straight away.
+        respond(
+            &mut rx,
+            &tx,
+            build_set_profile_cmd(ProbeIdx::Probe4, upper_limit(20)),
+        )
+        .await;
+        assert!(temps(rx.get_notification().await.unwrap()).alarms[3]);
+
+        while rx.get_notification().await.is_ok() {}
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+        assert_matches!(
+            tx.send_cmd(build_alarm_ack_cmd()).await,
+            Err(Error::Disconnected)
+        );
+
+        // Connecting waits for the link to come back, and the profile is still active.
+        let (mut rx, tx) = simulator.connect().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+        respond(&mut rx, &tx, build_startup_command()).await;
+        assert!(temps(rx.get_notification().await.unwrap()).alarms[3]);
+
+        // After the reboot, the profile is still there but doesn't alarm until it's set again.
+        while rx.get_notification().await.is_ok() {}
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+        let (mut rx, tx) = simulator.connect().await;
+        respond(&mut rx, &tx, build_startup_command()).await;
+        assert!(!temps(rx.get_notification().await.unwrap()).alarms[3]);
+        let n = respond(&mut rx, &tx, build_report_profile_cmd(ProbeIdx::Probe4)).await;
+        assert_matches!(n.decoded, Ok(N::ReportProbeProfile(data)) if data.threshold == upper_limit(20));
+        respond(
+            &mut rx,
+            &tx,
+            build_set_profile_cmd(ProbeIdx::Probe4, upper_limit(20)),
+        )
+        .await;
+        assert!(temps(rx.get_notification().await.unwrap()).alarms[3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cooks_a_brisket() {
+        let simulator = Simulator::new(Scenario::default());
+        let (rx, tx) = simulator.connect().await;
+        let publisher = Publisher::new(TP25State::default());
+        let mut state_rx = publisher.subscribe_state();
+        let (command_tx, command_rx) = command_channel(10);
+        tokio::spawn(async move {
+            ConnectionHandler::default()
+                .handle_one_connection(
+                    rx,
+                    tx,
+                    &ProtectedDeviceState::default(),
+                    &publisher,
+                    Arc::new(tokio::sync::Mutex::new(command_rx)),
+                    &CancellationToken::new(),
+                )
+                .await
+        });
+
+        let start = Instant::now();
+        command_tx
+            .request(CommandRequest::SetProfile(
+                ProbeIdx::Probe1,
+                upper_limit(95),
+            ))
+            .await
+            .unwrap();
+        let mut stalled = false;
+        loop {
+            state_rx.changed().await.unwrap();
+            let probe = state_rx.borrow_and_update().probes[0];
+            stalled |= matches!(probe.temperature, InRange(t) if f32::from(t) == 68.0);
+            if let AlarmState::Alarm = probe.alarm {
+                break;
+            }
+        }
+
+        // About 82 minutes to the stall, an hour stalled, and 92 minutes from there until it reads 95.0 (at 94.95).
+        assert!(stalled);
+        assert_eq!(start.elapsed().as_secs() / 60, 3 * 60 + 54);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::peripheral::command::Command;
 use crate::peripheral::notification::Notification;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum Transfer {
     Notification(Notification),
     Command(Command),
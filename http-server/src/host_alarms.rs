@@ -0,0 +1,158 @@
+use device_controller::controller::host_alarm::{
+    AlarmCondition, AlarmRule, HostAlarmState, HostAlarmStatus,
+};
+use device_controller::model::probe::ProbeIdx;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// A host alarm, as given in the file passed to `--host-alarms`.
+#[derive(Deserialize)]
+struct RuleData {
+    name: String,
+    #[serde(flatten)]
+    condition: ConditionData,
+    #[serde(default)]
+    hysteresis: f32,
+}
+
+/// The condition a host alarm watches for. Probe indexes are zero-based, as they are in the rest of the interface.
+#[derive(Deserialize)]
+#[serde(tag = "condition", rename_all = "snake_case")]
+enum ConditionData {
+    Above {
+        probe_idx: u8,
+        limit: f32,
+    },
+    Below {
+        probe_idx: u8,
+        limit: f32,
+    },
+    Approaching {
+        probe_idx: u8,
+        target: f32,
+        margin: f32,
+    },
+    RisingFaster {
+        probe_idx: u8,
+        degrees_per_minute: f32,
+    },
+    FallingFaster {
+        probe_idx: u8,
+        degrees_per_minute: f32,
+    },
+    Unplugged {
+        probe_idx: u8,
+    },
+    NoData {
+        after_secs: u64,
+    },
+    DifferenceBelow {
+        hotter_idx: u8,
+        cooler_idx: u8,
+        limit: f32,
+    },
+    DifferenceAbove {
+        hotter_idx: u8,
+        cooler_idx: u8,
+        limit: f32,
+    },
+}
+
+/// Read the host alarms from a JSON file.
+pub fn load_rules(path: &Path) -> Result<Vec<AlarmRule>, String> {
+    let s = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let rules: Vec<RuleData> = serde_json::from_str(&s).map_err(|e| e.to_string())?;
+    rules
+        .into_iter()
+        .map(|rule| {
+            Ok(AlarmRule {
+                hysteresis: rule.hysteresis,
+                ..AlarmRule::new(&rule.name, condition(rule.condition)?)
+            })
+        })
+        .collect()
+}
+
+fn probe(idx: u8) -> Result<ProbeIdx, String> {
+    ProbeIdx::try_from_zero_based(idx).map_err(|_| format!("there is no probe_idx {idx}"))
+}
+
+fn condition(data: ConditionData) -> Result<AlarmCondition, String> {
+    Ok(match data {
+        ConditionData::Above { probe_idx, limit } => AlarmCondition::Above {
+            probe: probe(probe_idx)?,
+            limit,
+        },
+        ConditionData::Below { probe_idx, limit } => AlarmCondition::Below {
+            probe: probe(probe_idx)?,
+            limit,
+        },
+        ConditionData::Approaching {
+            probe_idx,
+            target,
+            margin,
+        } => AlarmCondition::Approaching {
+            probe: probe(probe_idx)?,
+            target,
+            margin,
+        },
+        ConditionData::RisingFaster {
+            probe_idx,
+            degrees_per_minute,
+        } => AlarmCondition::RisingFaster {
+            probe: probe(probe_idx)?,
+            degrees_per_minute,
+        },
+        ConditionData::FallingFaster {
+            probe_idx,
+            degrees_per_minute,
+        } => AlarmCondition::FallingFaster {
+            probe: probe(probe_idx)?,
+            degrees_per_minute,
+        },
+        ConditionData::Unplugged { probe_idx } => AlarmCondition::Unplugged {
+            probe: probe(probe_idx)?,
+        },
+        ConditionData::NoData { after_secs } => AlarmCondition::NoData {
+            after: Duration::from_secs(after_secs),
+        },
+        ConditionData::DifferenceBelow {
+            hotter_idx,
+            cooler_idx,
+            limit,
+        } => AlarmCondition::DifferenceBelow {
+            hotter: probe(hotter_idx)?,
+            cooler: probe(cooler_idx)?,
+            limit,
+        },
+        ConditionData::DifferenceAbove {
+            hotter_idx,
+            cooler_idx,
+            limit,
+        } => AlarmCondition::DifferenceAbove {
+            hotter: probe(hotter_idx)?,
+            cooler: probe(cooler_idx)?,
+            limit,
+        },
+    })
+}
+
+fn status_to_json(status: &HostAlarmStatus) -> Value {
+    match status.state {
+        HostAlarmState::Clear => json!({"name": status.name, "state": "clear"}),
+        HostAlarmState::Raised {
+            since,
+            acknowledged,
+        } => json!({
+            "name": status.name,
+            "state": if acknowledged { "acknowledged" } else { "raised" },
+            "raised_secs_ago": SystemTime::now().duration_since(since).unwrap_or_default().as_secs(),
+        }),
+    }
+}
+
+pub fn statuses_to_json(statuses: &[HostAlarmStatus]) -> Value {
+    statuses.iter().map(status_to_json).collect()
+}
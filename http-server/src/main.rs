@@ -1,27 +1,89 @@
+mod host_alarms;
 mod state_to_json;
 
-use crate::state_to_json::state_to_json;
-use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use crate::host_alarms::{load_rules, statuses_to_json};
+use crate::state_to_json::{history_to_json, sessions_to_json, state_to_json};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer, Responder};
 use actix_ws::AggregatedMessage;
-use device_controller::controller::command_request::CommandRequest;
+use device_controller::controller::command_request::{CommandRequest, CommandResult};
 use device_controller::controller::connection_handler::ConnectionHandler;
 use device_controller::controller::connection_mgr::ConnectionManager;
-use device_controller::dev_finder::DeviceFinder;
-use device_controller::model::device::TP25State;
+use device_controller::controller::device_manager::DeviceManager;
+use device_controller::controller::handle::ControllerHandle;
+use device_controller::controller::history::{HistoryRecorder, HistorySettings, Resolution};
+use device_controller::controller::host_alarm::{AlarmEngine, AlarmRule, HostAlarms};
+use device_controller::controller::session::{SessionError, SessionRecorder, SessionStore};
+use device_controller::controller::transfer_recorder::{
+    CaptureSettings, CaptureWriter, TransferRecorder,
+};
+use device_controller::dev_finder::{DeviceFinder, FINDER_USAGE};
 use device_controller::model::probe::{
     AlarmThreshold, ProbeIdx, RangeLimitThreshold, UpperLimitThreshold,
 };
-use device_controller::peripheral::notification::calc_checksum;
+use device_controller::peripheral::replay::ReplayStepper;
+use device_controller::Error;
 use futures_util::StreamExt as _;
 use serde::Deserialize;
-use tokio::sync::mpsc::{channel as tokio_channel, Sender};
-use tokio::sync::watch;
-use tokio::sync::Mutex;
-use tokio::task::JoinSet;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, SystemTime};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Instant;
+
+/// A thermometer being served.
+struct Device {
+    /// The address it was asked for by, or `None` if it's whichever thermometer was found first.
+    address: Option<String>,
+    controller: ControllerHandle,
+    host_alarms: Option<HostAlarms>,
+    history: HistoryRecorder,
+    sessions: Option<SessionRecorder>,
+}
 
 struct AppState {
-    state_rx: Mutex<watch::Receiver<TP25State>>,
-    cmd_tx: Sender<CommandRequest>,
+    /// Every thermometer being served, by alias.
+    devices: BTreeMap<String, Device>,
+    /// Releases notifications, if a replay is being stepped through.
+    stepper: Option<ReplayStepper>,
+}
+
+impl AppState {
+    /// The device a request is for: the one whose alias or address is given as its `device` parameter, or the only
+    /// one if there's just one.
+    fn device(&self, query: &DeviceQuery) -> Result<&Device, HttpResponseBuilder> {
+        match &query.device {
+            Some(key) => self
+                .devices
+                .iter()
+                .find(|(alias, device)| {
+                    *alias == key
+                        || device
+                            .address
+                            .as_ref()
+                            .is_some_and(|a| a.eq_ignore_ascii_case(key))
+                })
+                .map(|(_, device)| device)
+                .ok_or_else(HttpResponse::NotFound),
+            None if self.devices.len() == 1 => Ok(self.devices.values().next().unwrap()),
+            None => Err(HttpResponse::BadRequest()),
+        }
+    }
+
+    /// The session recorder of the device a request is for, or `404 Not Found` if sessions aren't being recorded.
+    fn sessions(&self, query: &DeviceQuery) -> Result<&SessionRecorder, HttpResponseBuilder> {
+        self.device(query)?
+            .sessions
+            .as_ref()
+            .ok_or_else(HttpResponse::NotFound)
+    }
+}
+
+#[derive(Deserialize)]
+struct DeviceQuery {
+    device: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -36,44 +98,132 @@ struct ProfileData {
     alarm_high: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct TargetData {
+    probe_idx: u8,
+    target: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    probe_idx: u8,
+    /// How far back to go. An hour by default.
+    minutes: Option<u64>,
+    /// "full", "auto" (the default), or the shortest period in seconds that each point should cover.
+    resolution: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SessionData {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct HostAlarmAckData {
+    name: String,
+}
+
 #[derive(Deserialize)]
 struct CustomCmdData {
     cmd: String,
     allow_wrong_checksum: Option<bool>,
 }
 
-impl AppState {
-    fn new(state_rx: watch::Receiver<TP25State>, cmd_tx: Sender<CommandRequest>) -> Self {
-        Self {
-            state_rx: Mutex::new(state_rx),
-            cmd_tx,
-        }
-    }
+async fn get_state(data: web::Data<AppState>, query: web::Query<DeviceQuery>) -> impl Responder {
+    let device = match data.device(&query) {
+        Ok(device) => device,
+        Err(mut response) => return response.finish(),
+    };
+    let r = state_to_json(&device.controller.state());
+    HttpResponse::Ok()
+        .append_header(("Content-Type", "application/json"))
+        .body(r.to_string())
 }
 
-async fn get_state(data: web::Data<AppState>) -> impl Responder {
-    let state_g = data.state_rx.lock().await;
-    let state = state_g.borrow();
-    let r = state_to_json(&state);
+async fn get_history(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+    history_query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    let device = match data.device(&query) {
+        Ok(device) => device,
+        Err(mut response) => return response.finish(),
+    };
+    let Ok(probe_idx) = ProbeIdx::try_from_zero_based(history_query.probe_idx) else {
+        return HttpResponse::BadRequest().finish();
+    };
+    let resolution = match history_query.resolution.as_deref() {
+        None | Some("auto") => Resolution::Auto,
+        Some("full") => Resolution::Full,
+        Some(secs) => match secs.parse() {
+            Ok(secs) => Resolution::AtLeast(Duration::from_secs(secs)),
+            Err(_) => return HttpResponse::BadRequest().finish(),
+        },
+    };
+    let to = SystemTime::now();
+    let from = to - Duration::from_secs(history_query.minutes.unwrap_or(60) * 60);
+
+    let points = device.history.query(probe_idx, from, to, resolution);
     HttpResponse::Ok()
         .append_header(("Content-Type", "application/json"))
-        .body(r.to_string())
+        .body(history_to_json(&points).to_string())
 }
 
-async fn set_mode(data: web::Data<AppState>, json: web::Json<ModeData>) -> impl Responder {
-    if data
-        .cmd_tx
-        .send(CommandRequest::SetTempMode(json.celsius))
-        .await
-        .is_ok()
-    {
-        HttpResponse::Ok()
-    } else {
-        HttpResponse::InternalServerError()
+async fn get_devices(data: web::Data<AppState>) -> impl Responder {
+    let devices: Vec<_> = data
+        .devices
+        .iter()
+        .map(|(alias, device)| {
+            json!({
+                "alias": alias,
+                "address": device.address,
+                "state": state_to_json(&device.controller.state()),
+            })
+        })
+        .collect();
+    HttpResponse::Ok()
+        .append_header(("Content-Type", "application/json"))
+        .body(serde_json::Value::from(devices).to_string())
+}
+
+/// Pick a response depending on whether the thermometer acknowledged a command.
+fn command_response(result: CommandResult) -> HttpResponseBuilder {
+    match result {
+        Ok(()) => HttpResponse::Ok(),
+        Err(Error::DeviceError | Error::Decode(_)) => HttpResponse::BadGateway(),
+        Err(Error::Timeout) => HttpResponse::GatewayTimeout(),
+        Err(Error::Disconnected | Error::Shutdown) => HttpResponse::ServiceUnavailable(),
+        Err(Error::Transport(_)) => HttpResponse::InternalServerError(),
     }
 }
 
-async fn set_alarm(data: web::Data<AppState>, json: web::Json<ProfileData>) -> impl Responder {
+async fn set_mode(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+    json: web::Json<ModeData>,
+) -> impl Responder {
+    let device = match data.device(&query) {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+    command_response(
+        device
+            .controller
+            .commands()
+            .request(CommandRequest::SetTempMode(json.celsius))
+            .await,
+    )
+}
+
+async fn set_alarm(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+    json: web::Json<ProfileData>,
+) -> impl Responder {
+    let device = match data.device(&query) {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
     let Ok(probe_idx) = ProbeIdx::try_from_zero_based(json.probe_idx) else {
         return HttpResponse::BadRequest();
     };
@@ -96,36 +246,195 @@ async fn set_alarm(data: web::Data<AppState>, json: web::Json<ProfileData>) -> i
         }),
     };
 
-    if data
-        .cmd_tx
-        .send(CommandRequest::SetProfile(probe_idx, alarm_threshold))
+    if let Err(e) = device
+        .controller
+        .commands()
+        .request(CommandRequest::SetProfile(probe_idx, alarm_threshold))
         .await
-        .is_err()
     {
-        return HttpResponse::InternalServerError();
+        return command_response(Err(e));
     }
 
-    // Follow up straight away with a command to update the probe profile data.else
-    if data
-        .cmd_tx
-        .send(CommandRequest::ReportProfile(probe_idx))
-        .await
-        .is_ok()
-    {
-        HttpResponse::Ok()
-    } else {
-        HttpResponse::InternalServerError()
+    // Follow up straight away with a command to update the probe profile data, so that the new profile is in the
+    // state by the time we respond.
+    command_response(
+        device
+            .controller
+            .commands()
+            .request(CommandRequest::ReportProfile(probe_idx))
+            .await,
+    )
+}
+
+async fn set_target(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+    json: web::Json<TargetData>,
+) -> impl Responder {
+    let device = match data.device(&query) {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+    let Ok(probe_idx) = ProbeIdx::try_from_zero_based(json.probe_idx) else {
+        return HttpResponse::BadRequest();
+    };
+    let target = match &json.target {
+        Some(s) => match s.parse::<f32>() {
+            Ok(t) => Some(t.into()),
+            Err(_) => return HttpResponse::BadRequest(),
+        },
+        None => None,
+    };
+
+    device.controller.set_cook_target(probe_idx, target).await;
+    HttpResponse::Ok()
+}
+
+async fn post_step(data: web::Data<AppState>) -> impl Responder {
+    match &data.stepper {
+        Some(stepper) => {
+            stepper.step();
+            HttpResponse::Ok()
+        }
+        None => HttpResponse::NotFound(),
     }
 }
 
-async fn post_alarm_ack(data: web::Data<AppState>) -> impl Responder {
-    if data.cmd_tx.send(CommandRequest::AckAlarm).await.is_ok() {
-        HttpResponse::Ok()
-    } else {
-        HttpResponse::InternalServerError()
+async fn get_host_alarms(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+) -> impl Responder {
+    let host_alarms = match data.device(&query) {
+        Ok(Device {
+            host_alarms: Some(host_alarms),
+            ..
+        }) => host_alarms,
+        Ok(_) => return HttpResponse::NotFound().finish(),
+        Err(mut response) => return response.finish(),
+    };
+    HttpResponse::Ok()
+        .append_header(("Content-Type", "application/json"))
+        .body(statuses_to_json(&host_alarms.statuses()).to_string())
+}
+
+async fn post_host_alarm_ack(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+    json: web::Json<HostAlarmAckData>,
+) -> impl Responder {
+    match data.device(&query) {
+        Ok(Device {
+            host_alarms: Some(host_alarms),
+            ..
+        }) if host_alarms.acknowledge(&json.name) => HttpResponse::Ok(),
+        Ok(_) => HttpResponse::NotFound(),
+        Err(response) => response,
+    }
+}
+
+/// Pick a response for a session that couldn't be started, resumed or read.
+fn session_error_response(e: SessionError) -> HttpResponse {
+    let mut response = match e {
+        SessionError::InvalidName(_) => HttpResponse::BadRequest(),
+        SessionError::NotFound(_) => HttpResponse::NotFound(),
+        SessionError::AlreadyExists(_) | SessionError::AlreadyRecording(_) => {
+            HttpResponse::Conflict()
+        }
+        SessionError::Io(_) => HttpResponse::InternalServerError(),
+    };
+    response.body(e.to_string())
+}
+
+async fn get_sessions(data: web::Data<AppState>, query: web::Query<DeviceQuery>) -> impl Responder {
+    let sessions = match data.sessions(&query) {
+        Ok(sessions) => sessions,
+        Err(mut response) => return response.finish(),
+    };
+    match sessions.store().list() {
+        Ok(list) => HttpResponse::Ok()
+            .append_header(("Content-Type", "application/json"))
+            .body(sessions_to_json(sessions.current(), &list).to_string()),
+        Err(e) => session_error_response(e),
+    }
+}
+
+async fn post_session_start(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+    json: web::Json<SessionData>,
+) -> impl Responder {
+    match data.sessions(&query) {
+        Ok(sessions) => match sessions.start(&json.name) {
+            Ok(()) => HttpResponse::Ok().finish(),
+            Err(e) => session_error_response(e),
+        },
+        Err(mut response) => response.finish(),
+    }
+}
+
+async fn post_session_resume(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+    json: web::Json<SessionData>,
+) -> impl Responder {
+    match data.sessions(&query) {
+        Ok(sessions) => match sessions.resume(&json.name) {
+            Ok(()) => HttpResponse::Ok().finish(),
+            Err(e) => session_error_response(e),
+        },
+        Err(mut response) => response.finish(),
+    }
+}
+
+async fn post_session_stop(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+) -> impl Responder {
+    match data.sessions(&query) {
+        Ok(sessions) => match sessions.stop() {
+            Ok(Some(_)) => HttpResponse::Ok().finish(),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Err(e) => session_error_response(e),
+        },
+        Err(mut response) => response.finish(),
+    }
+}
+
+async fn get_session_csv(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+    session_query: web::Query<SessionData>,
+) -> impl Responder {
+    let sessions = match data.sessions(&query) {
+        Ok(sessions) => sessions,
+        Err(mut response) => return response.finish(),
+    };
+    let mut csv = Vec::new();
+    match sessions.store().export_csv(&session_query.name, &mut csv) {
+        Ok(()) => HttpResponse::Ok()
+            .append_header(("Content-Type", "text/csv"))
+            .body(csv),
+        Err(e) => session_error_response(e),
     }
 }
 
+async fn post_alarm_ack(
+    data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
+) -> impl Responder {
+    let device = match data.device(&query) {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+    command_response(
+        device
+            .controller
+            .commands()
+            .request(CommandRequest::AckAlarm)
+            .await,
+    )
+}
+
 fn hex_to_bytes(s: &str) -> Option<Vec<u8>> {
     if s.len() % 2 == 0 {
         (0..s.len())
@@ -142,8 +451,13 @@ fn hex_to_bytes(s: &str) -> Option<Vec<u8>> {
 
 async fn post_custom_cmd(
     data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
     json: web::Json<CustomCmdData>,
 ) -> impl Responder {
+    let device = match data.device(&query) {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
     if json.cmd.len() < 6 {
         return HttpResponse::BadRequest();
     }
@@ -154,28 +468,31 @@ async fn post_custom_cmd(
     };
 
     if enforce_checksum
-        && calc_checksum(&cmd_vec[..cmd_vec.len() - 1]) != cmd_vec[cmd_vec.len() - 1]
+        && tlvc::checksum(&cmd_vec[..cmd_vec.len() - 1]) != cmd_vec[cmd_vec.len() - 1]
     {
         return HttpResponse::BadRequest();
     }
 
-    if data
-        .cmd_tx
-        .send(CommandRequest::CustomCommand(cmd_vec))
-        .await
-        .is_ok()
-    {
-        HttpResponse::Ok()
-    } else {
-        HttpResponse::InternalServerError()
-    }
+    // There's no way of knowing what response to expect from a custom command, so this only waits for it to be sent.
+    command_response(
+        device
+            .controller
+            .commands()
+            .request(CommandRequest::CustomCommand(cmd_vec))
+            .await,
+    )
 }
 
 async fn get_ws(
     data: web::Data<AppState>,
+    query: web::Query<DeviceQuery>,
     req: HttpRequest,
     stream: web::Payload,
 ) -> impl Responder {
+    let device = match data.device(&query) {
+        Ok(device) => device,
+        Err(mut response) => return response.finish(),
+    };
     let (res, mut session, stream) = actix_ws::handle(&req, stream).unwrap();
     let mut s2 = session.clone();
 
@@ -212,7 +529,7 @@ async fn get_ws(
     });
 
     // Broadcast state changes to websocket.
-    let mut rx = data.state_rx.lock().await.clone();
+    let mut rx = device.controller.subscribe_state();
     actix_web::rt::spawn(async move {
         while rx.changed().await.is_ok() {
             let state = rx.borrow_and_update();
@@ -227,72 +544,240 @@ async fn get_ws(
     res
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let (state_tx, mut state_rx) = tokio_channel(10);
-    let (transfer_tx, mut transfer_rx) = tokio_channel(10);
-    let (cmd_tx, ui_request_rx) = tokio_channel(10);
-
-    let (state_watch_tx, state_watch_rx) = watch::channel(TP25State::default());
+/// Wait for Ctrl-C, or SIGTERM where there is such a thing.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
 
-    let state = web::Data::new(AppState::new(state_watch_rx, cmd_tx));
+/// What was asked for on the command line.
+struct Args {
+    /// What to talk to. By default it's a thermometer over Bluetooth.
+    finder: DeviceFinder,
+    /// The addresses of the thermometers to serve, and their aliases if given. If empty, the first thermometer found is
+    /// served.
+    devices: Vec<(String, Option<String>)>,
+    /// What to run alongside each thermometer's controller.
+    options: DeviceOptions,
+}
 
-    let mut all_tasks = JoinSet::new();
-    let finder = DeviceFinder {};
+struct DeviceOptions {
+    /// Where to record every command and notification, if anywhere.
+    record: Option<PathBuf>,
+    /// The alarms to evaluate on this machine, if any.
+    host_alarms: Option<Vec<AlarmRule>>,
+    /// Where to keep cook sessions, if they can be recorded.
+    sessions: Option<PathBuf>,
+}
 
-    // Controller task.
-    all_tasks.spawn(ConnectionManager::run(
+fn parse_args() -> Args {
+    let (mut finder, others) =
+        DeviceFinder::from_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            usage()
+        });
+    // Each address is a separate thermometer here, rather than a choice of which one to connect to.
+    let devices = std::mem::take(&mut finder.addresses)
+        .into_iter()
+        .map(|address| match address.split_once('=') {
+            Some((address, alias)) => (address.to_string(), Some(alias.to_string())),
+            None => (address, None),
+        })
+        .collect();
+    let mut options = DeviceOptions {
+        record: None,
+        host_alarms: None,
+        sessions: None,
+    };
+    for (option, value) in others {
+        match option.as_str() {
+            "--record" => options.record = Some(PathBuf::from(value)),
+            "--sessions" => options.sessions = Some(PathBuf::from(value)),
+            "--host-alarms" => {
+                let rules = load_rules(value.as_ref()).unwrap_or_else(|e| {
+                    eprintln!("Unable to read host alarms from {}: {}", value, e);
+                    process::exit(1);
+                });
+                options.host_alarms = Some(rules);
+            }
+            _ => usage(),
+        }
+    }
+    Args {
         finder,
-        ConnectionHandler {},
-        state_tx,
-        transfer_tx,
-        ui_request_rx,
-    ));
-
-    // Server task.
-    let s = HttpServer::new(move || {
+        devices,
+        options,
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: http-server {} [--record <dir>] [--host-alarms <alarms.json>] [--sessions <dir>]",
+        FINDER_USAGE
+    );
+    eprintln!("Each --address can be given as <ble address>=<alias>, and is served as a separate thermometer.");
+    process::exit(1);
+}
+
+/// Start whatever `options` ask for alongside a thermometer's controller. When serving several thermometers, each is
+/// told apart by `name`: capture files include it, and sessions are kept in a subdirectory named after it.
+fn attach(
+    controller: ControllerHandle,
+    address: Option<String>,
+    name: Option<&str>,
+    options: &DeviceOptions,
+) -> Device {
+    // These all stop by themselves when the controller does.
+    if let Some(dir) = &options.record {
+        let prefix = match name {
+            Some(name) => format!("http-server.{name}"),
+            None => "http-server".to_string(),
+        };
+        let writer =
+            CaptureWriter::new(dir, &prefix, CaptureSettings::default()).unwrap_or_else(|e| {
+                eprintln!("Unable to record to {}: {}", dir.display(), e);
+                process::exit(1);
+            });
+        tokio::spawn(TransferRecorder::new(controller.clone(), writer).run());
+    }
+    let host_alarms = options.host_alarms.as_ref().map(|rules| {
+        let engine = AlarmEngine::new(rules.clone(), Instant::now());
+        let host_alarms = HostAlarms::new(controller.clone(), engine);
+        tokio::spawn(host_alarms.clone().run());
+        host_alarms
+    });
+    let history = HistoryRecorder::new(controller.clone(), HistorySettings::default())
+        .expect("The default history settings are usable");
+    tokio::spawn(history.clone().run());
+    let sessions = options.sessions.as_ref().map(|dir| {
+        let dir = match name {
+            Some(name) => dir.join(name),
+            None => dir.clone(),
+        };
+        let store = SessionStore::new(&dir).unwrap_or_else(|e| {
+            eprintln!("Unable to keep sessions in {}: {}", dir.display(), e);
+            process::exit(1);
+        });
+        let sessions = SessionRecorder::new(controller.clone(), store);
+        tokio::spawn(sessions.clone().run());
+        sessions
+    });
+    Device {
+        address,
+        controller,
+        host_alarms,
+        history,
+        sessions,
+    }
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let args = parse_args();
+    let stepper = args.finder.backend.stepper().cloned();
+
+    // Everything attached to the controllers is started before they get a chance to run, so that nothing is missed.
+    let mut devices = BTreeMap::new();
+    let mut controller_task = None;
+    let mut device_manager = None;
+    if args.devices.is_empty() {
+        let (manager, controller) =
+            ConnectionManager::new(args.finder, ConnectionHandler::default());
+        devices.insert(
+            "default".to_string(),
+            attach(controller, None, None, &args.options),
+        );
+        controller_task = Some(tokio::spawn(manager.run()));
+    } else {
+        let manager = DeviceManager::new(args.finder, ConnectionHandler::default());
+        for (address, alias) in args.devices {
+            let controller = manager
+                .add_device(&address, alias.as_deref())
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    usage()
+                });
+            let alias = alias.unwrap_or_else(|| address.clone());
+            let name: String = alias
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                .collect();
+            let device = attach(controller, Some(address), Some(&name), &args.options);
+            devices.insert(alias, device);
+        }
+        device_manager = Some(manager);
+    }
+    let controllers: Vec<_> = devices.values().map(|d| d.controller.clone()).collect();
+    let state = web::Data::new(AppState { devices, stepper });
+
+    // Server task. Signals are handled below, so that the controllers can be shut down too.
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(state.clone())
+            .route("/devices", web::get().to(get_devices))
             .route("/state", web::get().to(get_state))
+            .route("/history", web::get().to(get_history))
             .route("/mode", web::post().to(set_mode))
             .route("/alarm", web::post().to(set_alarm))
             .route("/alarm_ack", web::post().to(post_alarm_ack))
+            .route("/host_alarms", web::get().to(get_host_alarms))
+            .route("/host_alarm_ack", web::post().to(post_host_alarm_ack))
+            .route("/sessions", web::get().to(get_sessions))
+            .route("/session_start", web::post().to(post_session_start))
+            .route("/session_resume", web::post().to(post_session_resume))
+            .route("/session_stop", web::post().to(post_session_stop))
+            .route("/session_csv", web::get().to(get_session_csv))
+            .route("/target", web::post().to(set_target))
             .route("/ws", web::get().to(get_ws))
             .route("/custom_cmd", web::post().to(post_custom_cmd))
+            .route("/step", web::post().to(post_step))
     })
+    .disable_signals()
     .bind(("127.0.0.1", 8080))?
     .run();
-    all_tasks.spawn(async move {
-        let _ = s.await;
-    });
-
-    // State update task.
-    all_tasks.spawn(async move {
-        loop {
-            let Some(state) = state_rx.recv().await else {
-                return;
-            };
-
-            if state_watch_tx.send(state).is_err() {
-                return;
-            };
+    let server_handle = server.handle();
+    let mut server_task = tokio::spawn(server);
+
+    // A `DeviceManager` keeps its controllers' tasks to itself, so only a lone controller is watched for stopping.
+    let controller_stopped = async {
+        match &mut controller_task {
+            Some(task) => {
+                let _ = task.await;
+            }
+            None => std::future::pending().await,
         }
-    });
+    };
+    tokio::select! {
+        _ = shutdown_signal() => println!("Shutting down"),
+        _ = controller_stopped => println!("Controller stopped"),
+        _ = &mut server_task => println!("Server stopped"),
+    }
 
-    // Transfer log update task.
-    all_tasks.spawn(async move {
-        loop {
-            let Some(_) = transfer_rx.recv().await else {
-                return;
-            };
+    // Disconnect from the thermometers cleanly, so that they're free for something else to connect to.
+    for controller in &controllers {
+        controller.shutdown();
+    }
+    server_handle.stop(true).await;
+    // Whichever task finished above has already been awaited.
+    if !server_task.is_finished() {
+        let _ = server_task.await;
+    }
+    if let Some(task) = controller_task {
+        if !task.is_finished() {
+            let _ = task.await;
         }
-    });
-
-    let Some(Ok(_)) = all_tasks.join_next().await else {
-        // TBH, not too bothered about the error just now.
-        println!("Some error happened");
-        return Ok(());
-    };
+    }
+    if let Some(manager) = device_manager {
+        manager.shutdown().await;
+    }
 
     Ok(())
 }
@@ -1,7 +1,13 @@
+use device_controller::controller::history::HistoryPoint;
+use device_controller::controller::session::SessionInfo;
+use device_controller::model::cook_estimate::CookEstimate;
 use device_controller::model::device::{TP25State, TemperatureMode};
+use device_controller::model::device_info::DeviceInfo;
 use device_controller::model::device_temperature::{DeviceTemperature, InRangeDeviceTemperature};
-use device_controller::model::probe::{AlarmState, AlarmThreshold, Probe};
+use device_controller::model::link_health::LinkHealth;
+use device_controller::model::probe::{AlarmState, AlarmThreshold, Probe, ProfileRearm};
 use serde_json::{json, Value};
+use std::time::UNIX_EPOCH;
 
 pub fn state_to_json(state: &TP25State) -> Value {
     if state.connected {
@@ -9,10 +15,13 @@ pub fn state_to_json(state: &TP25State) -> Value {
             "connected": true,
             "temp_mode": temp_mode_to_string(state.temperature_mode),
             "probes": probes_to_json(&state.probes),
+            "device_info": device_info_to_json(&state.device_info),
+            "link": link_health_to_json(&state.link),
         })
     } else {
         json!({
             "connected": false,
+            "link": link_health_to_json(&state.link),
         })
     }
 }
@@ -57,14 +66,92 @@ fn alarm_threshold_to_json(threshold: Option<AlarmThreshold>) -> Value {
     }
 }
 
+fn profile_rearm_to_string(rearm: ProfileRearm) -> &'static str {
+    match rearm {
+        ProfileRearm::NotNeeded => "not_needed",
+        ProfileRearm::Pending => "pending",
+        ProfileRearm::Rearmed => "rearmed",
+        ProfileRearm::Restored => "restored",
+        ProfileRearm::Failed => "failed",
+    }
+}
+
 fn probe_to_json(probe: &Probe) -> Value {
     json!({
         "alarm": alarm_state_to_string(probe.alarm),
         "temp": temp_option_to_string(probe.temperature),
         "alarm_threshold": alarm_threshold_to_json(probe.alarm_threshold),
+        "profile_rearm": profile_rearm_to_string(probe.profile_rearm),
+        "cook_target": probe.cook_target.map(temp_to_string),
+        "cook_estimate": probe.cook_estimate.as_ref().map(cook_estimate_to_json),
+    })
+}
+
+fn cook_estimate_to_json(estimate: &CookEstimate) -> Value {
+    json!({
+        "target": temp_to_string(estimate.target),
+        "degrees_per_minute": format!("{:.2}", estimate.degrees_per_minute),
+        "remaining_secs": estimate.remaining.map(|d| d.as_secs()),
+        "earliest_secs": estimate.earliest.map(|d| d.as_secs()),
+        "latest_secs": estimate.latest.map(|d| d.as_secs()),
+        "stalled": estimate.stalled,
     })
 }
 
 fn probes_to_json(probes: &[Probe]) -> Vec<Value> {
     probes.iter().map(probe_to_json).collect()
 }
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn device_info_to_json(info: &DeviceInfo) -> Value {
+    json!({
+        "sync_complete": info.sync_complete,
+        "unknown_26": info.unknown_26.as_ref().map(|r| bytes_to_hex(&r.decoded)),
+        "unknown_41": info.unknown_41.as_ref().map(|r| bytes_to_hex(&r.decoded)),
+    })
+}
+
+fn link_health_to_json(link: &LinkHealth) -> Value {
+    json!({
+        "connect_attempts": link.connect_attempts,
+        "last_disconnect_reason": link.last_disconnect_reason.as_ref().map(|e| e.to_string()),
+        "session_uptime_secs": link.session_uptime().map(|d| d.as_secs()),
+        "secs_since_last_notification": link.time_since_last_notification().map(|d| d.as_secs()),
+        "gave_up": link.gave_up,
+    })
+}
+
+fn history_point_to_json(point: &HistoryPoint) -> Value {
+    json!({
+        "at": point.at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        "period_secs": point.period.as_secs(),
+        "min": point.temperature.map(|t| format!("{:.1}", t.min)),
+        "mean": point.temperature.map(|t| format!("{:.1}", t.mean)),
+        "max": point.temperature.map(|t| format!("{:.1}", t.max)),
+        "alarm": point.alarm,
+    })
+}
+
+pub fn history_to_json(points: &[HistoryPoint]) -> Value {
+    points.iter().map(history_point_to_json).collect()
+}
+
+fn session_to_json(info: &SessionInfo) -> Value {
+    json!({
+        "name": info.name,
+        "started_ms": info.started_ms,
+        "last_ms": info.last_ms,
+        "readings": info.readings,
+        "stopped": info.stopped,
+    })
+}
+
+pub fn sessions_to_json(current: Option<String>, sessions: &[SessionInfo]) -> Value {
+    json!({
+        "current": current,
+        "sessions": sessions.iter().map(session_to_json).collect::<Vec<_>>(),
+    })
+}
@@ -1,7 +1,7 @@
 use std::{env, process};
 
 fn hex_to_bytes(hex_str: &str) -> Result<Vec<u8>, String> {
-    if hex_str.len() % 2 != 0 {
+    if hex_str.len() % 2 == 1 {
         return Err("Hex string must have an even length".to_string());
     }
 
@@ -16,13 +16,6 @@ fn hex_to_bytes(hex_str: &str) -> Result<Vec<u8>, String> {
     Ok(bytes)
 }
 
-fn calculate_checksum(bytes: &[u8]) -> u8 {
-    #[allow(arithmetic_overflow)]
-    let sum: u64 = bytes[0..bytes.len()].iter().map(|x| *x as u64).sum();
-
-    (sum % 256) as u8
-}
-
 /*
  * Takes a hex string and searches for a byte that could be a checksum of all previous bytes.
  * This has been useful when seeing if there is any structure to the notifications sent by the
@@ -41,7 +34,7 @@ fn main() {
     let bytes = hex_to_bytes(hex_str).unwrap();
 
     for i in 2..bytes.len() {
-        let c = calculate_checksum(&bytes[0..i]);
+        let c = tlvc::checksum(&bytes[0..i]);
         println!(
             "Length: {:?}, checksum: {:x}, expected: {:x}, match? {:?}",
             i,
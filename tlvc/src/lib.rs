@@ -0,0 +1,284 @@
+//! Encoding and decoding of the TLVC ("type, length, value, checksum") frames used by the TP25.
+//!
+//! Commands written to the thermometer are exactly one frame. Notifications sent by the thermometer are one frame
+//! followed by trailing bytes that seem to be junk, padding the notification out to 20 bytes. See
+//! `docs/common-info.md` for a description of the format.
+
+use std::fmt::{Display, Formatter};
+
+/// The number of bytes in a frame that aren't part of the value - type, length and checksum.
+pub const OVERHEAD: usize = 3;
+
+/// The longest value a frame can hold, as its length is a single byte.
+pub const MAX_VALUE_LEN: usize = u8::MAX as usize;
+
+/// The mod-256 sum of `bytes`.
+pub fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Encode a single frame with no trailing bytes.
+///
+/// Panics if `value` is longer than `MAX_VALUE_LEN`.
+pub fn encode(kind: u8, value: &[u8]) -> Vec<u8> {
+    Frame::new(kind, value).encode()
+}
+
+/// Decode a single frame from the start of `packet`. Anything after the checksum byte is kept in `trailing`.
+///
+/// The checksum is *not* validated, as it is useful to be able to look at frames with bad checksums. Use
+/// `Frame::is_checksum_valid` to check it.
+pub fn decode(packet: &[u8]) -> Result<Frame, Error> {
+    let (frame, used) = decode_frame(packet)?;
+    Ok(Frame {
+        trailing: packet[used..].to_vec(),
+        ..frame
+    })
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// Fewer bytes were available than the frame header says are needed.
+    TooShort { needed: usize, available: usize },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::TooShort { needed, available } => write!(
+                f,
+                "frame too short (needed {} bytes, {} available)",
+                needed, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Frame {
+    pub kind: u8,
+    pub value: Vec<u8>,
+    pub checksum: u8,
+    /// Bytes following the checksum. Only notifications have these, and they seem to be junk.
+    pub trailing: Vec<u8>,
+}
+
+impl Frame {
+    /// Build a frame with the correct checksum and no trailing bytes.
+    ///
+    /// Panics if `value` is longer than `MAX_VALUE_LEN`, as the length byte couldn't describe it.
+    pub fn new(kind: u8, value: &[u8]) -> Frame {
+        assert!(
+            value.len() <= MAX_VALUE_LEN,
+            "frame value of {} bytes is too long",
+            value.len()
+        );
+        let mut frame = Frame {
+            kind,
+            value: value.to_vec(),
+            checksum: 0,
+            trailing: Vec::new(),
+        };
+        frame.checksum = frame.expected_checksum();
+        frame
+    }
+
+    pub fn with_trailing(self, trailing: &[u8]) -> Frame {
+        Frame {
+            trailing: trailing.to_vec(),
+            ..self
+        }
+    }
+
+    /// The value of the length byte. Values longer than `MAX_VALUE_LEN` can't be represented, and wrap around.
+    pub fn length(&self) -> u8 {
+        self.value.len() as u8
+    }
+
+    /// The checksum that this frame should have, given its type, length and value.
+    pub fn expected_checksum(&self) -> u8 {
+        checksum(&[self.kind, self.length()]).wrapping_add(checksum(&self.value))
+    }
+
+    pub fn is_checksum_valid(&self) -> bool {
+        self.checksum == self.expected_checksum()
+    }
+
+    /// The bytes of the frame, including any trailing bytes.
+    ///
+    /// Panics if the value is longer than `MAX_VALUE_LEN`, as the frame couldn't be decoded again.
+    pub fn encode(&self) -> Vec<u8> {
+        assert!(
+            self.value.len() <= MAX_VALUE_LEN,
+            "frame value of {} bytes is too long",
+            self.value.len()
+        );
+        let mut raw = Vec::with_capacity(OVERHEAD + self.value.len() + self.trailing.len());
+        raw.push(self.kind);
+        raw.push(self.length());
+        raw.extend_from_slice(&self.value);
+        raw.push(self.checksum);
+        raw.extend_from_slice(&self.trailing);
+        raw
+    }
+}
+
+/// Splits a stream of bytes into back-to-back frames. Frames produced this way never have trailing bytes.
+#[derive(Clone, Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Remove and return the next complete frame, or `None` if there aren't enough bytes buffered yet.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        let (frame, used) = decode_frame(&self.buffer).ok()?;
+        self.buffer.drain(..used);
+        Some(frame)
+    }
+
+    /// Bytes that have been pushed but are not (yet) part of a complete frame.
+    pub fn remainder(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Iterator for Decoder {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        self.next_frame()
+    }
+}
+
+fn decode_frame(bytes: &[u8]) -> Result<(Frame, usize), Error> {
+    let needed = match bytes.get(1) {
+        Some(length) => OVERHEAD + *length as usize,
+        None => OVERHEAD,
+    };
+    if bytes.len() < needed {
+        return Err(Error::TooShort {
+            needed,
+            available: bytes.len(),
+        });
+    }
+
+    let frame = Frame {
+        kind: bytes[0],
+        value: bytes[2..needed - 1].to_vec(),
+        checksum: bytes[needed - 1],
+        trailing: Vec::new(),
+    };
+    Ok((frame, needed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_wraps() {
+        assert_eq!(checksum(&[]), 0);
+        assert_eq!(checksum(&[0x30, 0x00]), 0x30);
+        assert_eq!(checksum(&[0xff, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn encodes_commands() {
+        assert_eq!(encode(0x30, &[]), vec![0x30, 0x00, 0x30]);
+        assert_eq!(encode(0x24, &[0x04]), vec![0x24, 0x01, 0x04, 0x29]);
+        assert_eq!(
+            encode(0x23, &[0x04, 0xfa, 0x02, 0x90, 0x02, 0x50]),
+            vec![0x23, 0x06, 0x04, 0xfa, 0x02, 0x90, 0x02, 0x50, 0x0b]
+        );
+    }
+
+    #[test]
+    fn decodes_notification_with_trailing_bytes() {
+        let f = decode(&[0x41, 0x02, 0x31, 0x11, 0x85, 0x00, 0x91, 0x7d]).unwrap();
+        assert_eq!(f.kind, 0x41);
+        assert_eq!(f.value, vec![0x31, 0x11]);
+        assert_eq!(f.checksum, 0x85);
+        assert_eq!(f.trailing, vec![0x00, 0x91, 0x7d]);
+        assert!(f.is_checksum_valid());
+    }
+
+    #[test]
+    fn keeps_frames_with_bad_checksums() {
+        let f = decode(&[0x30, 0x00, 0x31]).unwrap();
+        assert!(!f.is_checksum_valid());
+        assert_eq!(f.expected_checksum(), 0x30);
+    }
+
+    #[test]
+    fn rejects_short_frames() {
+        assert_eq!(
+            decode(&[0x30]),
+            Err(Error::TooShort {
+                needed: 3,
+                available: 1
+            })
+        );
+        assert_eq!(
+            decode(&[0x24, 0x01, 0x04]),
+            Err(Error::TooShort {
+                needed: 4,
+                available: 3
+            })
+        );
+    }
+
+    #[test]
+    fn round_trips_through_encode() {
+        let f = Frame::new(0x27, &[]).with_trailing(&[0x00, 0x00]);
+        assert_eq!(decode(&f.encode()), Ok(f));
+    }
+
+    #[test]
+    fn encodes_the_longest_values() {
+        let raw = encode(0x42, &[0x01; MAX_VALUE_LEN]);
+        assert_eq!(raw.len(), OVERHEAD + MAX_VALUE_LEN);
+        assert_eq!(decode(&raw).unwrap().value.len(), MAX_VALUE_LEN);
+    }
+
+    #[test]
+    #[should_panic(expected = "too long")]
+    fn rejects_values_too_long_for_the_length_byte() {
+        encode(0x42, &[0x01; MAX_VALUE_LEN + 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "too long")]
+    fn refuses_to_encode_frames_built_by_hand_with_long_values() {
+        let frame = Frame {
+            kind: 0x42,
+            value: vec![0x01; MAX_VALUE_LEN + 1],
+            ..Frame::default()
+        };
+        frame.encode();
+    }
+
+    #[test]
+    fn streams_back_to_back_frames() {
+        let mut d = Decoder::new();
+        d.push(&[0x33, 0x00, 0x33, 0x24, 0x01]);
+        assert_eq!(d.next_frame(), Some(Frame::new(0x33, &[])));
+        assert_eq!(d.next_frame(), None);
+        assert_eq!(d.remainder(), &[0x24, 0x01]);
+
+        d.push(&[0x04, 0x29, 0x00]);
+        assert_eq!(d.next_frame(), Some(Frame::new(0x24, &[0x04])));
+        assert_eq!(d.next_frame(), None);
+        assert_eq!(d.remainder(), &[0x00]);
+    }
+}